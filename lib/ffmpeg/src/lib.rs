@@ -2,14 +2,15 @@ use anyhow::{Context, Result, anyhow, bail};
 use ffmpeg_sidecar::{
     command::FfmpegCommand,
     event::{
-        AudioStream, FfmpegDuration, FfmpegEvent, FfmpegProgress, OutputVideoFrame, Stream,
+        AudioStream, FfmpegDuration, FfmpegEvent, FfmpegProgress, LogLevel, OutputVideoFrame,
+        Stream,
         StreamTypeSpecificData::{Audio, Video},
         VideoStream,
     },
     ffprobe,
 };
 use image::RgbImage;
-use log::{info, warn};
+use log::{debug, info, warn};
 use serde::de::{self, Visitor};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use spin_sleep::SpinSleeper;
@@ -83,6 +84,107 @@ pub struct AudioMetadata {
     pub sample_rate: u32,
     pub channels: String,
     pub duration: f64,
+
+    // Every audio stream of the file, empty if ffprobe isn't installed
+    pub streams: Vec<AudioStreamInfo>,
+}
+
+/// An audio stream of a media file reported by ffprobe
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct AudioStreamInfo {
+    pub index: u32, // index of the stream among all streams of the file
+    pub codec: String,
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub channel_layout: String, // e.g. "stereo" or "5.1(side)", empty if unknown
+    pub language: Option<String>, // e.g. "eng", from the stream tags
+    pub title: Option<String>,  // e.g. "Commentary", from the stream tags
+    pub is_default: bool,
+}
+
+/// The audio stream and channel converted from a media file
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct AudioSelection {
+    // Position of the stream in `AudioMetadata.streams`, None is the stream ffmpeg picks
+    pub stream: Option<u32>,
+
+    // 0-based channel of the stream, None mixes every channel
+    pub channel: Option<u16>,
+}
+
+impl AudioSelection {
+    pub fn with_stream(mut self, stream: u32) -> Self {
+        self.stream = Some(stream);
+        self
+    }
+
+    pub fn with_channel(mut self, channel: u16) -> Self {
+        self.channel = Some(channel);
+        self
+    }
+
+    /// Position and info of the selected stream in `streams`. No selected stream is the default
+    /// stream, or else the first one
+    pub fn selected_stream<'a>(
+        &self,
+        streams: &'a [AudioStreamInfo],
+    ) -> Result<(u32, &'a AudioStreamInfo)> {
+        match self.stream {
+            Some(stream) => streams
+                .get(stream as usize)
+                .map(|info| (stream, info))
+                .with_context(|| {
+                    format!(
+                        "No audio stream {stream}, the file has {} audio streams",
+                        streams.len()
+                    )
+                }),
+            None => streams
+                .iter()
+                .position(|info| info.is_default)
+                .or((!streams.is_empty()).then_some(0))
+                .map(|position| (position as u32, &streams[position]))
+                .context("No audio stream found"),
+        }
+    }
+
+    /// The selection with the stream of `selected_stream`, so the channel is taken from the
+    /// stream it was checked against instead of the stream ffmpeg picks. Fails if the stream or
+    /// the channel isn't in `streams`
+    pub fn resolve(&self, streams: &[AudioStreamInfo]) -> Result<Self> {
+        let (stream, info) = self.selected_stream(streams)?;
+
+        if let Some(channel) = self.channel
+            && channel >= info.channels
+        {
+            bail!(
+                "No channel {channel} in audio stream {} with {} channels",
+                info.index,
+                info.channels
+            );
+        }
+
+        Ok(Self {
+            stream: Some(stream),
+            channel: self.channel,
+        })
+    }
+
+    // ffmpeg arguments of a resolved selection followed by the `aformat` filter
+    fn args(&self, aformat: &str) -> Vec<String> {
+        let mut args = vec![];
+        if let Some(stream) = self.stream {
+            args.extend(["-map".to_string(), format!("0:a:{stream}")]);
+        }
+
+        let filter = match self.channel {
+            Some(channel) => format!("pan=mono|c0=c{channel},{aformat}"),
+            None => aformat.to_string(),
+        };
+        args.extend(["-filter:a".to_string(), filter]);
+
+        args
+    }
 }
 
 #[derive(Debug, Default, Clone)]
@@ -261,6 +363,99 @@ pub fn media_type(path: impl AsRef<Path>) -> Result<MediaType> {
     Ok(ty)
 }
 
+/// Audio streams of a media file in the order of the file, with their language tags and
+/// channel layouts
+pub fn audio_streams(path: impl AsRef<Path>) -> Result<Vec<AudioStreamInfo>> {
+    if !ffprobe::ffprobe_is_installed() {
+        bail!("ffprobe is not install");
+    }
+
+    let path = path.as_ref().to_string_lossy();
+
+    let output = duct::cmd!(
+        ffprobe::ffprobe_path().to_string_lossy().to_string(),
+        "-v",
+        "quiet",
+        "-print_format",
+        "json",
+        "-show_streams",
+        "-select_streams",
+        "a",
+        path.to_string(),
+    )
+    .read()?
+    .to_string();
+
+    parse_audio_streams(&output)
+}
+
+fn parse_audio_streams(output: &str) -> Result<Vec<AudioStreamInfo>> {
+    #[derive(Deserialize)]
+    struct FfprobeDisposition {
+        #[serde(default)]
+        default: u8,
+    }
+
+    #[derive(Deserialize)]
+    struct FfprobeTags {
+        language: Option<String>,
+        title: Option<String>,
+    }
+
+    #[derive(Deserialize)]
+    struct FfprobeStreamsOutput {
+        index: u32,
+        codec_type: String,
+
+        #[serde(default)]
+        codec_name: String,
+
+        #[serde(default)]
+        sample_rate: String,
+
+        #[serde(default)]
+        channels: u16,
+
+        #[serde(default)]
+        channel_layout: String,
+
+        disposition: Option<FfprobeDisposition>,
+        tags: Option<FfprobeTags>,
+    }
+
+    #[derive(Deserialize)]
+    struct FfprobeOutput {
+        #[serde(default)]
+        streams: Vec<FfprobeStreamsOutput>,
+    }
+
+    let output = serde_json::from_str::<FfprobeOutput>(output)
+        .with_context(|| format!("parse {output} failed"))?;
+
+    Ok(output
+        .streams
+        .into_iter()
+        .filter(|stream| stream.codec_type == "audio")
+        .map(|stream| {
+            let (language, title) = match stream.tags {
+                Some(tags) => (tags.language.filter(|lang| lang != "und"), tags.title),
+                None => (None, None),
+            };
+
+            AudioStreamInfo {
+                index: stream.index,
+                codec: stream.codec_name,
+                sample_rate: stream.sample_rate.parse().unwrap_or_default(),
+                channels: stream.channels,
+                channel_layout: stream.channel_layout,
+                language,
+                title,
+                is_default: stream.disposition.is_some_and(|d| d.default != 0),
+            }
+        })
+        .collect())
+}
+
 pub fn audio_metadata(path: impl AsRef<str>) -> Result<AudioMetadata> {
     let mut ffmpeg_runner = FfmpegCommand::new()
         .input(path.as_ref())
@@ -293,6 +488,8 @@ pub fn audio_metadata(path: impl AsRef<str>) -> Result<AudioMetadata> {
 
     _ = ffmpeg_runner.kill();
     _ = ffmpeg_runner.wait();
+
+    metadata.streams = audio_streams_or_empty(path.as_ref());
     Ok(metadata)
 }
 
@@ -347,9 +544,18 @@ pub fn video_metadata(path: impl AsRef<str>) -> Result<VideoMetadata> {
 
     _ = ffmpeg_runner.kill();
     _ = ffmpeg_runner.wait();
+
+    metadata.auido_metadata.streams = audio_streams_or_empty(path.as_ref());
     Ok(metadata)
 }
 
+fn audio_streams_or_empty(path: &str) -> Vec<AudioStreamInfo> {
+    audio_streams(path).unwrap_or_else(|e| {
+        warn!("get audio streams of {path} failed: {e}");
+        vec![]
+    })
+}
+
 fn timestamp_to_ms(timestamp: &str) -> Result<u64> {
     let parts: Vec<&str> = timestamp.split(':').collect();
     if parts.len() != 3 {
@@ -377,13 +583,15 @@ fn timestamp_to_ms(timestamp: &str) -> Result<u64> {
     Ok(total_ms)
 }
 
+/// Convert the selected audio stream and channel of `input` into 16kHz mono 16-bit PCM
 pub fn convert_to_whisper_compatible_audio(
     input: impl AsRef<Path>,
     output: impl AsRef<Path>,
+    selection: AudioSelection,
     cancel: Arc<AtomicBool>,
     progress_cb: impl FnMut(i32) + 'static,
 ) -> Result<()> {
-    convert_to_audio(input, output, true, cancel, progress_cb)
+    convert_audio(input, output, true, selection, cancel, progress_cb)
 }
 
 pub fn convert_to_audio(
@@ -391,20 +599,41 @@ pub fn convert_to_audio(
     output: impl AsRef<Path>,
     is_mono: bool,
     cancel: Arc<AtomicBool>,
+    progress_cb: impl FnMut(i32) + 'static,
+) -> Result<()> {
+    convert_audio(
+        input,
+        output,
+        is_mono,
+        AudioSelection::default(),
+        cancel,
+        progress_cb,
+    )
+}
+
+/// Convert the selected audio stream and channel of `input` into 16kHz 16-bit PCM. A selection
+/// other than the default is resolved with the streams of ffprobe
+pub fn convert_audio(
+    input: impl AsRef<Path>,
+    output: impl AsRef<Path>,
+    is_mono: bool,
+    selection: AudioSelection,
+    cancel: Arc<AtomicBool>,
     mut progress_cb: impl FnMut(i32) + 'static,
 ) -> Result<()> {
     let mut audio_duration = None;
+    let selection = resolve_selection(&input, selection)?;
     let input = input.as_ref().display().to_string();
 
-    let arg_string = if is_mono {
-        "-filter:a aformat=sample_fmts=s16:channel_layouts=mono:sample_rates=16000"
+    let aformat = if is_mono {
+        "aformat=sample_fmts=s16:channel_layouts=mono:sample_rates=16000"
     } else {
-        "-filter:a aformat=sample_fmts=s16:sample_rates=16000"
+        "aformat=sample_fmts=s16:sample_rates=16000"
     };
 
     let mut process = FfmpegCommand::new()
         .input(&input)
-        .args(arg_string.split(' '))
+        .args(selection.args(aformat))
         .overwrite()
         .output(output.as_ref().display().to_string())
         .print_command()
@@ -443,6 +672,121 @@ pub fn convert_to_audio(
     Ok(())
 }
 
+// The default selection is left to ffmpeg, without running ffprobe
+fn resolve_selection(input: impl AsRef<Path>, selection: AudioSelection) -> Result<AudioSelection> {
+    if selection == AudioSelection::default() {
+        return Ok(selection);
+    }
+
+    selection.resolve(&audio_streams(&input)?)
+}
+
+/// Decode the selected audio stream and channel of `input` into 16kHz mono f32 PCM. The samples
+/// are read from an ffmpeg pipe and passed to `pcm_cb` as they are decoded, no output file is
+/// written
+pub fn decode_to_whisper_compatible_pcm(
+    input: impl AsRef<Path>,
+    selection: AudioSelection,
+    cancel: Arc<AtomicBool>,
+    mut progress_cb: impl FnMut(i32),
+    mut pcm_cb: impl FnMut(&[f32]),
+) -> Result<()> {
+    let mut audio_duration = None;
+    let mut pending_bytes = vec![];
+    let mut decoded_samples = 0;
+    let mut fatal_errors = vec![];
+
+    let selection = resolve_selection(&input, selection)?;
+    let input = input.as_ref().display().to_string();
+    let mut command = FfmpegCommand::new();
+    command
+        .input(&input)
+        .args(selection.args("aformat=sample_fmts=flt:channel_layouts=mono:sample_rates=16000"))
+        .args(["-vn", "-f", "f32le"])
+        .output("-");
+
+    // `print_command` writes to stdout, which the cli keeps for its json progress
+    debug!("Command: {:?}", command.as_inner());
+
+    let mut process = command
+        .spawn()
+        .with_context(|| format!("ffmpeg spawn child process for decoding {input} failed"))?;
+
+    let iter = process
+        .iter()
+        .with_context(|| format!("ffmpeg iter for decoding {input} failed"))?;
+
+    for event in iter.into_iter() {
+        if cancel.load(Ordering::Relaxed) {
+            break;
+        }
+
+        match event {
+            FfmpegEvent::ParsedDuration(FfmpegDuration { duration, .. }) => {
+                audio_duration = Some((duration * 1000.0) as u64);
+            }
+            FfmpegEvent::Progress(FfmpegProgress { time, .. }) => match timestamp_to_ms(&time) {
+                Ok(ms) if ms > 0 => {
+                    if let Some(duration) = audio_duration {
+                        progress_cb((100 * ms / duration).min(100) as i32);
+                    }
+                }
+                Err(e) => warn!("{e}"),
+                _ => (),
+            },
+
+            // A chunk of the pipe may end in the middle of a sample
+            FfmpegEvent::OutputChunk(bytes) => {
+                pending_bytes.extend_from_slice(&bytes);
+
+                let len = pending_bytes.len() / 4 * 4;
+                let samples = pending_bytes[..len]
+                    .chunks_exact(4)
+                    .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                    .collect::<Vec<_>>();
+                pending_bytes.drain(..len);
+
+                decoded_samples += samples.len();
+                pcm_cb(&samples);
+            }
+
+            // ffmpeg logs errors for damaged frames it conceals or skips, they don't fail decoding
+            FfmpegEvent::Error(e) | FfmpegEvent::Log(LogLevel::Error, e) => {
+                warn!("decoding {input}: {e}");
+            }
+            FfmpegEvent::Log(LogLevel::Fatal, e) => {
+                warn!("decoding {input}: {e}");
+                fatal_errors.push(e);
+            }
+            _ => (),
+        }
+    }
+
+    if cancel.load(Ordering::Relaxed) {
+        _ = process.kill();
+        _ = process.wait();
+        return Ok(());
+    }
+
+    // An input ffmpeg can't open or read to the end must not pass as shorter audio
+    let status = process
+        .wait()
+        .with_context(|| format!("ffmpeg wait for decoding {input} failed"))?;
+
+    if !status.success() || !fatal_errors.is_empty() {
+        bail!(
+            "ffmpeg decoding {input} failed ({status}): {}",
+            fatal_errors.join("; ")
+        );
+    }
+
+    if decoded_samples == 0 {
+        bail!("ffmpeg decoded no audio from {input}");
+    }
+
+    Ok(())
+}
+
 pub fn frame_to_rgb_ppm(frame: &OutputVideoFrame) -> String {
     let mut ppm = format!("P3\n{} {}\n255\n", frame.width, frame.height);
 
@@ -803,12 +1147,82 @@ mod tests {
         Ok(())
     }
 
+    // cargo test test_audio_streams -- --no-capture
+    #[test]
+    fn test_audio_streams() -> Result<()> {
+        let output = r#"{
+            "streams": [
+                {
+                    "index": 1,
+                    "codec_name": "aac",
+                    "codec_type": "audio",
+                    "sample_rate": "48000",
+                    "channels": 2,
+                    "channel_layout": "stereo",
+                    "disposition": { "default": 1 },
+                    "tags": { "language": "eng" }
+                },
+                {
+                    "index": 2,
+                    "codec_name": "ac3",
+                    "codec_type": "audio",
+                    "sample_rate": "44100",
+                    "channels": 6,
+                    "channel_layout": "5.1(side)",
+                    "disposition": { "default": 0 },
+                    "tags": { "language": "und", "title": "Commentary" }
+                }
+            ]
+        }"#;
+
+        let streams = parse_audio_streams(output)?;
+        assert_eq!(streams.len(), 2);
+        assert_eq!(streams[0].index, 1);
+        assert_eq!(streams[0].sample_rate, 48000);
+        assert_eq!(streams[0].language.as_deref(), Some("eng"));
+        assert!(streams[0].is_default);
+        assert_eq!(streams[1].channel_layout, "5.1(side)");
+        assert_eq!(streams[1].language, None);
+        assert_eq!(streams[1].title.as_deref(), Some("Commentary"));
+
+        // The channel of the default stream is checked if no stream is selected
+        let selection = AudioSelection::default();
+        for (selection, is_valid) in [
+            (selection, true),
+            (selection.with_channel(1), true),
+            (selection.with_channel(2), false),
+            (selection.with_stream(1).with_channel(5), true),
+            (selection.with_stream(2), false),
+        ] {
+            assert_eq!(
+                selection.resolve(&streams).is_ok(),
+                is_valid,
+                "{selection:?}"
+            );
+        }
+        assert!(selection.resolve(&[]).is_err());
+
+        // A channel without a stream is mapped from the stream it was checked against
+        let mut streams = streams;
+        streams[0].is_default = false;
+        streams[1].is_default = true;
+        let resolved = selection.with_channel(5).resolve(&streams)?;
+        assert_eq!(resolved, selection.with_stream(1).with_channel(5));
+
+        let args = resolved.args("aformat=sample_rates=16000");
+        assert_eq!(args[..2], ["-map", "0:a:1"]);
+        assert_eq!(args[3], "pan=mono|c0=c5,aformat=sample_rates=16000");
+
+        Ok(())
+    }
+
     // cargo test test_convert_to_whisper_audio -- --no-capture
     #[test]
     fn test_convert_to_whisper_audio() -> Result<()> {
         convert_to_whisper_compatible_audio(
             "./data/test.mp4",
             "./tmp/output.wav",
+            AudioSelection::default(),
             Arc::new(AtomicBool::new(false)),
             |progress| println!("convert video progress: {}%", progress),
         )?;
@@ -816,6 +1230,7 @@ mod tests {
         convert_to_whisper_compatible_audio(
             "./data/test.mp3",
             "./tmp/output.wav",
+            AudioSelection::default().with_channel(0),
             Arc::new(AtomicBool::new(false)),
             |progress| println!("convert audio progress: {}%", progress),
         )?;
@@ -823,6 +1238,34 @@ mod tests {
         Ok(())
     }
 
+    // cargo test test_decode_to_whisper_compatible_pcm -- --no-capture
+    #[test]
+    fn test_decode_to_whisper_compatible_pcm() -> Result<()> {
+        let mut samples = 0;
+        decode_to_whisper_compatible_pcm(
+            "./data/test.mp3",
+            AudioSelection::default(),
+            Arc::new(AtomicBool::new(false)),
+            |progress| println!("decode audio progress: {}%", progress),
+            |pcm| samples += pcm.len(),
+        )?;
+        assert!(samples > 0);
+
+        let broken = std::env::temp_dir().join("ffmpeg-test-broken.mp3");
+        std::fs::write(&broken, [0x42u8; 4096])?;
+        let result = decode_to_whisper_compatible_pcm(
+            &broken,
+            AudioSelection::default(),
+            Arc::new(AtomicBool::new(false)),
+            |_| (),
+            |_| (),
+        );
+        std::fs::remove_file(&broken)?;
+        assert!(result.is_err());
+
+        Ok(())
+    }
+
     // cargo test test_convert_to_audio -- --no-capture
     #[test]
     fn test_convert_to_audio() -> Result<()> {
@@ -27,6 +27,7 @@ async fn main() -> Result<()> {
         whisper::convert_to_compatible_audio(
             &audio_path,
             &output_audio_path,
+            ffmpeg::AudioSelection::default(),
             Arc::new(AtomicBool::new(false)),
             |progress| println!("convert to auido progress: {progress}%"),
         )?;
@@ -0,0 +1,59 @@
+// cargo run --example main_streaming
+
+use anyhow::{bail, Result};
+use std::{path::PathBuf, thread, time::Duration};
+use transcribe::{
+    wav,
+    whisper::{StreamingConfig, StreamingEvent, StreamingTranscriber, WhisperConfig},
+};
+
+fn main() -> Result<()> {
+    let model_path = PathBuf::from("./examples/models/ggml-base.bin");
+    let audio_path = PathBuf::from("./examples/data/test-20.wav");
+
+    if !model_path.exists() {
+        bail!("Can't find modle: {}", model_path.display());
+    }
+
+    let audio_data = wav::read_file(&audio_path)?;
+    if !audio_data.is_whisper_compatible() {
+        bail!("{} is not whisper compatible", audio_path.display());
+    }
+
+    let (transcriber, events) =
+        StreamingTranscriber::new(WhisperConfig::new(model_path), StreamingConfig::default())?;
+
+    let printer = thread::spawn(move || {
+        for event in events {
+            match event {
+                StreamingEvent::Provisional { window, segments } => {
+                    for segment in segments {
+                        println!(
+                            "[{window}] ~ {} -> {}: {}",
+                            segment.start_time, segment.end_time, segment.text
+                        );
+                    }
+                }
+                StreamingEvent::Final { window, segments } => {
+                    for segment in segments {
+                        println!(
+                            "[{window}] {}: {} -> {}: {}",
+                            segment.index, segment.start_time, segment.end_time, segment.text
+                        );
+                    }
+                }
+            }
+        }
+    });
+
+    // Replay the file in 100ms blocks at real time speed like a microphone
+    for block in audio_data.samples.chunks(1600) {
+        transcriber.push_samples(block)?;
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    transcriber.finish()?;
+    _ = printer.join();
+
+    Ok(())
+}
@@ -0,0 +1,294 @@
+use super::wav::AudioData;
+use anyhow::{Context, Result};
+use log::debug;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, LazyLock, Mutex},
+    time::SystemTime,
+};
+
+// About 30 minutes of 48kHz stereo audio
+pub const DEFAULT_MEMORY_BUDGET: u64 = 256 * 1024 * 1024;
+
+static GLOBAL_AUDIO_CACHE: LazyLock<AudioCache> = LazyLock::new(AudioCache::default);
+
+#[derive(Clone, Debug, PartialEq)]
+struct FileKey {
+    path: PathBuf, // canonical path
+
+    // A wav file replaced on disk is read again
+    file_size: u64,
+    modified: Option<SystemTime>,
+}
+
+impl FileKey {
+    fn new(path: impl AsRef<Path>) -> Result<Self> {
+        let path = fs::canonicalize(path.as_ref())
+            .with_context(|| format!("Read audio {} failed", path.as_ref().display()))?;
+        let metadata =
+            fs::metadata(&path).with_context(|| format!("Read audio {} failed", path.display()))?;
+
+        Ok(Self {
+            path,
+            file_size: metadata.len(),
+            modified: metadata.modified().ok(),
+        })
+    }
+}
+
+struct CacheEntry {
+    key: FileKey,
+    start_ms: u64,
+    end_ms: u64,
+    audio_data: Arc<AudioData>,
+    last_used: u64,
+}
+
+impl CacheEntry {
+    fn size(&self) -> u64 {
+        (self.audio_data.samples.len() * size_of::<f32>()) as u64
+    }
+}
+
+/// Decoded ranges of wav files shared by the process, e.g. the waveform of a subtitle is drawn
+/// again without reading the file. A range inside a cached range is sliced from it. The least
+/// recently used ranges are dropped when they take more memory than the budget
+pub struct AudioCache {
+    inner: Mutex<CacheEntries>,
+}
+
+struct CacheEntries {
+    entries: Vec<CacheEntry>,
+    memory_budget: u64,
+    tick: u64,
+}
+
+impl Default for AudioCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_MEMORY_BUDGET)
+    }
+}
+
+impl AudioCache {
+    pub fn new(memory_budget: u64) -> Self {
+        Self {
+            inner: Mutex::new(CacheEntries {
+                entries: vec![],
+                memory_budget,
+                tick: 0,
+            }),
+        }
+    }
+
+    pub fn global() -> &'static AudioCache {
+        &GLOBAL_AUDIO_CACHE
+    }
+
+    /// The audio between `start_ms` and `end_ms` of a wav file, read with
+    /// `AudioData::read_range` on a miss. The file is read without holding the lock
+    pub fn read_range(
+        &self,
+        path: impl AsRef<Path>,
+        start_ms: u64,
+        end_ms: u64,
+    ) -> Result<Arc<AudioData>> {
+        let key = FileKey::new(path.as_ref())?;
+
+        if let Some(audio_data) = self.inner.lock().unwrap().get(&key, start_ms, end_ms) {
+            return Ok(audio_data);
+        }
+
+        let audio_data = Arc::new(AudioData::read_range(&key.path, start_ms, end_ms)?);
+        self.inner
+            .lock()
+            .unwrap()
+            .insert(key, start_ms, end_ms, audio_data.clone());
+
+        Ok(audio_data)
+    }
+
+    pub fn memory_budget(&self) -> u64 {
+        self.inner.lock().unwrap().memory_budget
+    }
+
+    /// Ranges are dropped at once if they take more memory than `memory_budget`.
+    /// 0 disables caching
+    pub fn set_memory_budget(&self, memory_budget: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.memory_budget = memory_budget;
+        inner.shrink();
+    }
+
+    /// Memory of the cached samples in bytes
+    pub fn memory_usage(&self) -> u64 {
+        self.inner.lock().unwrap().usage()
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drop every cached range of the file, e.g. before the file is removed
+    pub fn evict(&self, path: impl AsRef<Path>) {
+        let path = fs::canonicalize(path.as_ref()).unwrap_or(path.as_ref().to_path_buf());
+
+        self.inner
+            .lock()
+            .unwrap()
+            .entries
+            .retain(|entry| entry.key.path != path);
+    }
+
+    pub fn clear(&self) {
+        self.inner.lock().unwrap().entries.clear();
+    }
+}
+
+impl CacheEntries {
+    fn usage(&self) -> u64 {
+        self.entries.iter().map(|entry| entry.size()).sum()
+    }
+
+    fn get(&mut self, key: &FileKey, start_ms: u64, end_ms: u64) -> Option<Arc<AudioData>> {
+        self.tick += 1;
+
+        let entry = self.entries.iter_mut().find(|entry| {
+            &entry.key == key && entry.start_ms <= start_ms && entry.end_ms >= end_ms
+        })?;
+        entry.last_used = self.tick;
+
+        if entry.start_ms == start_ms && entry.end_ms == end_ms {
+            return Some(entry.audio_data.clone());
+        }
+
+        Some(Arc::new(
+            entry
+                .audio_data
+                .slice(start_ms - entry.start_ms, end_ms - entry.start_ms),
+        ))
+    }
+
+    fn insert(&mut self, key: FileKey, start_ms: u64, end_ms: u64, audio_data: Arc<AudioData>) {
+        self.tick += 1;
+
+        // Ranges of an older version of a replaced file are never used again, and ranges inside
+        // the new one are sliced from it
+        self.entries.retain(|entry| {
+            entry.key.path != key.path
+                || (entry.key == key && !(start_ms <= entry.start_ms && end_ms >= entry.end_ms))
+        });
+
+        self.entries.push(CacheEntry {
+            key,
+            start_ms,
+            end_ms,
+            audio_data,
+            last_used: self.tick,
+        });
+
+        self.shrink();
+    }
+
+    // Drop the least recently used entries until the usage is within the budget
+    fn shrink(&mut self) {
+        while !self.entries.is_empty() && self.usage() > self.memory_budget {
+            let (index, _) = self
+                .entries
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .unwrap();
+
+            let entry = self.entries.remove(index);
+            debug!(
+                "Drop cached audio {}: {}ms - {}ms",
+                entry.key.path.display(),
+                entry.start_ms,
+                entry.end_ms
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wav::AudioConfig;
+
+    fn key(path: &str, file_size: u64) -> FileKey {
+        FileKey {
+            path: PathBuf::from(path),
+            file_size,
+            modified: None,
+        }
+    }
+
+    // 1000 frames per second, 1000 * 4 bytes per second
+    fn audio(start_ms: u64, end_ms: u64) -> Arc<AudioData> {
+        let samples = (start_ms..end_ms).map(|ms| ms as f32).collect();
+        Arc::new(AudioData::new(samples, AudioConfig::new(1000, 1, 16)))
+    }
+
+    // cargo test test_audio_cache_entries -- --no-capture
+    #[test]
+    fn test_audio_cache_entries() {
+        let mut entries = CacheEntries {
+            entries: vec![],
+            memory_budget: 10_000,
+            tick: 0,
+        };
+
+        entries.insert(key("a.wav", 1), 1000, 2000, audio(1000, 2000));
+        assert_eq!(entries.usage(), 4000);
+
+        // Inside the cached range
+        let audio_data = entries.get(&key("a.wav", 1), 1200, 1500).unwrap();
+        assert_eq!(audio_data.samples.len(), 300);
+        assert_eq!(audio_data.samples[0], 1200.0);
+
+        assert!(entries.get(&key("a.wav", 1), 500, 1500).is_none());
+        assert!(entries.get(&key("a.wav", 2), 1200, 1500).is_none());
+
+        // A wider range replaces the ranges inside it
+        entries.insert(key("a.wav", 1), 0, 2000, audio(0, 2000));
+        assert_eq!(entries.entries.len(), 1);
+
+        // a.wav is the least recently used one
+        entries.insert(key("b.wav", 1), 0, 1000, audio(0, 1000));
+        entries.insert(key("c.wav", 1), 0, 1000, audio(0, 1000));
+        assert_eq!(entries.entries.len(), 2);
+        assert!(entries.get(&key("a.wav", 1), 0, 1000).is_none());
+
+        // The replaced file is never used again
+        entries.insert(key("b.wav", 2), 0, 500, audio(0, 500));
+        assert!(entries.get(&key("b.wav", 1), 0, 1000).is_none());
+        assert_eq!(entries.usage(), 6000);
+    }
+
+    // cargo test test_audio_cache_read_range -- --no-capture
+    #[test]
+    fn test_audio_cache_read_range() -> Result<()> {
+        let path = "./examples/data/test-20.wav";
+        let cache = AudioCache::default();
+
+        let audio_data = cache.read_range(path, 1000, 3000)?;
+        assert_eq!(cache.len(), 1);
+
+        let cached = cache.read_range(path, 1000, 3000)?;
+        assert!(Arc::ptr_eq(&audio_data, &cached));
+
+        let inner = cache.read_range(path, 1500, 2000)?;
+        assert_eq!(inner.samples, audio_data.slice(500, 1000).samples);
+        assert_eq!(cache.len(), 1);
+
+        cache.evict(path);
+        assert!(cache.is_empty());
+
+        Ok(())
+    }
+}
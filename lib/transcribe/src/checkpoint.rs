@@ -0,0 +1,356 @@
+use super::chunking::ChunkingStrategy;
+use super::wav::AudioPreprocess;
+use super::whisper::{TranscriptionResult, WhisperConfig, WhisperSamplingStrategy};
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckpointChunk {
+    pub index: usize,
+    pub start_offset_ms: u64,
+    pub end_offset_ms: u64,
+
+    // Of the 16kHz mono chunk samples before preprocessing. Empty in checkpoints written
+    // before chunks were hashed, so they are never resumed
+    #[serde(default)]
+    pub audio_hash: String,
+
+    pub result: TranscriptionResult, // timestamps are relative to `start_offset_ms`
+}
+
+/// What became of the checkpoint of a chunked transcription
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum CheckpointStatus {
+    // No checkpoint was found, a new one is written
+    Created,
+
+    // Started from the checkpoint, `chunks` finished chunks are not decoded again
+    Resumed { chunks: usize },
+
+    // The checkpoint can't be resumed, e.g. it was made with another model. Started over
+    Discarded { reason: String },
+}
+
+/// Finished chunks of a chunked transcription. A transcription started with the same
+/// model, language, prompt, glossary, sampling strategy, chunking and audio preprocessing skips
+/// the chunks with the same audio
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TranscriptionCheckpoint {
+    pub model_name: String,
+    pub model_size: u64,          // bytes
+    pub language: Option<String>, // None is auto detect
+
+    #[serde(default)]
+    pub initial_prompt: Option<String>,
+
+    #[serde(default)]
+    pub glossary_terms: Vec<String>, // put into the prompt
+
+    // None in checkpoints written before it was checked, so they are never resumed
+    #[serde(default)]
+    pub sampling_strategy: Option<WhisperSamplingStrategy>,
+
+    #[serde(default)]
+    pub chunking: Option<ChunkingStrategy>,
+
+    #[serde(default)]
+    pub preprocess: Option<AudioPreprocess>,
+
+    pub chunks: Vec<CheckpointChunk>, // sorted by index
+}
+
+impl TranscriptionCheckpoint {
+    pub fn new(config: &WhisperConfig) -> Result<Self> {
+        let (model_name, model_size) = model_identity(config)?;
+
+        Ok(Self {
+            model_name,
+            model_size,
+            language: checkpoint_language(config),
+            initial_prompt: config.initial_prompt.clone(),
+            glossary_terms: glossary_terms(config),
+            sampling_strategy: Some(config.sampling_strategy),
+            chunking: config.chunking,
+            preprocess: config.preprocess.clone(),
+            chunks: vec![],
+        })
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let contents = fs::read_to_string(path.as_ref())
+            .with_context(|| format!("Read checkpoint {} failed", path.as_ref().display()))?;
+
+        serde_json::from_str(&contents)
+            .with_context(|| format!("Parse checkpoint {} failed", path.as_ref().display()))
+    }
+
+    /// Written to a temporary file first, so a crash never leaves a broken checkpoint
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let tmp_path = path.with_extension("tmp");
+
+        fs::write(&tmp_path, serde_json::to_string(self)?)
+            .with_context(|| format!("Write checkpoint {} failed", tmp_path.display()))?;
+        fs::rename(&tmp_path, path)
+            .with_context(|| format!("Save checkpoint {} failed", path.display()))?;
+
+        Ok(())
+    }
+
+    /// Fails if the checkpoint was made with another model, language, prompt, glossary,
+    /// sampling strategy, chunking or audio preprocessing
+    pub fn check_config(&self, config: &WhisperConfig) -> Result<()> {
+        let (model_name, model_size) = model_identity(config)?;
+        if model_name != self.model_name || model_size != self.model_size {
+            bail!(
+                "Checkpoint was made with model {}, not {model_name}",
+                self.model_name
+            );
+        }
+
+        let language = checkpoint_language(config);
+        if language != self.language {
+            bail!(
+                "Checkpoint was made with language {}, not {}",
+                self.language.as_deref().unwrap_or("auto"),
+                language.as_deref().unwrap_or("auto")
+            );
+        }
+
+        if config.initial_prompt != self.initial_prompt {
+            bail!("Checkpoint was made with another initial prompt");
+        }
+
+        if glossary_terms(config) != self.glossary_terms {
+            bail!("Checkpoint was made with another glossary");
+        }
+
+        if Some(config.sampling_strategy) != self.sampling_strategy {
+            bail!("Checkpoint was made with another sampling strategy");
+        }
+
+        if config.chunking != self.chunking {
+            bail!("Checkpoint was made with other chunking");
+        }
+
+        if config.preprocess != self.preprocess {
+            bail!("Checkpoint was made with other audio preprocessing");
+        }
+
+        Ok(())
+    }
+
+    /// The finished chunk at `index`, if it has the same offsets and audio
+    pub fn find_chunk(
+        &self,
+        index: usize,
+        start_offset_ms: u64,
+        end_offset_ms: u64,
+        audio_hash: &str,
+    ) -> Option<&CheckpointChunk> {
+        self.chunks.iter().find(|chunk| {
+            chunk.index == index
+                && chunk.start_offset_ms == start_offset_ms
+                && chunk.end_offset_ms == end_offset_ms
+                && chunk.audio_hash == audio_hash
+        })
+    }
+
+    pub fn push_chunk(&mut self, chunk: CheckpointChunk) {
+        self.chunks.retain(|c| c.index != chunk.index);
+
+        let pos = self.chunks.partition_point(|c| c.index < chunk.index);
+        self.chunks.insert(pos, chunk);
+    }
+
+    /// End offset of the last chunk finished without gaps from the start of the audio
+    pub fn resume_offset_ms(&self) -> u64 {
+        self.chunks
+            .iter()
+            .enumerate()
+            .take_while(|(i, chunk)| chunk.index == *i)
+            .last()
+            .map(|(_, chunk)| chunk.end_offset_ms)
+            .unwrap_or_default()
+    }
+}
+
+/// FNV-1a hash of the samples of a chunk. It is stable across runs and platforms
+pub fn audio_hash(samples: &[f32]) -> String {
+    let mut hash = FNV_OFFSET_BASIS;
+    for sample in samples.iter() {
+        hash = fnv1a(hash, &sample.to_bits().to_le_bytes());
+    }
+
+    format!("{hash:016x}")
+}
+
+/// Checkpoint file name of a media file, e.g. "intro.mp4.1f2e3d4c5b6a7980.checkpoint.json".
+/// The hash of the canonical path keeps files of the same name in other directories apart
+pub fn checkpoint_file_name(media_path: impl AsRef<Path>) -> String {
+    let media_path = media_path.as_ref();
+    let path = fs::canonicalize(media_path).unwrap_or(media_path.to_path_buf());
+    let name = media_path.file_name().unwrap_or_default().to_string_lossy();
+    let hash = fnv1a(FNV_OFFSET_BASIS, path.to_string_lossy().as_bytes());
+
+    format!("{name}.{hash:016x}.checkpoint.json")
+}
+
+fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+
+    hash
+}
+
+fn model_identity(config: &WhisperConfig) -> Result<(String, u64)> {
+    let model_name = config
+        .model_path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let model_size = fs::metadata(&config.model_path)
+        .with_context(|| format!("Read model {} failed", config.model_path.display()))?
+        .len();
+
+    Ok((model_name, model_size))
+}
+
+fn glossary_terms(config: &WhisperConfig) -> Vec<String> {
+    config
+        .glossary
+        .as_ref()
+        .map(|glossary| glossary.terms.clone())
+        .unwrap_or_default()
+}
+
+fn checkpoint_language(config: &WhisperConfig) -> Option<String> {
+    if config.is_auto_language() {
+        None
+    } else {
+        config.language.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::glossary::Glossary;
+
+    fn chunk(index: usize, start_offset_ms: u64, end_offset_ms: u64) -> CheckpointChunk {
+        CheckpointChunk {
+            index,
+            start_offset_ms,
+            end_offset_ms,
+            audio_hash: audio_hash(&[index as f32, 0.1]),
+            result: TranscriptionResult {
+                text: format!("chunk {index}"),
+                language: Some("en".to_string()),
+                segments: vec![],
+                processing_time: 0,
+                audio_duration: end_offset_ms - start_offset_ms,
+                hallucinations: vec![],
+                glossary_replacements: vec![],
+                chunks: vec![],
+                checkpoint: None,
+            },
+        }
+    }
+
+    // cargo test test_checkpoint -- --no-capture
+    #[test]
+    fn test_checkpoint() -> Result<()> {
+        let dir = std::env::temp_dir().join("transcribe-test-checkpoint");
+        fs::create_dir_all(&dir)?;
+
+        let model_path = dir.join("ggml-test.bin");
+        fs::write(&model_path, [0u8; 16])?;
+
+        let config = WhisperConfig::new(&model_path).with_language("en");
+
+        let mut checkpoint = TranscriptionCheckpoint::new(&config)?;
+        checkpoint.push_chunk(chunk(2, 118000, 180000));
+        checkpoint.push_chunk(chunk(0, 0, 60000));
+        assert_eq!(checkpoint.resume_offset_ms(), 60000);
+
+        checkpoint.push_chunk(chunk(1, 59000, 119000));
+        assert_eq!(checkpoint.resume_offset_ms(), 180000);
+
+        let path = dir.join("test.checkpoint.json");
+        checkpoint.save(&path)?;
+
+        let checkpoint = TranscriptionCheckpoint::load(&path)?;
+        assert_eq!(checkpoint.chunks.len(), 3);
+        let hash = audio_hash(&[1.0, 0.1]);
+        assert!(checkpoint.find_chunk(1, 59000, 119000, &hash).is_some());
+        assert!(checkpoint.find_chunk(1, 60000, 120000, &hash).is_none());
+
+        // Other audio at the same offsets
+        let other_hash = audio_hash(&[1.0, 0.2]);
+        assert_ne!(hash, other_hash);
+        assert!(
+            checkpoint
+                .find_chunk(1, 59000, 119000, &other_hash)
+                .is_none()
+        );
+
+        checkpoint.check_config(&config)?;
+
+        assert!(
+            checkpoint
+                .check_config(&config.clone().with_language("zh"))
+                .is_err()
+        );
+
+        assert!(
+            checkpoint
+                .check_config(&config.clone().with_preprocess(AudioPreprocess::default()))
+                .is_err()
+        );
+
+        assert!(
+            checkpoint
+                .check_config(&config.clone().with_initial_prompt("Rust meetup"))
+                .is_err()
+        );
+        assert!(
+            checkpoint
+                .check_config(&config.clone().with_glossary(Glossary::new(["Tokio"])))
+                .is_err()
+        );
+        assert!(
+            checkpoint
+                .check_config(&config.clone().with_beam_search(5, -1.0))
+                .is_err()
+        );
+        assert!(
+            checkpoint
+                .check_config(&config.clone().with_chunk_length_ms(30000))
+                .is_err()
+        );
+
+        fs::write(&model_path, [0u8; 32])?;
+        assert!(checkpoint.check_config(&config).is_err());
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    // cargo test test_checkpoint_file_name -- --no-capture
+    #[test]
+    fn test_checkpoint_file_name() {
+        let name = checkpoint_file_name("a/intro.mp4");
+        assert!(name.starts_with("intro.mp4."));
+        assert!(name.ends_with(".checkpoint.json"));
+
+        assert_eq!(name, checkpoint_file_name("a/intro.mp4"));
+        assert_ne!(name, checkpoint_file_name("b/intro.mp4"));
+    }
+}
@@ -0,0 +1,611 @@
+use super::vad::EnergyVAD;
+use super::wav::{AudioConfig, AudioData};
+use super::whisper::{self, VadParams};
+use anyhow::{Result, bail};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::mem;
+
+// Frames of the energy silence detection
+const SILENCE_FRAME_SIZE_MS: u64 = 200;
+const SILENCE_FRAME_SHIFT_MS: u64 = 100;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum ChunkSplitMode {
+    // Split in the middle of the silence closest to the target length. Frames quieter than
+    // `silence_rms_ratio` of the RMS around the target are silence
+    #[default]
+    Silence,
+
+    // Split at the target length, the next chunk starts `overlap_ms` before the split
+    FixedOverlap,
+
+    // Split in the middle of the gap between silero speech segments closest to the target length
+    Vad,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum SplitReason {
+    Silence { silence_ms: u64 },
+    SpeechGap { gap_ms: u64 },
+
+    // No silence or speech gap is found, or `ChunkSplitMode::FixedOverlap`.
+    // The next chunk overlaps this one by `overlap_ms`
+    Fixed,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ChunkRange {
+    pub start_ms: u64,
+    pub end_ms: u64,
+    pub split: Option<SplitReason>, // how `end_ms` was chosen, None for the last chunk
+}
+
+/// How long audio is split into chunks, which are decoded separately to avoid timestamp drift
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ChunkingStrategy {
+    pub mode: ChunkSplitMode,
+    pub chunk_length_ms: u64, // Target length of a chunk, default 60000, 0 disables chunking
+    pub overlap_ms: u64,      // Overlap of a chunk not split in a silence, default 1000
+
+    // A split point is searched in this range around the target length
+    pub forward_search_ms: u64,  // default 30000
+    pub backward_search_ms: u64, // default 0, less than `chunk_length_ms`
+
+    pub min_silence_ms: u64, // Shortest silence or speech gap to split in, default 500
+    pub silence_rms_ratio: f32, // default 0.5, of `ChunkSplitMode::Silence`
+    pub vad_params: VadParams, // of `ChunkSplitMode::Vad`
+}
+
+impl Default for ChunkingStrategy {
+    fn default() -> Self {
+        Self {
+            mode: ChunkSplitMode::default(),
+            chunk_length_ms: 60_000,
+            overlap_ms: 1000,
+            forward_search_ms: 30_000,
+            backward_search_ms: 0,
+            min_silence_ms: 500,
+            silence_rms_ratio: 0.5,
+            vad_params: VadParams::default(),
+        }
+    }
+}
+
+impl ChunkingStrategy {
+    pub fn new(mode: ChunkSplitMode) -> Self {
+        Self {
+            mode,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_chunk_length_ms(mut self, ms: u64) -> Self {
+        self.chunk_length_ms = ms;
+        self
+    }
+
+    pub fn with_overlap_ms(mut self, ms: u64) -> Self {
+        self.overlap_ms = ms;
+        self
+    }
+
+    pub fn with_forward_search_ms(mut self, ms: u64) -> Self {
+        self.forward_search_ms = ms;
+        self
+    }
+
+    pub fn with_backward_search_ms(mut self, ms: u64) -> Self {
+        self.backward_search_ms = ms;
+        self
+    }
+
+    pub fn with_min_silence_ms(mut self, ms: u64) -> Self {
+        self.min_silence_ms = ms;
+        self
+    }
+
+    pub fn with_silence_rms_ratio(mut self, ratio: f32) -> Self {
+        self.silence_rms_ratio = ratio;
+        self
+    }
+
+    pub fn with_vad_params(mut self, vad_params: VadParams) -> Self {
+        self.vad_params = vad_params;
+        self
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.chunk_length_ms == 0 {
+            return Ok(());
+        }
+
+        if self.overlap_ms >= self.chunk_length_ms {
+            bail!("chunk overlap_ms should be less than chunk_length_ms");
+        }
+
+        if self.backward_search_ms >= self.chunk_length_ms {
+            bail!("backward_search_ms should be less than chunk_length_ms");
+        }
+
+        if self.silence_rms_ratio <= 0.0 {
+            bail!("silence_rms_ratio should be greater than 0.0");
+        }
+
+        self.vad_params.validate()
+    }
+
+    /// Chunks of mono audio and how they are split, e.g. to show the split points on the
+    /// waveform. A single chunk if the audio is not longer than `chunk_length_ms`
+    pub fn split(&self, audio_data: &AudioData) -> Result<Vec<ChunkRange>> {
+        let mono_audio;
+        let audio_data = if audio_data.config.channels > 1 {
+            mono_audio = audio_data.to_mono();
+            &mono_audio
+        } else {
+            audio_data
+        };
+
+        let sample_rate = audio_data.config.sample_rate as f64;
+        let to_ms = |pos: usize| (pos as f64 / sample_rate * 1000.0) as u64;
+
+        Ok(self
+            .split_samples(audio_data)?
+            .into_iter()
+            .map(|(start, end, split)| ChunkRange {
+                start_ms: to_ms(start),
+                end_ms: to_ms(end),
+                split,
+            })
+            .collect())
+    }
+
+    // Sample ranges of the chunks of mono audio
+    pub(crate) fn split_samples(
+        &self,
+        audio_data: &AudioData,
+    ) -> Result<Vec<(usize, usize, Option<SplitReason>)>> {
+        let mut splitter = ChunkSplitter::new(*self, audio_data.config.sample_rate)?;
+        let block = splitter.to_samples(self.chunk_length_ms).max(1);
+
+        // Pushed in blocks, so the buffer of the splitter stays small
+        let mut chunks = vec![];
+        for samples in audio_data.samples.chunks(block) {
+            chunks.extend(splitter.push(samples)?);
+        }
+        chunks.extend(splitter.finish()?);
+
+        Ok(chunks
+            .into_iter()
+            .map(|chunk| (chunk.start, chunk.end, chunk.split))
+            .collect())
+    }
+
+    // Middle of the silence in `search` closest to `target` and the silence length in ms
+    fn find_silence_split_point(
+        &self,
+        samples: &[f32],
+        sample_rate: f64,
+        search: (usize, usize),
+        target: usize,
+    ) -> Option<(usize, u64)> {
+        let search_samples = &samples[search.0..search.1];
+        if search_samples.is_empty() {
+            return None;
+        }
+
+        let vad = EnergyVAD::new(sample_rate as u32)
+            .with_threshold(EnergyVAD::calculate_rms(search_samples) * self.silence_rms_ratio)
+            .with_frame_size_ms(SILENCE_FRAME_SIZE_MS)
+            .with_frame_shift_ms(SILENCE_FRAME_SHIFT_MS);
+
+        let to_samples = |ms: u64| ((ms as f64 * sample_rate / 1000.0) as usize).max(1);
+        let (frame_size, frame_shift) = (
+            to_samples(SILENCE_FRAME_SIZE_MS),
+            to_samples(SILENCE_FRAME_SHIFT_MS),
+        );
+        let min_silence = to_samples(self.min_silence_ms);
+
+        let mut best: Option<(usize, usize)> = None;
+        let mut silence_start = None;
+
+        for offset in (0..search_samples.len()).step_by(frame_shift) {
+            let frame_end = (offset + frame_size).min(search_samples.len());
+            let has_speech = vad.contain_speech(&search_samples[offset..frame_end]);
+
+            if !has_speech {
+                silence_start.get_or_insert(offset);
+            }
+
+            // A silence ends at speech or at the end of the search range
+            let is_last = offset + frame_shift >= search_samples.len();
+            if !(has_speech || is_last) {
+                continue;
+            }
+
+            let Some(start) = silence_start.take() else {
+                continue;
+            };
+
+            let end = if has_speech {
+                offset
+            } else {
+                search_samples.len()
+            };
+
+            if end - start >= min_silence {
+                let split = search.0 + start + (end - start) / 2;
+                if best.is_none_or(|(pos, _)| split.abs_diff(target) < pos.abs_diff(target)) {
+                    best = Some((split, end - start));
+                }
+            }
+        }
+
+        let Some((split, silence)) = best else {
+            debug!(
+                "No suitable silence found around {:.2}s",
+                target as f64 / sample_rate
+            );
+            return None;
+        };
+
+        Some((split, (silence as f64 / sample_rate * 1000.0) as u64))
+    }
+}
+
+/// A chunk split off by `ChunkSplitter`, positions are sample indexes in the whole audio
+#[derive(Debug, Clone)]
+pub(crate) struct ChunkSamples {
+    pub start: usize,
+    pub end: usize,
+    pub split: Option<SplitReason>, // None for the last chunk
+    pub overlap: usize,             // samples at the end shared with the next chunk
+    pub samples: Vec<f32>,
+}
+
+/// Split mono samples into chunks as they are pushed, e.g. while they are decoded. A split is
+/// chosen once the search range after the target is pushed, so only the audio from the start of
+/// the current chunk to the end of its search range is kept
+pub(crate) struct ChunkSplitter {
+    strategy: ChunkingStrategy,
+    sample_rate: f64,
+    buffer: Vec<f32>,
+    buffer_start: usize, // Sample index of `buffer[0]`, the start of the current chunk
+}
+
+impl ChunkSplitter {
+    pub fn new(strategy: ChunkingStrategy, sample_rate: u32) -> Result<Self> {
+        strategy.validate()?;
+
+        Ok(Self {
+            strategy,
+            sample_rate: sample_rate as f64,
+            buffer: vec![],
+            buffer_start: 0,
+        })
+    }
+
+    /// The chunks whose split is chosen with `samples`
+    pub fn push(&mut self, samples: &[f32]) -> Result<Vec<ChunkSamples>> {
+        self.buffer.extend_from_slice(samples);
+
+        let chunk_samples = self.to_samples(self.strategy.chunk_length_ms);
+        let search_end = chunk_samples + self.to_samples(self.strategy.forward_search_ms);
+
+        let mut chunks = vec![];
+        while chunk_samples > 0 && self.buffer.len() > search_end {
+            chunks.push(self.split_next()?);
+        }
+
+        Ok(chunks)
+    }
+
+    /// The remaining chunks at the end of the audio
+    pub fn finish(mut self) -> Result<Vec<ChunkSamples>> {
+        let chunk_samples = self.to_samples(self.strategy.chunk_length_ms);
+
+        let mut chunks = vec![];
+        while chunk_samples > 0 && self.buffer.len() > chunk_samples {
+            let chunk = self.split_next()?;
+            let is_last = chunk.split.is_none();
+            chunks.push(chunk);
+
+            if is_last {
+                return Ok(chunks);
+            }
+        }
+
+        chunks.push(self.take_last());
+        Ok(chunks)
+    }
+
+    // Split the next chunk off the buffer, which is longer than a chunk. The split is searched
+    // in the buffer only, so a search range cut by the end of the audio is searched as it is
+    fn split_next(&mut self) -> Result<ChunkSamples> {
+        let total = self.buffer.len();
+        let target = self.to_samples(self.strategy.chunk_length_ms);
+        let overlap = self.to_samples(self.strategy.overlap_ms);
+        let (backward, forward) = (
+            self.to_samples(self.strategy.backward_search_ms),
+            self.to_samples(self.strategy.forward_search_ms),
+        );
+
+        let search = (
+            target.saturating_sub(backward).max(1),
+            (target + forward).min(total),
+        );
+
+        let split = match self.strategy.mode {
+            ChunkSplitMode::FixedOverlap => None,
+            ChunkSplitMode::Silence => self
+                .strategy
+                .find_silence_split_point(&self.buffer, self.sample_rate, search, target)
+                .map(|(pos, silence_ms)| (pos, SplitReason::Silence { silence_ms })),
+            ChunkSplitMode::Vad => {
+                let audio_data = AudioData::new(
+                    self.buffer[..search.1].to_vec(),
+                    AudioConfig::new(self.sample_rate as u32, 1, 16),
+                );
+                let speech =
+                    whisper::silero_speech_segments(&audio_data, &self.strategy.vad_params)?;
+
+                closest_speech_gap(
+                    &speech,
+                    self.to_ms(search.1),
+                    (self.to_ms(search.0), self.to_ms(search.1)),
+                    self.to_ms(target),
+                    self.strategy.min_silence_ms,
+                )
+                .map(|(middle_ms, gap_ms)| {
+                    (
+                        self.to_samples(middle_ms),
+                        SplitReason::SpeechGap { gap_ms },
+                    )
+                })
+            }
+        };
+
+        let (end, next_start, reason) = match split {
+            Some((pos, reason)) if pos > 0 => (pos.min(total), pos.min(total), reason),
+            _ => (target, target - overlap, SplitReason::Fixed),
+        };
+
+        if end >= total {
+            return Ok(self.take_last());
+        }
+
+        debug!(
+            "Split chunk at {:.2}s: {reason:?}",
+            (self.buffer_start + end) as f64 / self.sample_rate
+        );
+
+        let chunk = ChunkSamples {
+            start: self.buffer_start,
+            end: self.buffer_start + end,
+            split: Some(reason),
+            overlap: end - next_start,
+            samples: self.buffer[..end].to_vec(),
+        };
+
+        self.buffer.drain(..next_start);
+        self.buffer_start += next_start;
+        Ok(chunk)
+    }
+
+    fn take_last(&mut self) -> ChunkSamples {
+        let samples = mem::take(&mut self.buffer);
+        let start = self.buffer_start;
+        self.buffer_start += samples.len();
+
+        ChunkSamples {
+            start,
+            end: self.buffer_start,
+            split: None,
+            overlap: 0,
+            samples,
+        }
+    }
+
+    fn to_samples(&self, ms: u64) -> usize {
+        (ms as f64 * self.sample_rate / 1000.0) as usize
+    }
+
+    fn to_ms(&self, pos: usize) -> u64 {
+        (pos as f64 / self.sample_rate * 1000.0) as u64
+    }
+}
+
+// Middle of the gap between `speech` segments closest to `target_ms` with the middle in
+// `search_ms`, and the gap length. The audio before the first and after the last speech are gaps
+fn closest_speech_gap(
+    speech: &[(u64, u64)],
+    total_ms: u64,
+    search_ms: (u64, u64),
+    target_ms: u64,
+    min_gap_ms: u64,
+) -> Option<(u64, u64)> {
+    let mut gaps = vec![];
+    let mut previous_end = 0;
+
+    for (start, end) in speech {
+        if *start > previous_end {
+            gaps.push((previous_end, *start));
+        }
+        previous_end = previous_end.max(*end);
+    }
+
+    if total_ms > previous_end {
+        gaps.push((previous_end, total_ms));
+    }
+
+    gaps.into_iter()
+        .filter(|(start, end)| end - start >= min_gap_ms)
+        .map(|(start, end)| ((start + end) / 2, end - start))
+        .filter(|(middle, _)| (search_ms.0..=search_ms.1).contains(middle))
+        .min_by_key(|(middle, _)| middle.abs_diff(target_ms))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wav::AudioConfig;
+
+    // 16kHz mono tone with the silences (start_ms, end_ms)
+    fn audio_with_silences(duration_ms: u64, silences: &[(u64, u64)]) -> AudioData {
+        let samples = (0..duration_ms * 16)
+            .map(|i| {
+                let ms = i / 16;
+                if silences
+                    .iter()
+                    .any(|(start, end)| (*start..*end).contains(&ms))
+                {
+                    0.0
+                } else {
+                    (i as f32 * 0.05).sin() * 0.5
+                }
+            })
+            .collect();
+
+        AudioData::new(samples, AudioConfig::whisper_optimized())
+    }
+
+    fn ends(ranges: &[ChunkRange]) -> Vec<u64> {
+        ranges.iter().map(|range| range.end_ms).collect()
+    }
+
+    // cargo test test_split_fixed_overlap -- --no-capture
+    #[test]
+    fn test_split_fixed_overlap() -> Result<()> {
+        let audio_data = audio_with_silences(25_000, &[]);
+        let ranges = ChunkingStrategy::new(ChunkSplitMode::FixedOverlap)
+            .with_chunk_length_ms(10_000)
+            .with_overlap_ms(1000)
+            .split(&audio_data)?;
+
+        let starts = ranges.iter().map(|r| r.start_ms).collect::<Vec<_>>();
+        assert_eq!(starts, vec![0, 9000, 18_000]);
+        assert_eq!(ends(&ranges), vec![10_000, 19_000, 25_000]);
+        assert_eq!(ranges[0].split, Some(SplitReason::Fixed));
+        assert_eq!(ranges[2].split, None);
+
+        // Not longer than a chunk
+        let ranges = ChunkingStrategy::default().split(&audio_data)?;
+        assert_eq!(ranges.len(), 1);
+        assert_eq!(ranges[0].end_ms, 25_000);
+
+        Ok(())
+    }
+
+    // cargo test test_split_silence -- --no-capture
+    #[test]
+    fn test_split_silence() -> Result<()> {
+        let audio_data = audio_with_silences(30_000, &[(8000, 9000), (12_000, 13_000)]);
+
+        // Forward search finds the silence after the target only
+        let strategy = ChunkingStrategy::new(ChunkSplitMode::Silence)
+            .with_chunk_length_ms(10_000)
+            .with_forward_search_ms(5000);
+        let ranges = strategy.split(&audio_data)?;
+
+        println!("{ranges:#?}");
+        assert!(ranges[0].end_ms.abs_diff(12_500) <= 200);
+        assert!(matches!(
+            ranges[0].split,
+            Some(SplitReason::Silence { silence_ms }) if silence_ms >= 800
+        ));
+
+        // Split in silence, so the next chunk doesn't overlap
+        assert_eq!(ranges[1].start_ms, ranges[0].end_ms);
+
+        // The closer silence before the target is taken with backward search
+        let ranges = strategy.with_backward_search_ms(3000).split(&audio_data)?;
+        assert!(ranges[0].end_ms.abs_diff(8500) <= 200);
+
+        // No silence, fall back to the target with overlap
+        let ranges = strategy.with_forward_search_ms(1000).split(&audio_data)?;
+        assert_eq!(ranges[0].end_ms, 10_000);
+        assert_eq!(ranges[0].split, Some(SplitReason::Fixed));
+        assert_eq!(ranges[1].start_ms, 9000);
+
+        Ok(())
+    }
+
+    // cargo test test_chunk_splitter -- --no-capture
+    #[test]
+    fn test_chunk_splitter() -> Result<()> {
+        let audio_data = audio_with_silences(45_000, &[(12_000, 13_000), (26_000, 26_400)]);
+        let strategy = ChunkingStrategy::new(ChunkSplitMode::Silence)
+            .with_chunk_length_ms(10_000)
+            .with_forward_search_ms(5000);
+
+        // Pushed in small blocks, the chunks are the same as split at once
+        let mut splitter = ChunkSplitter::new(strategy, 16000)?;
+        let mut chunks = vec![];
+        for samples in audio_data.samples.chunks(1000) {
+            chunks.extend(splitter.push(samples)?);
+        }
+        chunks.extend(splitter.finish()?);
+
+        let ranges = strategy.split_samples(&audio_data)?;
+        assert_eq!(
+            chunks
+                .iter()
+                .map(|chunk| (chunk.start, chunk.end, chunk.split.clone()))
+                .collect::<Vec<_>>(),
+            ranges
+        );
+        assert!(ranges.len() > 2);
+
+        for chunk in chunks.iter() {
+            assert_eq!(chunk.samples, audio_data.samples[chunk.start..chunk.end]);
+        }
+
+        // The silence is too short, so the second chunk overlaps the third
+        assert_eq!(chunks[1].split, Some(SplitReason::Fixed));
+        assert_eq!(chunks[2].start, chunks[1].end - chunks[1].overlap);
+        assert_eq!(chunks[0].overlap, 0);
+
+        Ok(())
+    }
+
+    // cargo test test_closest_speech_gap -- --no-capture
+    #[test]
+    fn test_closest_speech_gap() {
+        let speech = [(500, 9000), (9800, 12_000), (12_200, 20_000)];
+
+        assert_eq!(
+            closest_speech_gap(&speech, 30_000, (8000, 15_000), 10_000, 500),
+            Some((9400, 800))
+        );
+
+        // The 200ms gap is too short
+        assert_eq!(
+            closest_speech_gap(&speech, 30_000, (11_000, 15_000), 12_000, 500),
+            None
+        );
+
+        // The audio after the last speech
+        assert_eq!(
+            closest_speech_gap(&speech, 30_000, (20_000, 30_000), 24_000, 500),
+            Some((25_000, 10_000))
+        );
+    }
+
+    // cargo test test_chunking_validate -- --no-capture
+    #[test]
+    fn test_chunking_validate() {
+        assert!(ChunkingStrategy::default().validate().is_ok());
+        assert!(
+            ChunkingStrategy::default()
+                .with_chunk_length_ms(1000)
+                .validate()
+                .is_err()
+        );
+        assert!(
+            ChunkingStrategy::default()
+                .with_backward_search_ms(60_000)
+                .validate()
+                .is_err()
+        );
+    }
+}
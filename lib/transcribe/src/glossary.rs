@@ -0,0 +1,530 @@
+use super::whisper::{TranscriptionResult, Word};
+use anyhow::Result;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::{mem, ops::Range};
+
+// A term may differ by one edit per this many chars. Shorter terms are only close to common
+// words, e.g. "React" and "reach" or "Swift" and "shift", so they are never fuzzy matched
+const FUZZY_CHARS_PER_EDIT: usize = 6;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GlossaryReplacement {
+    pub segment_index: i32,
+    pub start_time: u64, // ms, of the segment
+    pub original: String,
+    pub replacement: String,
+    pub similarity: f32, // (0.0-1.0), 1.0 if only the case differs
+}
+
+/// Product names and jargon of a project. They are put into the whisper prompt, and a post-pass
+/// fixes near-miss spellings of them in the transcript
+#[derive(Clone, Debug)]
+pub struct Glossary {
+    pub terms: Vec<String>,
+
+    // A word sequence is replaced by a term if their case-insensitive edit distance similarity
+    // is at least this value, and the edit distance is at most one per 6 chars of the term
+    pub min_similarity: f32, // default 0.8, above 1.0 disables the post-pass
+
+    // Only the case of terms with an inner capital like "OpenAI" is fixed by default. Terms like
+    // "Go" or "Rust" are common words in lowercase
+    pub fix_case: bool, // default false
+}
+
+impl Default for Glossary {
+    fn default() -> Self {
+        Self {
+            terms: vec![],
+            min_similarity: 0.8,
+            fix_case: false,
+        }
+    }
+}
+
+impl Glossary {
+    /// Empty and repeated terms are ignored
+    pub fn new<S: AsRef<str>>(terms: impl IntoIterator<Item = S>) -> Self {
+        let mut glossary = Self::default();
+
+        for term in terms {
+            let term = term
+                .as_ref()
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ");
+            if !term.is_empty() && !glossary.terms.contains(&term) {
+                glossary.terms.push(term);
+            }
+        }
+
+        glossary
+    }
+
+    pub fn with_min_similarity(mut self, min_similarity: f32) -> Self {
+        self.min_similarity = min_similarity;
+        self
+    }
+
+    pub fn with_fix_case(mut self, fix_case: bool) -> Self {
+        self.fix_case = fix_case;
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    /// Prompt text listing as many terms as fit in `max_tokens`, in the order of the glossary.
+    /// None if not even one term fits
+    pub fn prompt(
+        &self,
+        max_tokens: usize,
+        count_tokens: impl Fn(&str) -> Result<usize>,
+    ) -> Result<Option<String>> {
+        let mut prompt = None;
+
+        for n in 1..=self.terms.len() {
+            let text = format!("Glossary: {}.", self.terms[..n].join(", "));
+            if count_tokens(&text)? > max_tokens {
+                break;
+            }
+            prompt = Some(text);
+        }
+
+        Ok(prompt)
+    }
+
+    /// Replace near-miss spellings of the terms in the segments and put the replacements into
+    /// `result.glossary_replacements`
+    pub fn apply(&self, result: &mut TranscriptionResult) {
+        let mut changed = false;
+
+        for segment in result.segments.iter_mut() {
+            let corrections = self.corrections(&segment.text);
+            if corrections.is_empty() {
+                continue;
+            }
+
+            // Words keep their timestamps, the words of a replaced sequence are merged into one
+            // word. Processed backwards, so the text positions of the earlier words stay valid
+            match word_ranges(&segment.text, &segment.words) {
+                Some(ranges) => {
+                    let mut next_first = segment.words.len();
+                    for correction in corrections.iter().rev() {
+                        next_first = replace_words(
+                            &mut segment.words,
+                            &ranges,
+                            &segment.text,
+                            correction,
+                            next_first,
+                        );
+                    }
+                }
+                None => debug!(
+                    "Glossary words at {}ms don't match the segment text",
+                    segment.start_time
+                ),
+            }
+
+            for correction in corrections.iter() {
+                let original = segment.text[correction.range.clone()].to_string();
+                debug!(
+                    "Glossary replacement at {}ms: {original} -> {} ({:.2})",
+                    segment.start_time, correction.term, correction.similarity
+                );
+
+                result.glossary_replacements.push(GlossaryReplacement {
+                    segment_index: segment.index,
+                    start_time: segment.start_time,
+                    original,
+                    replacement: correction.term.clone(),
+                    similarity: correction.similarity,
+                });
+            }
+
+            segment.text = replace_text(&segment.text, &corrections);
+            changed = true;
+        }
+
+        if changed {
+            result.text = result
+                .segments
+                .iter()
+                .map(|s| s.text.as_str())
+                .collect::<Vec<_>>()
+                .join(" ");
+        }
+    }
+
+    /// The corrected text and the replacements as (original, term, similarity). Words are split
+    /// by whitespace with the punctuation around them kept, and a term of n words is compared
+    /// with every n consecutive words
+    pub fn correct_text(&self, text: &str) -> (String, Vec<(String, String, f32)>) {
+        let corrections = self.corrections(text);
+        let replacements = corrections
+            .iter()
+            .map(|c| {
+                (
+                    text[c.range.clone()].to_string(),
+                    c.term.clone(),
+                    c.similarity,
+                )
+            })
+            .collect();
+
+        (replace_text(text, &corrections), replacements)
+    }
+
+    // Near-miss spellings of the terms in `text`, in the order of the text
+    fn corrections(&self, text: &str) -> Vec<Correction> {
+        let words = word_spans(text);
+        let terms = self
+            .terms
+            .iter()
+            .map(|term| {
+                let fix_case = self.fix_case || term.chars().skip(1).any(char::is_uppercase);
+                let max_edits = term.chars().count() / FUZZY_CHARS_PER_EDIT;
+                (
+                    term,
+                    term.split(' ').count(),
+                    term.to_lowercase(),
+                    fix_case,
+                    max_edits,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let mut corrections = vec![];
+        let mut index = 0;
+
+        while index < words.len() {
+            let mut best: Option<(&String, usize, f32)> = None;
+
+            for (term, n_words, lower_term, fix_case, max_edits) in terms.iter() {
+                if index + n_words > words.len() {
+                    continue;
+                }
+
+                let candidate = &text[words[index].0..words[index + n_words - 1].1];
+                if candidate == term.as_str() {
+                    best = None;
+                    break;
+                }
+
+                let (score, edits) = similarity(&candidate.to_lowercase(), lower_term);
+                if score < self.min_similarity || edits > *max_edits || (score == 1.0 && !fix_case)
+                {
+                    continue;
+                }
+
+                if best.is_none_or(|(_, _, s)| score > s) {
+                    best = Some((*term, *n_words, score));
+                }
+            }
+
+            let Some((term, n_words, score)) = best else {
+                index += 1;
+                continue;
+            };
+
+            corrections.push(Correction {
+                range: words[index].0..words[index + n_words - 1].1,
+                term: term.clone(),
+                similarity: score,
+            });
+            index += n_words;
+        }
+
+        corrections
+    }
+}
+
+struct Correction {
+    range: Range<usize>, // bytes of the text
+    term: String,
+    similarity: f32,
+}
+
+fn replace_text(text: &str, corrections: &[Correction]) -> String {
+    let (mut output, mut copied) = (String::new(), 0);
+
+    for correction in corrections.iter() {
+        output.push_str(&text[copied..correction.range.start]);
+        output.push_str(&correction.term);
+        copied = correction.range.end;
+    }
+
+    output.push_str(&text[copied..]);
+    output
+}
+
+// Byte ranges of the words in the segment text, None if the words don't make up the text
+fn word_ranges(text: &str, words: &[Word]) -> Option<Vec<Range<usize>>> {
+    let mut ranges = vec![];
+    let mut offset = 0;
+
+    for word in words.iter() {
+        let start = offset + text[offset..].find(word.text.as_str())?;
+        offset = start + word.text.len();
+        ranges.push(start..offset);
+    }
+
+    Some(ranges)
+}
+
+// Put the term into the first word overlapping the correction and merge the other overlapping
+// words into it. Words at or after `next_first` were already merged by a later correction and
+// are left alone. Returns the index of the first overlapping word
+fn replace_words(
+    words: &mut Vec<Word>,
+    ranges: &[Range<usize>],
+    text: &str,
+    correction: &Correction,
+    next_first: usize,
+) -> usize {
+    let range = &correction.range;
+    let Some(first) = ranges.iter().position(|r| r.end > range.start) else {
+        return next_first;
+    };
+    let Some(last) = ranges.iter().rposition(|r| r.start < range.end) else {
+        return next_first;
+    };
+    if first > last || last >= next_first {
+        return next_first;
+    }
+
+    let (first_range, last_range) = (&ranges[first], &ranges[last]);
+    let prefix = &text[first_range.start..range.start.max(first_range.start)];
+    let suffix = &text[range.end.min(last_range.end)..last_range.end];
+
+    let probability = words[first..=last]
+        .iter()
+        .map(|word| word.probability)
+        .fold(f32::MAX, f32::min);
+
+    words[first].text = format!("{prefix}{}{suffix}", correction.term);
+    words[first].end_time = words[last].end_time;
+    words[first].probability = probability;
+    words.drain(first + 1..=last);
+
+    first
+}
+
+// Byte ranges of the whitespace separated words without the punctuation around them
+fn word_spans(text: &str) -> Vec<(usize, usize)> {
+    let mut spans = vec![];
+    let mut offset = 0;
+
+    for word in text.split_whitespace() {
+        let start = offset + text[offset..].find(word).unwrap_or_default();
+        offset = start + word.len();
+
+        let trimmed = word.trim_start_matches(|c: char| !c.is_alphanumeric());
+        let word_start = start + word.len() - trimmed.len();
+        let trimmed = trimmed.trim_end_matches(|c: char| !c.is_alphanumeric());
+
+        if !trimmed.is_empty() {
+            spans.push((word_start, word_start + trimmed.len()));
+        }
+    }
+
+    spans
+}
+
+// 1.0 minus the Levenshtein distance of the chars divided by the longer length, and the distance
+fn similarity(a: &str, b: &str) -> (f32, usize) {
+    let (a, b) = (a.chars().collect::<Vec<_>>(), b.chars().collect::<Vec<_>>());
+    let max_len = a.len().max(b.len());
+    if max_len == 0 {
+        return (1.0, 0);
+    }
+
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    let mut current = vec![0; b.len() + 1];
+
+    for (i, ca) in a.iter().enumerate() {
+        current[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == cb { 0 } else { 1 };
+            current[j + 1] = (previous[j] + cost)
+                .min(previous[j + 1] + 1)
+                .min(current[j] + 1);
+        }
+        mem::swap(&mut previous, &mut current);
+    }
+
+    let distance = previous[b.len()];
+    (1.0 - distance as f32 / max_len as f32, distance)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{subtitle, whisper::TranscriptionSegment};
+
+    // cargo test test_glossary_correct_text -- --no-capture
+    #[test]
+    fn test_glossary_correct_text() {
+        let glossary = Glossary::new(["Kubernetes", "WhisperCap", "Slint UI", "Go", " ", "Go"]);
+        assert_eq!(
+            glossary.terms,
+            vec!["Kubernetes", "WhisperCap", "Slint UI", "Go"]
+        );
+
+        let (text, replacements) =
+            glossary.correct_text("We deploy kubernetis with whispercap, and a slint ui. So go!");
+        assert_eq!(
+            text,
+            "We deploy Kubernetes with WhisperCap, and a Slint UI. So go!"
+        );
+
+        let replaced = replacements
+            .iter()
+            .map(|(original, term, _)| (original.as_str(), term.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            replaced,
+            vec![
+                ("kubernetis", "Kubernetes"),
+                ("whispercap", "WhisperCap"),
+                ("slint ui", "Slint UI")
+            ]
+        );
+        assert!((replacements[0].2 - 0.9).abs() < 1e-6);
+        assert_eq!(replacements[1].2, 1.0);
+
+        // Short terms are never fuzzy matched
+        let (text, replacements) = glossary.correct_text("Kubernetes is so good");
+        assert_eq!(text, "Kubernetes is so good");
+        assert!(replacements.is_empty());
+
+        // Dictionary words one edit away from a short term are kept
+        let (text, replacements) = Glossary::new(["React", "Swift", "Rust", "Docker"])
+            .correct_text("We reach the night shift, rest and take the ducks to the dock.");
+        assert_eq!(
+            text,
+            "We reach the night shift, rest and take the ducks to the dock."
+        );
+        assert!(replacements.is_empty());
+
+        // A 2 edits near miss of a long term is only replaced if the term has 12 chars or more
+        let (_, replacements) = Glossary::new(["Kubernetes"]).correct_text("kuberntis");
+        assert!(replacements.is_empty());
+        let (text, _) = Glossary::new(["Elasticsearch"]).correct_text("elastisearh");
+        assert_eq!(text, "Elasticsearch");
+
+        // The case of common words is only fixed if asked for
+        let (text, _) = glossary.clone().with_fix_case(true).correct_text("So go!");
+        assert_eq!(text, "So Go!");
+
+        let (_, replacements) = glossary
+            .clone()
+            .with_min_similarity(0.95)
+            .correct_text("kubernetis");
+        assert!(replacements.is_empty());
+    }
+
+    // cargo test test_glossary_apply -- --no-capture
+    #[test]
+    fn test_glossary_apply() {
+        let mut result = TranscriptionResult {
+            text: String::new(),
+            language: Some("en".to_string()),
+            segments: vec![TranscriptionSegment {
+                index: 1,
+                start_time: 1000,
+                end_time: 2000,
+                text: " Hello kubernetis.".to_string(),
+                confidence: 0.8,
+                words: vec![
+                    Word {
+                        text: " Hello".to_string(),
+                        start_time: 1000,
+                        end_time: 1400,
+                        probability: 0.9,
+                    },
+                    Word {
+                        text: " kubernetis.".to_string(),
+                        start_time: 1400,
+                        end_time: 2000,
+                        probability: 0.5,
+                    },
+                ],
+                temperature: 0.0,
+                speaker_turn_next: false,
+                speaker: None,
+                no_speech_prob: 0.0,
+            }],
+            processing_time: 0,
+            audio_duration: 2000,
+            hallucinations: vec![],
+            glossary_replacements: vec![],
+            chunks: vec![],
+            checkpoint: None,
+        };
+
+        Glossary::new(["Kubernetes"]).apply(&mut result);
+
+        assert_eq!(result.text, " Hello Kubernetes.");
+        assert_eq!(result.segments[0].words[1].text, " Kubernetes.");
+        assert_eq!(result.glossary_replacements.len(), 1);
+        assert_eq!(result.glossary_replacements[0].start_time, 1000);
+        assert_eq!(result.glossary_replacements[0].original, "kubernetis");
+
+        // The replacements are carried into the subtitles for review
+        let subtitles = subtitle::transcription_to_subtitle(&result);
+        assert_eq!(
+            subtitles[0].glossary.as_deref(),
+            Some("kubernetis -> Kubernetes")
+        );
+
+        // Words are found by their position, not by their text, and the tokens of a replaced
+        // word are merged into one word
+        let words = [
+            (" openaire", 0, 500),
+            (" is", 500, 700),
+            (" not", 700, 900),
+            (" open", 900, 1200),
+            ("ai.", 1200, 1500),
+        ];
+        result.segments[0].text = words.iter().map(|w| w.0).collect();
+        result.segments[0].words = words
+            .iter()
+            .map(|(text, start_time, end_time)| Word {
+                text: text.to_string(),
+                start_time: *start_time,
+                end_time: *end_time,
+                probability: 0.9,
+            })
+            .collect();
+
+        Glossary::new(["OpenAI"]).apply(&mut result);
+
+        let words = &result.segments[0].words;
+        assert_eq!(result.text, " openaire is not OpenAI.");
+        assert_eq!(words.len(), 4);
+        assert_eq!(words[0].text, " openaire");
+        assert_eq!(words[3].text, " OpenAI.");
+        assert_eq!((words[3].start_time, words[3].end_time), (900, 1500));
+    }
+
+    // cargo test test_glossary_prompt -- --no-capture
+    #[test]
+    fn test_glossary_prompt() -> Result<()> {
+        let glossary = Glossary::new(["Kubernetes", "WhisperCap", "Slint"]);
+        let count_words = |text: &str| -> Result<usize> { Ok(text.split_whitespace().count()) };
+
+        assert_eq!(
+            glossary.prompt(10, count_words)?.as_deref(),
+            Some("Glossary: Kubernetes, WhisperCap, Slint.")
+        );
+        assert_eq!(
+            glossary.prompt(3, count_words)?.as_deref(),
+            Some("Glossary: Kubernetes, WhisperCap.")
+        );
+        assert_eq!(glossary.prompt(1, count_words)?, None);
+
+        Ok(())
+    }
+}
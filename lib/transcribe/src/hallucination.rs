@@ -0,0 +1,370 @@
+use super::whisper::{TranscriptionResult, TranscriptionSegment, normalize_text};
+use flate2::{Compression, write::ZlibEncoder};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt, io::Write};
+
+// Phrases of this key apply to every language
+pub const ALL_LANGUAGES: &str = "*";
+
+// Like the reference decoder, a segment with a high no speech probability is only a
+// hallucination if it is also decoded with low confidence
+const NO_SPEECH_MAX_CONFIDENCE: f32 = 0.4;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum HallucinationAction {
+    // Keep the segments in the result and only report them
+    #[default]
+    Flag,
+
+    // Remove the segments from the result
+    Drop,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum HallucinationReason {
+    // Same text as the previous segment
+    Duplicate,
+
+    // The text compresses too well, e.g. a phrase looping in one segment
+    CompressionRatio(f32),
+
+    // No speech probability of the segment
+    NoSpeech(f32),
+
+    // The matched phrase of the blocklist
+    Blocklist(String),
+}
+
+impl fmt::Display for HallucinationReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Duplicate => write!(f, "duplicate"),
+            Self::CompressionRatio(ratio) => write!(f, "compression ratio {ratio:.1}"),
+            Self::NoSpeech(prob) => write!(f, "no speech {:.0}%", prob * 100.0),
+            Self::Blocklist(phrase) => write!(f, "blocklist: {phrase}"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Hallucination {
+    pub segment: TranscriptionSegment,
+    pub reason: HallucinationReason,
+    pub dropped: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct HallucinationFilter {
+    pub action: HallucinationAction,
+    pub remove_duplicates: bool,          // default true
+    pub compression_ratio_threshold: f32, // default 2.4, 0.0 disables it
+    pub no_speech_threshold: f32,         // default 0.6, above 1.0 disables it
+
+    // Language code -> phrases. A segment is a hallucination if its whole text is one of them,
+    // ignoring case and punctuation
+    pub blocklist: HashMap<String, Vec<String>>,
+}
+
+impl Default for HallucinationFilter {
+    fn default() -> Self {
+        Self {
+            action: HallucinationAction::default(),
+            remove_duplicates: true,
+            compression_ratio_threshold: 2.4,
+            no_speech_threshold: 0.6,
+            blocklist: default_blocklist(),
+        }
+    }
+}
+
+impl HallucinationFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_action(mut self, action: HallucinationAction) -> Self {
+        self.action = action;
+        self
+    }
+
+    pub fn with_remove_duplicates(mut self, remove_duplicates: bool) -> Self {
+        self.remove_duplicates = remove_duplicates;
+        self
+    }
+
+    pub fn with_compression_ratio_threshold(mut self, threshold: f32) -> Self {
+        self.compression_ratio_threshold = threshold;
+        self
+    }
+
+    pub fn with_no_speech_threshold(mut self, threshold: f32) -> Self {
+        self.no_speech_threshold = threshold;
+        self
+    }
+
+    pub fn with_blocklist_phrases<S: Into<String>>(
+        mut self,
+        language: impl Into<String>,
+        phrases: impl IntoIterator<Item = S>,
+    ) -> Self {
+        self.blocklist
+            .entry(language.into())
+            .or_default()
+            .extend(phrases.into_iter().map(|p| p.into()));
+        self
+    }
+
+    /// Find the hallucinated segments of `result` and put them into `result.hallucinations`.
+    /// With `HallucinationAction::Drop` they are removed from the segments and the text
+    pub fn apply(&self, result: &mut TranscriptionResult) {
+        let language = result.language.clone().unwrap_or_default();
+        let drop = self.action == HallucinationAction::Drop;
+
+        let mut kept = Vec::with_capacity(result.segments.len());
+        let mut previous_text = String::new();
+
+        for segment in result.segments.drain(..) {
+            let text = normalize_text(&segment.text);
+
+            let Some(reason) = self.check(&segment, &text, &previous_text, &language) else {
+                previous_text = text;
+                kept.push(segment);
+                continue;
+            };
+
+            debug!(
+                "Hallucination {reason:?} at {}ms: {}",
+                segment.start_time, segment.text
+            );
+
+            if !drop {
+                kept.push(segment.clone());
+            }
+
+            result.hallucinations.push(Hallucination {
+                segment,
+                reason,
+                dropped: drop,
+            });
+        }
+
+        for (index, segment) in kept.iter_mut().enumerate() {
+            segment.index = index as i32 + 1;
+        }
+
+        if drop {
+            result.text = kept
+                .iter()
+                .map(|s| s.text.as_str())
+                .collect::<Vec<_>>()
+                .join(" ");
+        }
+
+        result.segments = kept;
+    }
+
+    fn check(
+        &self,
+        segment: &TranscriptionSegment,
+        text: &str,
+        previous_text: &str,
+        language: &str,
+    ) -> Option<HallucinationReason> {
+        let phrase = [language, ALL_LANGUAGES]
+            .iter()
+            .filter_map(|lang| self.blocklist.get(*lang))
+            .flatten()
+            .find(|phrase| !text.is_empty() && normalize_text(phrase) == text);
+        if let Some(phrase) = phrase {
+            return Some(HallucinationReason::Blocklist(phrase.clone()));
+        }
+
+        if self.remove_duplicates && !text.is_empty() && text == previous_text {
+            return Some(HallucinationReason::Duplicate);
+        }
+
+        if segment.no_speech_prob > self.no_speech_threshold
+            && segment.confidence < NO_SPEECH_MAX_CONFIDENCE
+        {
+            return Some(HallucinationReason::NoSpeech(segment.no_speech_prob));
+        }
+
+        if self.compression_ratio_threshold > 0.0 {
+            let ratio = compression_ratio(&segment.text);
+            if ratio > self.compression_ratio_threshold {
+                return Some(HallucinationReason::CompressionRatio(ratio));
+            }
+        }
+
+        None
+    }
+}
+
+/// Phrases whisper often makes up on music and silence, learned from subtitles of online videos
+pub fn default_blocklist() -> HashMap<String, Vec<String>> {
+    HashMap::from([
+        (
+            "en".to_string(),
+            vec![
+                "Thanks for watching".to_string(),
+                "Thank you for watching".to_string(),
+                "Thank you so much for watching".to_string(),
+                "Please subscribe to my channel".to_string(),
+                "Subtitles by the Amara.org community".to_string(),
+            ],
+        ),
+        (
+            "zh".to_string(),
+            vec![
+                "谢谢观看".to_string(),
+                "感谢观看".to_string(),
+                "请不吝点赞 订阅 转发 打赏支持明镜与点点栏目".to_string(),
+                "字幕由Amara.org社区提供".to_string(),
+                "小编字幕由Amara.org社区提供".to_string(),
+            ],
+        ),
+    ])
+}
+
+/// Length of the UTF-8 text divided by its zlib compressed length, like the whisper reference
+/// decoder. Repetitive text has a high ratio
+pub fn compression_ratio(text: &str) -> f32 {
+    let bytes = text.as_bytes();
+    if bytes.is_empty() {
+        return 0.0;
+    }
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    if encoder.write_all(bytes).is_err() {
+        return 0.0;
+    }
+
+    let compressed = encoder
+        .finish()
+        .map(|data| data.len())
+        .unwrap_or(bytes.len());
+
+    bytes.len() as f32 / compressed.max(1) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subtitle;
+
+    fn segment(index: i32, text: &str, no_speech_prob: f32) -> TranscriptionSegment {
+        TranscriptionSegment {
+            index,
+            start_time: index as u64 * 1000,
+            end_time: index as u64 * 1000 + 1000,
+            text: text.to_string(),
+            confidence: 0.8,
+            words: vec![],
+            temperature: 0.0,
+            speaker_turn_next: false,
+            speaker: None,
+            no_speech_prob,
+        }
+    }
+
+    fn result(segments: Vec<TranscriptionSegment>) -> TranscriptionResult {
+        TranscriptionResult {
+            text: String::new(),
+            language: Some("en".to_string()),
+            segments,
+            processing_time: 0,
+            audio_duration: 10000,
+            hallucinations: vec![],
+            glossary_replacements: vec![],
+            chunks: vec![],
+            checkpoint: None,
+        }
+    }
+
+    // cargo test test_hallucination_filter -- --no-capture
+    #[test]
+    fn test_hallucination_filter() {
+        let looping = "I'm going to go. ".repeat(20);
+        let mut no_speech = segment(5, "Okay.", 0.95);
+        no_speech.confidence = 0.2;
+
+        let mut transcription = result(vec![
+            segment(1, "Hello everyone.", 0.1),
+            segment(2, "Hello, everyone!", 0.1),
+            segment(3, "Today we talk about rust.", 0.1),
+            segment(4, &looping, 0.1),
+            no_speech,
+            segment(6, "Thanks for watching!", 0.1),
+        ]);
+
+        HallucinationFilter::default()
+            .with_action(HallucinationAction::Drop)
+            .apply(&mut transcription);
+
+        let texts = transcription
+            .segments
+            .iter()
+            .map(|s| (s.index, s.text.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            texts,
+            vec![(1, "Hello everyone."), (2, "Today we talk about rust.")]
+        );
+        assert_eq!(
+            transcription.text,
+            "Hello everyone. Today we talk about rust."
+        );
+
+        let reasons = transcription
+            .hallucinations
+            .iter()
+            .map(|h| h.reason.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(reasons[0], HallucinationReason::Duplicate);
+        assert!(matches!(
+            reasons[1],
+            HallucinationReason::CompressionRatio(_)
+        ));
+        assert_eq!(reasons[2], HallucinationReason::NoSpeech(0.95));
+        assert_eq!(
+            reasons[3],
+            HallucinationReason::Blocklist("Thanks for watching".to_string())
+        );
+        assert!(transcription.hallucinations.iter().all(|h| h.dropped));
+    }
+
+    // cargo test test_hallucination_filter_flag -- --no-capture
+    #[test]
+    fn test_hallucination_filter_flag() {
+        let mut transcription = result(vec![
+            segment(1, "Hello.", 0.1),
+            segment(2, "Merci d'avoir regardé", 0.1),
+        ]);
+
+        HallucinationFilter::default()
+            .with_action(HallucinationAction::Flag)
+            .with_blocklist_phrases(ALL_LANGUAGES, ["Merci d'avoir regardé"])
+            .apply(&mut transcription);
+
+        assert_eq!(transcription.segments.len(), 2);
+        assert_eq!(transcription.hallucinations.len(), 1);
+        assert!(!transcription.hallucinations[0].dropped);
+
+        // The flag is carried into the subtitles for review
+        let subtitles = subtitle::transcription_to_subtitle(&transcription);
+        assert_eq!(subtitles[0].hallucination, None);
+        assert_eq!(
+            subtitles[1].hallucination.as_deref(),
+            Some("blocklist: Merci d'avoir regardé")
+        );
+    }
+
+    // cargo test test_compression_ratio -- --no-capture
+    #[test]
+    fn test_compression_ratio() {
+        assert!(compression_ratio("The quick brown fox jumps over the lazy dog.") < 1.5);
+        assert!(compression_ratio(&"la la la ".repeat(30)) > 2.4);
+        assert_eq!(compression_ratio(""), 0.0);
+    }
+}
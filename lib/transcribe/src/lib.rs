@@ -1,3 +1,11 @@
+pub mod audio_cache;
+pub mod checkpoint;
+pub mod chunking;
+pub mod glossary;
+pub mod hallucination;
+pub mod model_cache;
+pub mod peaks;
+pub mod speaker;
 pub mod subtitle;
 pub mod vad;
 pub mod wav;
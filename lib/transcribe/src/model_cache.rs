@@ -0,0 +1,362 @@
+use anyhow::{Context, Result, anyhow};
+use log::debug;
+use std::{
+    fs, mem,
+    path::{Path, PathBuf},
+    sync::{Arc, LazyLock, Mutex},
+    time::SystemTime,
+};
+use whisper_rs::{WhisperContext, WhisperContextParameters};
+
+// Enough for one large model, e.g. ggml-large-v3.bin is about 3GB
+pub const DEFAULT_MEMORY_BUDGET: u64 = 4 * 1024 * 1024 * 1024;
+
+static GLOBAL_MODEL_CACHE: LazyLock<ModelCache> = LazyLock::new(ModelCache::default);
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ModelKey {
+    pub model_path: PathBuf, // canonical path
+    pub use_gpu: bool,
+    pub flash_attn: bool,
+    pub gpu_device: i32,
+
+    // A model file replaced on disk is loaded again
+    pub file_size: u64,
+    pub modified: Option<SystemTime>,
+}
+
+impl ModelKey {
+    pub fn new(
+        model_path: impl AsRef<Path>,
+        ctx_params: &WhisperContextParameters,
+    ) -> Result<Self> {
+        let model_path = fs::canonicalize(model_path.as_ref())
+            .with_context(|| format!("Read model {} failed", model_path.as_ref().display()))?;
+        let metadata = fs::metadata(&model_path)
+            .with_context(|| format!("Read model {} failed", model_path.display()))?;
+
+        Ok(Self {
+            model_path,
+            use_gpu: ctx_params.use_gpu,
+            flash_attn: ctx_params.flash_attn,
+            gpu_device: ctx_params.gpu_device,
+            file_size: metadata.len(),
+            modified: metadata.modified().ok(),
+        })
+    }
+}
+
+/// Loaded whisper models shared by every transcription of the process. The least recently
+/// used models are unloaded when the models take more memory than the budget. A model in use
+/// by a transcriber stays loaded until the transcriber is dropped
+pub struct ModelCache {
+    inner: Mutex<LruEntries<Arc<WhisperContext>>>,
+    loading: LoadingSlots,
+}
+
+impl Default for ModelCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_MEMORY_BUDGET)
+    }
+}
+
+impl ModelCache {
+    pub fn new(memory_budget: u64) -> Self {
+        Self {
+            inner: Mutex::new(LruEntries::new(memory_budget)),
+            loading: LoadingSlots::default(),
+        }
+    }
+
+    pub fn global() -> &'static ModelCache {
+        &GLOBAL_MODEL_CACHE
+    }
+
+    /// The cached model of `model_path` and `ctx_params`, loaded on a miss. Jobs started at the
+    /// same time wait for one load of a model. Other models can be used or loaded meanwhile
+    pub fn get_or_load(
+        &self,
+        model_path: impl AsRef<Path>,
+        ctx_params: WhisperContextParameters,
+    ) -> Result<Arc<WhisperContext>> {
+        let key = ModelKey::new(model_path.as_ref(), &ctx_params)?;
+        if let Some(context) = self.get(&key) {
+            return Ok(context);
+        }
+
+        let slot = self.loading.slot(&key);
+        let result = {
+            let _loading = slot.lock().unwrap();
+
+            // Loaded by the job this one waited for
+            match self.get(&key) {
+                Some(context) => Ok(context),
+                None => self.load(key.clone(), ctx_params),
+            }
+        };
+
+        self.loading.release(&key, &slot);
+        result
+    }
+
+    fn get(&self, key: &ModelKey) -> Option<Arc<WhisperContext>> {
+        let context = self.inner.lock().unwrap().get(key)?;
+        debug!("Whisper model cache hit: {}", key.model_path.display());
+
+        Some(context)
+    }
+
+    // The cache is only locked to insert the loaded model
+    fn load(
+        &self,
+        key: ModelKey,
+        ctx_params: WhisperContextParameters,
+    ) -> Result<Arc<WhisperContext>> {
+        debug!("Load Whisper model: {}", key.model_path.display());
+
+        let context =
+            WhisperContext::new_with_params(key.model_path.to_string_lossy().as_ref(), ctx_params)
+                .map_err(|e| anyhow!("Load Whisper model error: {e}"))?;
+        let context = Arc::new(context);
+
+        // The model file is mapped into memory, so its size is a good estimate of the usage
+        let size = key.file_size;
+        let evicted = self
+            .inner
+            .lock()
+            .unwrap()
+            .insert(key, context.clone(), size);
+        for key in evicted {
+            debug!("Unload Whisper model: {}", key.model_path.display());
+        }
+
+        Ok(context)
+    }
+
+    pub fn memory_budget(&self) -> u64 {
+        self.inner.lock().unwrap().memory_budget
+    }
+
+    /// Models are unloaded at once if they take more memory than `memory_budget`.
+    /// 0 disables caching
+    pub fn set_memory_budget(&self, memory_budget: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.memory_budget = memory_budget;
+        inner.shrink();
+    }
+
+    /// Estimated memory of the cached models in bytes
+    pub fn memory_usage(&self) -> u64 {
+        self.inner.lock().unwrap().usage()
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Unload every cached variant of the model, e.g. before the model file is removed
+    pub fn evict(&self, model_path: impl AsRef<Path>) {
+        let model_path =
+            fs::canonicalize(model_path.as_ref()).unwrap_or(model_path.as_ref().to_path_buf());
+
+        self.inner
+            .lock()
+            .unwrap()
+            .remove_where(|key| key.model_path == model_path);
+    }
+
+    pub fn clear(&self) {
+        self.inner.lock().unwrap().entries.clear();
+    }
+}
+
+// A lock per model being loaded
+#[derive(Default)]
+struct LoadingSlots {
+    slots: Mutex<Vec<(ModelKey, Arc<Mutex<()>>)>>,
+}
+
+impl LoadingSlots {
+    fn slot(&self, key: &ModelKey) -> Arc<Mutex<()>> {
+        let mut slots = self.slots.lock().unwrap();
+        if let Some((_, slot)) = slots.iter().find(|(k, _)| k == key) {
+            return slot.clone();
+        }
+
+        let slot = Arc::new(Mutex::new(()));
+        slots.push((key.clone(), slot.clone()));
+        slot
+    }
+
+    // Jobs still waiting on `slot` check the cache once they get it
+    fn release(&self, key: &ModelKey, slot: &Arc<Mutex<()>>) {
+        self.slots
+            .lock()
+            .unwrap()
+            .retain(|(k, s)| !(k == key && Arc::ptr_eq(s, slot)));
+    }
+}
+
+struct LruEntry<V> {
+    key: ModelKey,
+    value: V,
+    size: u64,
+    last_used: u64,
+}
+
+struct LruEntries<V> {
+    entries: Vec<LruEntry<V>>,
+    memory_budget: u64,
+    tick: u64,
+}
+
+impl<V: Clone> LruEntries<V> {
+    fn new(memory_budget: u64) -> Self {
+        Self {
+            entries: vec![],
+            memory_budget,
+            tick: 0,
+        }
+    }
+
+    fn usage(&self) -> u64 {
+        self.entries.iter().map(|entry| entry.size).sum()
+    }
+
+    fn get(&mut self, key: &ModelKey) -> Option<V> {
+        self.tick += 1;
+
+        let entry = self.entries.iter_mut().find(|entry| &entry.key == key)?;
+        entry.last_used = self.tick;
+        Some(entry.value.clone())
+    }
+
+    // Returns the keys of the evicted entries
+    fn insert(&mut self, key: ModelKey, value: V, size: u64) -> Vec<ModelKey> {
+        self.tick += 1;
+
+        // Older versions of a replaced model file are never used again
+        let mut evicted = self.remove_where(|k| {
+            k.model_path == key.model_path
+                && (k.file_size != key.file_size || k.modified != key.modified)
+        });
+
+        self.entries.retain(|entry| entry.key != key);
+        self.entries.push(LruEntry {
+            key,
+            value,
+            size,
+            last_used: self.tick,
+        });
+
+        evicted.extend(self.shrink());
+        evicted
+    }
+
+    // Evict the least recently used entries until the usage is within the budget
+    fn shrink(&mut self) -> Vec<ModelKey> {
+        let mut evicted = vec![];
+
+        while !self.entries.is_empty() && self.usage() > self.memory_budget {
+            let (index, _) = self
+                .entries
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .unwrap();
+
+            evicted.push(self.entries.remove(index).key);
+        }
+
+        evicted
+    }
+
+    fn remove_where(&mut self, f: impl Fn(&ModelKey) -> bool) -> Vec<ModelKey> {
+        let (removed, kept) = mem::take(&mut self.entries)
+            .into_iter()
+            .partition::<Vec<_>, _>(|entry| f(&entry.key));
+
+        self.entries = kept;
+        removed.into_iter().map(|entry| entry.key).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(model_path: &str, use_gpu: bool, file_size: u64) -> ModelKey {
+        ModelKey {
+            model_path: PathBuf::from(model_path),
+            use_gpu,
+            flash_attn: false,
+            gpu_device: 0,
+            file_size,
+            modified: None,
+        }
+    }
+
+    // cargo test test_lru_entries -- --no-capture
+    #[test]
+    fn test_lru_entries() {
+        let mut entries = LruEntries::new(250);
+
+        assert!(
+            entries
+                .insert(key("base.bin", true, 100), 1, 100)
+                .is_empty()
+        );
+        assert!(
+            entries
+                .insert(key("small.bin", true, 100), 2, 100)
+                .is_empty()
+        );
+        assert_eq!(entries.get(&key("base.bin", true, 100)), Some(1));
+        assert_eq!(entries.get(&key("base.bin", false, 100)), None);
+
+        // small.bin is the least recently used one
+        let evicted = entries.insert(key("medium.bin", true, 100), 3, 100);
+        assert_eq!(evicted, vec![key("small.bin", true, 100)]);
+        assert_eq!(entries.usage(), 200);
+
+        // The replaced model file is never used again
+        let evicted = entries.insert(key("base.bin", false, 120), 4, 120);
+        assert_eq!(evicted, vec![key("base.bin", true, 100)]);
+        assert_eq!(entries.get(&key("base.bin", false, 120)), Some(4));
+
+        // A model larger than the budget is not kept
+        let evicted = entries.insert(key("large.bin", true, 300), 5, 300);
+        assert_eq!(evicted.len(), 3);
+        assert!(entries.entries.is_empty());
+
+        entries.memory_budget = 1000;
+        entries.insert(key("base.bin", true, 100), 1, 100);
+        entries.insert(key("small.bin", true, 100), 2, 100);
+        entries.memory_budget = 100;
+        assert_eq!(entries.shrink(), vec![key("base.bin", true, 100)]);
+
+        let removed = entries.remove_where(|k| k.model_path == Path::new("small.bin"));
+        assert_eq!(removed.len(), 1);
+        assert_eq!(entries.usage(), 0);
+    }
+
+    // cargo test test_loading_slots -- --no-capture
+    #[test]
+    fn test_loading_slots() {
+        let slots = LoadingSlots::default();
+        let (base, small) = (key("base.bin", true, 100), key("small.bin", true, 100));
+
+        // Jobs loading the same model share a slot, other models don't wait for it
+        let slot = slots.slot(&base);
+        let _loading = slot.lock().unwrap();
+        assert!(Arc::ptr_eq(&slot, &slots.slot(&base)));
+        assert!(slots.slot(&small).try_lock().is_ok());
+
+        slots.release(&base, &slot);
+        assert!(!Arc::ptr_eq(&slot, &slots.slot(&base)));
+    }
+}
@@ -0,0 +1,399 @@
+use super::wav::{AudioData, WavHeader};
+use anyhow::{Context, Result, bail};
+use log::debug;
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+const MAGIC: &[u8; 4] = b"WCPK";
+const VERSION: u32 = 1;
+
+// Frames per bucket of the finest level, 4ms at 16kHz
+const BASE_FRAMES_PER_BUCKET: u32 = 64;
+
+// Every level has this many times fewer buckets than the previous one
+const LEVEL_FACTOR: u32 = 4;
+const MAX_LEVELS: usize = 8;
+
+// The wav file is read in windows of this length, so a long recording isn't loaded at once
+const READ_WINDOW_MS: u64 = 60_000;
+
+/// Min and max sample of a bucket of frames, mono
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Peak {
+    pub min: i16,
+    pub max: i16,
+}
+
+impl Peak {
+    fn merge(self, other: Peak) -> Peak {
+        Peak {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    /// The larger magnitude of min and max in (0.0-1.0)
+    pub fn magnitude(&self) -> f32 {
+        (self.min as f32).abs().max(self.max as f32) / i16::MAX as f32
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PeakLevel {
+    pub frames_per_bucket: u32,
+    pub peaks: Vec<Peak>,
+}
+
+/// Min/max peaks of audio at several zoom levels, like the `.dat` files of audiowaveform.
+/// A waveform of any range is drawn from the level with about as many buckets as it shows
+#[derive(Clone, Debug, PartialEq)]
+pub struct WaveformPeaks {
+    pub sample_rate: u32,
+    pub frame_count: u64,
+    pub levels: Vec<PeakLevel>, // from the finest to the coarsest
+}
+
+/// The peak file stored next to the audio file, e.g. `{id}.peaks` of `{id}.wav`
+pub fn peaks_path(audio_path: impl AsRef<Path>) -> PathBuf {
+    audio_path.as_ref().with_extension("peaks")
+}
+
+impl WaveformPeaks {
+    pub fn from_audio(audio_data: &AudioData) -> Self {
+        let mut builder = PeakBuilder::new(audio_data.config.sample_rate);
+        builder.push(audio_data);
+        builder.finish()
+    }
+
+    /// Peaks of a wav file, read window by window
+    pub fn generate(audio_path: impl AsRef<Path>) -> Result<Self> {
+        let audio_path = audio_path.as_ref();
+        let (header, _) = WavHeader::open(audio_path)?;
+        let duration_ms = header.duration_ms();
+
+        let mut builder = PeakBuilder::new(header.sample_rate);
+        for start_ms in (0..=duration_ms).step_by(READ_WINDOW_MS as usize) {
+            let audio_data =
+                AudioData::read_range(audio_path, start_ms, start_ms + READ_WINDOW_MS)?;
+            builder.push(&audio_data);
+        }
+
+        let peaks = builder.finish();
+        debug!(
+            "Generated waveform peaks of {}: {} frames, {} levels",
+            audio_path.display(),
+            peaks.frame_count,
+            peaks.levels.len()
+        );
+
+        Ok(peaks)
+    }
+
+    pub fn duration_ms(&self) -> u64 {
+        self.frame_count * 1000 / self.sample_rate.max(1) as u64
+    }
+
+    /// At most `max_buckets` peaks between `start_ms` and `end_ms`. They are merged from the
+    /// coarsest level that still has `max_buckets` buckets in the range, so it takes time
+    /// proportional to `max_buckets` rather than the length of the range
+    pub fn range(&self, start_ms: u64, end_ms: u64, max_buckets: usize) -> Vec<Peak> {
+        let start_frame = (start_ms * self.sample_rate as u64 / 1000).min(self.frame_count);
+        let end_frame =
+            (end_ms * self.sample_rate as u64 / 1000).clamp(start_frame, self.frame_count);
+        if start_frame == end_frame || max_buckets == 0 {
+            return vec![];
+        }
+
+        let bucket_range = |level: &PeakLevel| {
+            let frames = level.frames_per_bucket as u64;
+            let start = (start_frame / frames) as usize;
+            let end = (end_frame.div_ceil(frames) as usize).min(level.peaks.len());
+            start.min(end)..end
+        };
+
+        let Some(level) = self
+            .levels
+            .iter()
+            .rev()
+            .find(|level| bucket_range(level).len() >= max_buckets)
+            .or(self.levels.first())
+        else {
+            return vec![];
+        };
+
+        let peaks = &level.peaks[bucket_range(level)];
+        let group = peaks.len().div_ceil(max_buckets).max(1);
+
+        peaks
+            .chunks(group)
+            .map(|chunk| {
+                chunk
+                    .iter()
+                    .copied()
+                    .reduce(Peak::merge)
+                    .unwrap_or_default()
+            })
+            .collect()
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let file =
+            File::create(path).with_context(|| format!("create {} failed", path.display()))?;
+        let mut writer = BufWriter::new(file);
+
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&self.sample_rate.to_le_bytes())?;
+        writer.write_all(&self.frame_count.to_le_bytes())?;
+        writer.write_all(&(self.levels.len() as u32).to_le_bytes())?;
+
+        for level in self.levels.iter() {
+            writer.write_all(&level.frames_per_bucket.to_le_bytes())?;
+            writer.write_all(&(level.peaks.len() as u64).to_le_bytes())?;
+        }
+
+        for level in self.levels.iter() {
+            for peak in level.peaks.iter() {
+                writer.write_all(&peak.min.to_le_bytes())?;
+                writer.write_all(&peak.max.to_le_bytes())?;
+            }
+        }
+
+        writer
+            .flush()
+            .with_context(|| format!("save {} failed", path.display()))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path).with_context(|| format!("open {} failed", path.display()))?;
+        let file_size = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            bail!("{} is not a peak file", path.display());
+        }
+
+        let version = read_u32(&mut reader)?;
+        if version != VERSION {
+            bail!("Unsupported peak file version: {version}");
+        }
+
+        let sample_rate = read_u32(&mut reader)?;
+        let frame_count = read_u64(&mut reader)?;
+        let level_count = read_u32(&mut reader)? as usize;
+        if level_count > MAX_LEVELS {
+            bail!("Invalid peak file {}: {level_count} levels", path.display());
+        }
+
+        let mut headers = vec![];
+        for _ in 0..level_count {
+            let (frames_per_bucket, count) = (read_u32(&mut reader)?, read_u64(&mut reader)?);
+            if frames_per_bucket == 0 {
+                bail!("Invalid peak file {}: empty bucket", path.display());
+            }
+            headers.push((frames_per_bucket, count));
+        }
+
+        // Refuse a truncated file before allocating the peaks
+        let peak_count = headers
+            .iter()
+            .fold(0u64, |sum, (_, count)| sum.saturating_add(*count));
+        if peak_count.saturating_mul(4) > file_size {
+            bail!("Invalid peak file {}: truncated", path.display());
+        }
+
+        let mut levels = vec![];
+        for (frames_per_bucket, count) in headers {
+            let mut peaks = Vec::with_capacity(count as usize);
+            for _ in 0..count {
+                let mut bytes = [0; 4];
+                reader.read_exact(&mut bytes)?;
+                peaks.push(Peak {
+                    min: i16::from_le_bytes([bytes[0], bytes[1]]),
+                    max: i16::from_le_bytes([bytes[2], bytes[3]]),
+                });
+            }
+
+            levels.push(PeakLevel {
+                frames_per_bucket,
+                peaks,
+            });
+        }
+
+        Ok(Self {
+            sample_rate,
+            frame_count,
+            levels,
+        })
+    }
+}
+
+/// Generate the peak file of a wav file next to it
+pub fn generate_peaks_file(audio_path: impl AsRef<Path>) -> Result<PathBuf> {
+    let path = peaks_path(&audio_path);
+    let tmp_path = path.with_extension("peaks.tmp");
+
+    WaveformPeaks::generate(&audio_path)?.save(&tmp_path)?;
+    fs::rename(&tmp_path, &path).with_context(|| format!("save {} failed", path.display()))?;
+
+    Ok(path)
+}
+
+// Builds the finest level from frames pushed window by window, the other levels are merged
+// from it when it's finished
+struct PeakBuilder {
+    sample_rate: u32,
+    frame_count: u64,
+    peaks: Vec<Peak>,
+    current: Option<Peak>,
+    current_frames: u32,
+}
+
+impl PeakBuilder {
+    fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            frame_count: 0,
+            peaks: vec![],
+            current: None,
+            current_frames: 0,
+        }
+    }
+
+    fn push(&mut self, audio_data: &AudioData) {
+        let channels = audio_data.config.channels.max(1) as usize;
+
+        for frame in audio_data.samples.chunks(channels) {
+            let sample = frame.iter().sum::<f32>() / frame.len() as f32;
+            let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            let peak = Peak {
+                min: sample,
+                max: sample,
+            };
+
+            self.current = Some(self.current.map_or(peak, |current| current.merge(peak)));
+            self.current_frames += 1;
+            self.frame_count += 1;
+
+            if self.current_frames == BASE_FRAMES_PER_BUCKET {
+                self.peaks.extend(self.current.take());
+                self.current_frames = 0;
+            }
+        }
+    }
+
+    fn finish(mut self) -> WaveformPeaks {
+        self.peaks.extend(self.current.take());
+
+        let mut levels = vec![PeakLevel {
+            frames_per_bucket: BASE_FRAMES_PER_BUCKET,
+            peaks: self.peaks,
+        }];
+
+        while levels.len() < MAX_LEVELS {
+            let last = &levels[levels.len() - 1];
+            if last.peaks.len() <= 1 {
+                break;
+            }
+
+            let peaks = last
+                .peaks
+                .chunks(LEVEL_FACTOR as usize)
+                .map(|chunk| {
+                    chunk
+                        .iter()
+                        .copied()
+                        .reduce(Peak::merge)
+                        .unwrap_or_default()
+                })
+                .collect();
+
+            levels.push(PeakLevel {
+                frames_per_bucket: last.frames_per_bucket * LEVEL_FACTOR,
+                peaks,
+            });
+        }
+
+        WaveformPeaks {
+            sample_rate: self.sample_rate,
+            frame_count: self.frame_count,
+            levels,
+        }
+    }
+}
+
+fn read_u32(reader: &mut impl Read) -> Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wav::{self, AudioConfig};
+
+    // cargo test test_waveform_peaks -- --no-capture
+    #[test]
+    fn test_waveform_peaks() {
+        // 1s of 16kHz audio, louder in the second half
+        let samples = (0..16000)
+            .map(|i| {
+                let v = (i as f32 * 0.1).sin();
+                if i < 8000 { v * 0.25 } else { v * 0.75 }
+            })
+            .collect::<Vec<_>>();
+        let peaks = WaveformPeaks::from_audio(&AudioData::new(samples, AudioConfig::default()));
+
+        assert_eq!(peaks.frame_count, 16000);
+        assert_eq!(peaks.duration_ms(), 1000);
+        assert_eq!(peaks.levels[0].peaks.len(), 250);
+        assert_eq!(peaks.levels[1].peaks.len(), 63);
+        assert_eq!(peaks.levels[1].frames_per_bucket, 256);
+        assert_eq!(peaks.levels.last().unwrap().peaks.len(), 1);
+
+        // 16 buckets of the third level merged in pairs
+        let range = peaks.range(0, 1000, 10);
+        assert_eq!(range.len(), 8);
+        assert!((range[0].magnitude() - 0.25).abs() < 0.01);
+        assert!((range[7].magnitude() - 0.75).abs() < 0.01);
+
+        // A short range uses the finest level
+        assert_eq!(peaks.range(500, 520, 100).len(), 5);
+        assert!(peaks.range(2000, 3000, 10).is_empty());
+    }
+
+    // cargo test test_waveform_peaks_file -- --no-capture
+    #[test]
+    fn test_waveform_peaks_file() -> Result<()> {
+        let audio_path = "./examples/data/test-20.wav";
+        let peaks = WaveformPeaks::generate(audio_path)?;
+        assert_eq!(
+            peaks,
+            WaveformPeaks::from_audio(&wav::read_file(audio_path)?)
+        );
+
+        let path = std::env::temp_dir().join("transcribe-test-waveform.peaks");
+        peaks.save(&path)?;
+        assert_eq!(WaveformPeaks::load(&path)?, peaks);
+
+        fs::write(&path, b"WCPK")?;
+        assert!(WaveformPeaks::load(&path).is_err());
+
+        _ = fs::remove_file(&path);
+        Ok(())
+    }
+}
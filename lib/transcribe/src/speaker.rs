@@ -0,0 +1,196 @@
+use super::vad::EnergyVAD;
+use super::wav::AudioData;
+use super::whisper::TranscriptionSegment;
+use anyhow::{Result, bail};
+use log::debug;
+use std::path::Path;
+
+// A channel is the speaker when its RMS is this many times the RMS of the other channel
+const CHANNEL_DOMINANCE_RATIO: f32 = 1.5;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum SpeakerDetection {
+    #[default]
+    None,
+
+    // Speaker turn tokens of tinydiarize models, e.g. ggml-small.en-tdrz.bin.
+    // Each turn switches between two speakers
+    TinyDiarize,
+
+    // One speaker per channel of stereo audio, e.g. an interview recorded with two microphones
+    StereoChannel,
+
+    // TinyDiarize for tdrz models, otherwise StereoChannel when the audio has two channels
+    Auto,
+}
+
+pub fn speaker_label(index: usize) -> String {
+    format!("Speaker {}", index + 1)
+}
+
+/// Tinydiarize models are published with `tdrz` in the file name
+pub fn is_tdrz_model(model_path: impl AsRef<Path>) -> bool {
+    model_path
+        .as_ref()
+        .file_name()
+        .is_some_and(|name| name.to_string_lossy().to_lowercase().contains("tdrz"))
+}
+
+/// Label segments from tinydiarize speaker turns. The first segment is `Speaker 1` and the
+/// speaker alternates after every segment followed by a turn
+pub fn assign_turn_speakers(segments: &mut [TranscriptionSegment]) {
+    let mut speaker = 0;
+
+    for segment in segments.iter_mut() {
+        segment.speaker = Some(speaker_label(speaker));
+
+        if segment.speaker_turn_next {
+            speaker = 1 - speaker;
+        }
+    }
+}
+
+/// Label segments with the louder channel of stereo audio, left is `Speaker 1` and right is
+/// `Speaker 2`. A segment without a clearly louder channel keeps the previous speaker
+pub fn assign_stereo_speakers(
+    audio_data: &AudioData,
+    segments: &mut [TranscriptionSegment],
+) -> Result<()> {
+    if audio_data.config.channels != 2 {
+        bail!(
+            "Stereo speaker detection needs 2 channels, actual {}",
+            audio_data.config.channels
+        );
+    }
+
+    let mut previous = 0;
+
+    for segment in segments.iter_mut() {
+        let (left, right) = channel_rms(audio_data, segment.start_time, segment.end_time);
+
+        let speaker = if left > right * CHANNEL_DOMINANCE_RATIO {
+            0
+        } else if right > left * CHANNEL_DOMINANCE_RATIO {
+            1
+        } else {
+            previous
+        };
+
+        debug!(
+            "Segment {} channel rms: left={left:.4}, right={right:.4}, speaker={}",
+            segment.index,
+            speaker + 1
+        );
+
+        segment.speaker = Some(speaker_label(speaker));
+        previous = speaker;
+    }
+
+    Ok(())
+}
+
+// RMS of the left and right channel between `start_ms` and `end_ms`
+fn channel_rms(audio_data: &AudioData, start_ms: u64, end_ms: u64) -> (f32, f32) {
+    let sample_rate = audio_data.config.sample_rate as u64;
+    let frame_count = audio_data.frame_count();
+
+    let start_frame = ((start_ms * sample_rate / 1000) as usize).min(frame_count);
+    let end_frame = ((end_ms * sample_rate / 1000) as usize).min(frame_count);
+    if start_frame >= end_frame {
+        return (0.0, 0.0);
+    }
+
+    let frames = &audio_data.samples[start_frame * 2..end_frame * 2];
+    let left = frames.iter().step_by(2).copied().collect::<Vec<_>>();
+    let right = frames.iter().skip(1).step_by(2).copied().collect::<Vec<_>>();
+
+    (EnergyVAD::calculate_rms(&left), EnergyVAD::calculate_rms(&right))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wav::AudioConfig;
+
+    fn segment(index: i32, start_time: u64, end_time: u64, turn: bool) -> TranscriptionSegment {
+        TranscriptionSegment {
+            index,
+            start_time,
+            end_time,
+            text: format!("segment {index}"),
+            confidence: 0.8,
+            words: vec![],
+            temperature: 0.0,
+            speaker_turn_next: turn,
+            speaker: None,
+            no_speech_prob: 0.0,
+        }
+    }
+
+    // cargo test test_assign_turn_speakers -- --no-capture
+    #[test]
+    fn test_assign_turn_speakers() {
+        let mut segments = vec![
+            segment(1, 0, 1000, false),
+            segment(2, 1000, 2000, true),
+            segment(3, 2000, 3000, false),
+            segment(4, 3000, 4000, true),
+            segment(5, 4000, 5000, false),
+        ];
+
+        assign_turn_speakers(&mut segments);
+
+        let speakers = segments
+            .iter()
+            .map(|s| s.speaker.clone().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            speakers,
+            vec!["Speaker 1", "Speaker 1", "Speaker 2", "Speaker 2", "Speaker 1"]
+        );
+    }
+
+    // cargo test test_assign_stereo_speakers -- --no-capture
+    #[test]
+    fn test_assign_stereo_speakers() -> Result<()> {
+        // 1s left only, 1s right only, 1s both channels
+        let mut samples = vec![];
+        for i in 0..48000 {
+            let v = (i as f32 * 0.1).sin() * 0.5;
+            let (left, right) = match i / 16000 {
+                0 => (v, 0.01 * v),
+                1 => (0.01 * v, v),
+                _ => (v, v),
+            };
+            samples.push(left);
+            samples.push(right);
+        }
+
+        let audio_data = AudioData::new(samples, AudioConfig::new(16000, 2, 16));
+        let mut segments = vec![
+            segment(1, 0, 1000, false),
+            segment(2, 1000, 2000, false),
+            segment(3, 2000, 3000, false),
+        ];
+
+        assign_stereo_speakers(&audio_data, &mut segments)?;
+
+        assert_eq!(segments[0].speaker.as_deref(), Some("Speaker 1"));
+        assert_eq!(segments[1].speaker.as_deref(), Some("Speaker 2"));
+
+        // Both channels are equally loud, so the previous speaker continues
+        assert_eq!(segments[2].speaker.as_deref(), Some("Speaker 2"));
+
+        let mono = audio_data.to_mono();
+        assert!(assign_stereo_speakers(&mono, &mut segments).is_err());
+
+        Ok(())
+    }
+
+    // cargo test test_is_tdrz_model -- --no-capture
+    #[test]
+    fn test_is_tdrz_model() {
+        assert!(is_tdrz_model("models/ggml-small.en-tdrz.bin"));
+        assert!(!is_tdrz_model("models/ggml-base.bin"));
+    }
+}
@@ -1,4 +1,4 @@
-use super::whisper::{TranscriptionResult, TranscriptionSegment};
+use super::whisper::{TranscriptionResult, TranscriptionSegment, Word};
 use anyhow::{Context, Result};
 use chrono::{NaiveTime, Timelike};
 use std::{fs, path::Path};
@@ -11,6 +11,11 @@ pub struct Subtitle {
     pub start_timestamp: u64,
     pub end_timestamp: u64,
     pub text: String,
+    pub speaker: Option<String>,
+    pub confidence: Option<f32>, // None is unknown, e.g. edited by hand
+    pub hallucination: Option<String>, // reason of a flagged hallucination, None if not flagged
+    pub glossary: Option<String>, // replaced glossary terms, e.g. `kubernetis -> Kubernetes`
+    pub words: Vec<Word>,
 }
 
 impl From<SegmentCallbackData> for Subtitle {
@@ -20,6 +25,11 @@ impl From<SegmentCallbackData> for Subtitle {
             start_timestamp: (segment.start_timestamp as u64) * 10,
             end_timestamp: (segment.end_timestamp as u64) * 10,
             text: segment.text,
+            speaker: None,
+            confidence: None,
+            hallucination: None,
+            glossary: None,
+            words: vec![],
         }
     }
 }
@@ -31,15 +41,42 @@ impl From<&TranscriptionSegment> for Subtitle {
             start_timestamp: segment.start_time,
             end_timestamp: segment.end_time,
             text: segment.text.clone(),
+            speaker: segment.speaker.clone(),
+            confidence: Some(segment.confidence),
+            hallucination: None,
+            glossary: None,
+            words: segment.words.clone(),
         }
     }
 }
 
+/// Subtitles of the segments. Segments flagged by the hallucination filter are marked with the
+/// reason, they are found by their times because the glossary may have changed their text.
+/// Segments fixed by the glossary are marked with their replacements
 pub fn transcription_to_subtitle(transcription: &TranscriptionResult) -> Vec<Subtitle> {
     let mut item = vec![];
 
     for segment in transcription.segments.iter() {
-        item.push(segment.into());
+        let mut subtitle: Subtitle = segment.into();
+        subtitle.hallucination = transcription
+            .hallucinations
+            .iter()
+            .find(|h| {
+                !h.dropped
+                    && h.segment.start_time == segment.start_time
+                    && h.segment.end_time == segment.end_time
+            })
+            .map(|h| h.reason.to_string());
+
+        let replacements = transcription
+            .glossary_replacements
+            .iter()
+            .filter(|r| r.start_time == segment.start_time)
+            .map(|r| format!("{} -> {}", r.original, r.replacement))
+            .collect::<Vec<_>>();
+        subtitle.glossary = (!replacements.is_empty()).then(|| replacements.join(", "));
+
+        item.push(subtitle);
     }
 
     item
@@ -91,9 +128,15 @@ pub fn subtitle_to_srt(subtitle: &Subtitle) -> String {
     )
 }
 
+// The speaker is written as a WebVTT voice tag, e.g. `<v Speaker 1>Hello`
 pub fn subtitle_to_vtt(subtitle: &Subtitle) -> String {
+    let voice = match &subtitle.speaker {
+        Some(speaker) if !speaker.is_empty() => format!("<v {speaker}>"),
+        _ => String::default(),
+    };
+
     format!(
-        "{}\n{} --> {}\n{}",
+        "{}\n{} --> {}\n{voice}{}",
         subtitle.index,
         ms_to_vtt_timestamp(subtitle.start_timestamp),
         ms_to_vtt_timestamp(subtitle.end_timestamp),
@@ -1,6 +1,10 @@
 use super::ProgressStatus;
-use crate::wav;
-use anyhow::{Result, bail};
+use crate::{
+    audio_cache::AudioCache,
+    wav::AudioData,
+    whisper::{self, VadParams},
+};
+use anyhow::Result;
 use std::{
     path::Path,
     sync::{
@@ -167,45 +171,29 @@ pub fn trim_slient_duration_of_audio(
     cancel: Arc<AtomicBool>,
     mut progress_cb: impl FnMut(i32) + 'static,
 ) -> Result<(Vec<(u64, u64)>, ProgressStatus)> {
-    let audio_data = wav::read_file(&audio_path)?;
-
-    let audio_samples = if !audio_data.is_whisper_compatible() {
-        if audio_data.config.sample_rate != 16000 {
-            bail!(
-                "Not compatible with whisper. Actual sample rate {}, expect 16kHz",
-                audio_data.config.sample_rate
-            );
-        }
-
-        if audio_data.config.channels > 1 {
-            audio_data.to_mono().samples
-        } else {
-            audio_data.samples.clone()
-        }
-    } else {
-        audio_data.samples.clone()
-    };
-
     let mut output_timestamps = vec![];
-    let sample_rate = audio_data.config.sample_rate;
-    let total_indexs = audio_samples.len();
 
     for (index, (start_ms, end_ms)) in timestamps.iter().enumerate() {
         if cancel.load(Ordering::Relaxed) {
             return Ok((vec![], ProgressStatus::Cancelled));
         }
 
-        let start_index = ((sample_rate as u64 * start_ms) as f64 / 1000.0) as usize;
-        let end_index =
-            (((sample_rate as u64 * end_ms) as f64 / 1000.0) as usize).min(total_indexs);
+        // Only the audio of the segment is read, not the whole file
+        let audio_data = AudioData::read_range(&audio_path, *start_ms, *end_ms)?;
+        let audio_data = if !audio_data.is_whisper_compatible() {
+            audio_data.to_whisper_compatible()?
+        } else {
+            audio_data
+        };
+
+        let sample_rate = audio_data.config.sample_rate;
+        let segment = &audio_data.samples;
 
-        if start_index >= end_index {
+        if segment.is_empty() {
             output_timestamps.push((*start_ms, *end_ms));
             continue;
         }
 
-        let segment = &audio_samples[start_index..end_index];
-
         let vad = EnergyVAD::new(sample_rate)
             .with_threshold(EnergyVAD::calculate_rms(segment) * adaptive_threshold_factor);
 
@@ -253,26 +241,68 @@ pub fn trim_slient_duration_of_audio(
     Ok((output_timestamps, ProgressStatus::Finished))
 }
 
+/// Like `trim_slient_duration_of_audio`, but the silence is found by the bundled silero model.
+/// The audio of all the timestamps is read and detected at once
+pub fn trim_silent_duration_with_silero(
+    audio_path: impl AsRef<Path>,
+    timestamps: &[(u64, u64)], // (ms, ms)
+    vad_params: &VadParams,
+    cancel: Arc<AtomicBool>,
+    mut progress_cb: impl FnMut(i32) + 'static,
+) -> Result<(Vec<(u64, u64)>, ProgressStatus)> {
+    let (Some(start_ms), Some(end_ms)) = (
+        timestamps.iter().map(|(start, _)| *start).min(),
+        timestamps.iter().map(|(_, end)| *end).max(),
+    ) else {
+        return Ok((vec![], ProgressStatus::Finished));
+    };
+
+    let audio_data = AudioData::read_range(&audio_path, start_ms, end_ms)?;
+    let speech = whisper::silero_speech_segments(&audio_data, vad_params)?
+        .into_iter()
+        .map(|(start, end)| (start + start_ms, end + start_ms))
+        .collect::<Vec<_>>();
+
+    if cancel.load(Ordering::Relaxed) {
+        return Ok((vec![], ProgressStatus::Cancelled));
+    }
+
+    progress_cb(100);
+    Ok((
+        trim_to_speech(timestamps, &speech),
+        ProgressStatus::Finished,
+    ))
+}
+
+// Shrink each timestamp to the speech inside it. A timestamp without speech is kept
+fn trim_to_speech(timestamps: &[(u64, u64)], speech: &[(u64, u64)]) -> Vec<(u64, u64)> {
+    timestamps
+        .iter()
+        .map(|&(start_ms, end_ms)| {
+            let mut inside = speech
+                .iter()
+                .filter(|(start, end)| *start < end_ms && *end > start_ms);
+
+            match (inside.next(), inside.last()) {
+                (Some(first), last) => (first.0.max(start_ms), last.unwrap_or(first).1.min(end_ms)),
+                (None, _) => (start_ms, end_ms),
+            }
+        })
+        .collect()
+}
+
 pub fn estimate_rms_for_duration(
     wav_path: impl AsRef<std::path::Path>,
     duration_seconds: u32,
 ) -> Result<f32> {
-    let audio_data = wav::read_file(wav_path)?;
+    let audio_data = AudioData::read_range(wav_path, 0, duration_seconds as u64 * 1000)?;
     let samples = if audio_data.config.channels > 1 {
         audio_data.to_mono().samples
     } else {
         audio_data.samples
     };
 
-    let sample_rate = audio_data.config.sample_rate;
-    let max_samples = duration_seconds as usize * sample_rate as usize;
-    let samples_to_process = if samples.len() > max_samples {
-        &samples[..max_samples]
-    } else {
-        &samples
-    };
-
-    Ok(EnergyVAD::calculate_rms(samples_to_process))
+    Ok(EnergyVAD::calculate_rms(&samples))
 }
 
 pub fn get_audio_samples(
@@ -280,34 +310,25 @@ pub fn get_audio_samples(
     timestamps: &[(u64, u64)], // (ms, ms)
     max_samples: u64,
 ) -> Result<Vec<Vec<f32>>> {
-    let audio_data = wav::read_file(&audio_path)?;
-
-    let audio_samples = if !audio_data.is_whisper_compatible() {
-        if audio_data.config.channels > 1 {
-            audio_data.to_mono().samples
-        } else {
-            audio_data.samples.clone()
-        }
-    } else {
-        audio_data.samples.clone()
-    };
-
-    let sample_rate = audio_data.config.sample_rate;
-    let total_indices = audio_samples.len();
     let mut result = Vec::new();
 
+    // Ranges are read from the cache, so drawing a waveform again doesn't read the file
     for (start_ms, end_ms) in timestamps.iter() {
-        let start_index = ((sample_rate as u64 * start_ms) as f64 / 1000.0) as usize;
-        let end_index =
-            (((sample_rate as u64 * end_ms) as f64 / 1000.0) as usize).min(total_indices);
+        let audio_data = AudioCache::global().read_range(&audio_path, *start_ms, *end_ms)?;
 
-        if start_index >= end_index {
+        let mono_audio;
+        let segment = if audio_data.config.channels > 1 {
+            mono_audio = audio_data.to_mono();
+            &mono_audio.samples
+        } else {
+            &audio_data.samples
+        };
+
+        if segment.is_empty() {
             result.push(Vec::new());
             continue;
         }
 
-        let segment = &audio_samples[start_index..end_index];
-
         let samples = if segment.len() <= max_samples as usize {
             segment.to_vec()
         } else {
@@ -328,6 +349,7 @@ pub fn get_audio_samples(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::wav;
 
     // cargo test test_vad_detect -- --no-capture
     #[test]
@@ -389,6 +411,18 @@ mod tests {
         Ok(())
     }
 
+    // cargo test test_trim_to_speech -- --no-capture
+    #[test]
+    fn test_trim_to_speech() {
+        let timestamps = [(0, 3000), (3000, 5000), (5000, 7000), (7000, 9000)];
+        let speech = [(500, 2000), (2500, 3500), (4000, 4600), (8000, 9500)];
+
+        assert_eq!(
+            trim_to_speech(&timestamps, &speech),
+            vec![(500, 3000), (3000, 4600), (5000, 7000), (8000, 9000)]
+        );
+    }
+
     // cargo test test_trailing_silent_detection -- --no-capture
     #[test]
     fn test_trailing_silent_detection() -> Result<()> {
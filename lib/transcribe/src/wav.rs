@@ -1,8 +1,42 @@
-use anyhow::{anyhow, bail, Result};
-use hound::{SampleFormat, WavReader};
+use anyhow::{Context, Result, bail};
 use log::debug;
 use serde::{Deserialize, Serialize};
-use std::path::Path;
+use std::{
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+pub const WHISPER_SAMPLE_RATE: u32 = 16000;
+
+// Zero crossings of the sinc on each side of the resampling filter
+const RESAMPLE_HALF_TAPS: usize = 16;
+
+// Passband of the resampling filter, as a fraction of the lower Nyquist frequency
+const RESAMPLE_CUTOFF: f64 = 0.9;
+
+// Kaiser window beta of the resampling filter, about 80dB stopband attenuation
+const RESAMPLE_KAISER_BETA: f64 = 8.0;
+
+// Fractional positions are rounded to one of this many filter phases if the rates have no
+// small common ratio
+const RESAMPLE_MAX_PHASES: usize = 4096;
+
+const WAVE_FORMAT_PCM: u16 = 0x0001;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 0x0003;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+// Frames decoded from each read of a wav file
+const READ_BUFFER_FRAMES: usize = 4096;
+
+// Frame length of the noise reduction STFT, 64ms at 16kHz. Frames overlap by 3/4
+const DENOISE_FRAME_LEN: usize = 1024;
+const DENOISE_HOP_LEN: usize = DENOISE_FRAME_LEN / 4;
+
+// The noise floor of a frequency is this percentile of its magnitudes in at most
+// `DENOISE_PROFILE_FRAMES` frames spread over the audio
+const DENOISE_NOISE_PERCENTILE: f32 = 0.3;
+const DENOISE_PROFILE_FRAMES: usize = 2000;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AudioConfig {
@@ -34,11 +68,22 @@ impl AudioConfig {
         Self::default()
     }
 
+    // The samples are decoded to f32, so any bit depth is fine
     pub fn is_whisper_compatible(&self) -> bool {
-        self.sample_rate == 16000 && self.channels == 1 && self.bit_depth == 16
+        self.sample_rate == WHISPER_SAMPLE_RATE && self.channels == 1
     }
 }
 
+/// The channel of multi-channel audio to transcribe
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AudioChannel {
+    #[default]
+    Mix, // average of every channel
+    Left,
+    Right,
+    Index(u16), // 0-based
+}
+
 #[derive(Debug, Clone)]
 pub struct AudioData {
     pub samples: Vec<f32>, // range: (-1.0 , 1.0）
@@ -50,6 +95,25 @@ impl AudioData {
         Self { samples, config }
     }
 
+    /// The audio between `start_ms` and `end_ms` of a wav file, clamped to its duration.
+    /// Only the frames of the range are read, so it takes time proportional to the range
+    pub fn read_range(path: impl AsRef<Path>, start_ms: u64, end_ms: u64) -> Result<AudioData> {
+        let (header, mut reader) = WavHeader::open(path)?;
+
+        let sample_rate = header.sample_rate as u64;
+        let frame_count = header.frame_count();
+
+        let start_frame = (start_ms * sample_rate / 1000).min(frame_count);
+        let end_frame = (end_ms * sample_rate / 1000).clamp(start_frame, frame_count);
+
+        reader.seek(SeekFrom::Start(
+            header.data_offset + start_frame * header.block_align(),
+        ))?;
+        let samples = header.read_frames(&mut reader, end_frame - start_frame)?;
+
+        Ok(AudioData::new(samples, header.audio_config()))
+    }
+
     pub fn duration(&self) -> f64 {
         let frames = self.samples.len() / self.config.channels as usize;
         frames as f64 / self.config.sample_rate as f64
@@ -59,6 +123,21 @@ impl AudioData {
         self.samples.len() / self.config.channels as usize
     }
 
+    /// The audio between `start_ms` and `end_ms`, clamped to the duration
+    pub fn slice(&self, start_ms: u64, end_ms: u64) -> AudioData {
+        let channels = self.config.channels.max(1) as usize;
+        let sample_rate = self.config.sample_rate as u64;
+        let frame_count = self.frame_count();
+
+        let start_frame = ((start_ms * sample_rate / 1000) as usize).min(frame_count);
+        let end_frame = ((end_ms * sample_rate / 1000) as usize).clamp(start_frame, frame_count);
+
+        AudioData::new(
+            self.samples[start_frame * channels..end_frame * channels].to_vec(),
+            self.config.clone(),
+        )
+    }
+
     pub fn to_mono(&self) -> AudioData {
         if self.config.channels == 1 {
             return self.clone();
@@ -86,6 +165,41 @@ impl AudioData {
         AudioData::new(mono_samples, mono_config)
     }
 
+    /// A mono audio of one channel, or of the mix of every channel
+    pub fn select_channel(&self, channel: AudioChannel) -> Result<AudioData> {
+        let channels = self.config.channels as usize;
+        let index = match channel {
+            AudioChannel::Mix => return Ok(self.to_mono()),
+            AudioChannel::Left => 0,
+            AudioChannel::Right => 1,
+            AudioChannel::Index(index) => index as usize,
+        };
+
+        if index >= channels {
+            bail!("No channel {channel:?} in audio with {channels} channels");
+        }
+
+        if channels == 1 {
+            return Ok(self.clone());
+        }
+
+        let samples = self
+            .samples
+            .iter()
+            .skip(index)
+            .step_by(channels)
+            .copied()
+            .collect();
+
+        Ok(AudioData::new(
+            samples,
+            AudioConfig {
+                channels: 1,
+                ..self.config
+            },
+        ))
+    }
+
     pub fn normalize(&mut self) {
         if self.samples.is_empty() {
             return;
@@ -115,72 +229,1442 @@ impl AudioData {
     pub fn is_whisper_compatible(&self) -> bool {
         self.config.is_whisper_compatible()
     }
-}
 
-pub fn read_file<P: AsRef<Path>>(path: P) -> Result<AudioData> {
-    let path = path.as_ref();
-    if !path.exists() {
-        bail!("file not found {}", path.display());
+    /// Resample every channel to `target_rate` with a Kaiser windowed-sinc filter. When
+    /// downsampling, frequencies above the new Nyquist frequency are removed so they don't alias
+    pub fn resample(&self, target_rate: u32) -> Result<AudioData> {
+        let source_rate = self.config.sample_rate;
+        if source_rate == 0 || target_rate == 0 {
+            bail!("Invalid sample rate: {source_rate} -> {target_rate}");
+        }
+
+        let config = AudioConfig {
+            sample_rate: target_rate,
+            ..self.config
+        };
+
+        if source_rate == target_rate || self.samples.is_empty() {
+            return Ok(AudioData::new(self.samples.clone(), config));
+        }
+
+        let filter = ResampleFilter::new(source_rate, target_rate);
+        let channels = self.config.channels.max(1) as usize;
+        let frame_count = self.frame_count();
+        let output_frames =
+            (frame_count as u64 * target_rate as u64).div_ceil(source_rate as u64) as usize;
+
+        let mut samples = vec![0.0; output_frames * channels];
+        for frame in 0..output_frames {
+            let (position, taps) = filter.taps(frame as u64);
+
+            for channel in 0..channels {
+                let mut sum = 0.0;
+                for (i, tap) in taps.iter().enumerate() {
+                    let index = position + i as i64 - filter.half_len as i64;
+                    if index >= 0 && (index as usize) < frame_count {
+                        sum += tap * self.samples[index as usize * channels + channel];
+                    }
+                }
+                samples[frame * channels + channel] = sum;
+            }
+        }
+
+        debug!("Resampled audio: {source_rate}Hz -> {target_rate}Hz");
+
+        Ok(AudioData::new(samples, config))
     }
 
-    let mut reader = WavReader::open(path).map_err(|e| anyhow!("open wav file failed: {e}"))?;
+    /// Mono 16kHz audio for whisper
+    pub fn to_whisper_compatible(&self) -> Result<AudioData> {
+        let audio_data = self.to_mono().resample(WHISPER_SAMPLE_RATE)?;
+        Ok(AudioData::new(
+            audio_data.samples,
+            AudioConfig::whisper_optimized(),
+        ))
+    }
+}
 
-    let spec = reader.spec();
-    let config = AudioConfig {
-        sample_rate: spec.sample_rate,
-        channels: spec.channels,
-        bit_depth: spec.bits_per_sample,
-    };
+// Polyphase windowed-sinc filter. Output frame `n` is at input position `n * step / phases`,
+// and every fractional position has its own precomputed taps
+struct ResampleFilter {
+    phases: u64,
+    step: u64,
+    rounded: Option<usize>, // number of phases used if `phases` is too many to precompute
+    half_len: usize,
+    table: Vec<Vec<f32>>,
+}
 
-    let samples: Vec<f32> = match spec.sample_format {
-        SampleFormat::Float => reader
-            .samples::<f32>()
-            .collect::<Result<Vec<_>, _>>()
-            .map_err(|e| anyhow!("Read float point sample failed: {e}"))?,
-
-        SampleFormat::Int => {
-            let int_samples: Result<Vec<i32>, _> = reader.samples::<i32>().collect();
-            match int_samples {
-                Ok(samples) => {
-                    // Convert floating point according to bit depth
-                    let max_value = match spec.bits_per_sample {
-                        16 => i16::MAX as f32,
-                        24 => 8388607.0, // 2^23 - 1
-                        32 => i32::MAX as f32,
-                        _ => bail!("Unsupported bits per sample: {}", spec.bits_per_sample),
-                    };
-                    samples.into_iter().map(|x| x as f32 / max_value).collect()
+impl ResampleFilter {
+    fn new(source_rate: u32, target_rate: u32) -> Self {
+        let divisor = gcd(source_rate as u64, target_rate as u64);
+        let (phases, step) = (target_rate as u64 / divisor, source_rate as u64 / divisor);
+
+        let rounded = (phases as usize > RESAMPLE_MAX_PHASES).then_some(RESAMPLE_MAX_PHASES);
+        let table_phases = rounded.unwrap_or(phases as usize);
+
+        // The cutoff is relative to the input Nyquist frequency, the sinc gets wider when
+        // downsampling to remove the frequencies the output can't represent
+        let cutoff = RESAMPLE_CUTOFF * (target_rate as f64 / source_rate as f64).min(1.0);
+        let half_width = RESAMPLE_HALF_TAPS as f64 / cutoff;
+        let half_len = half_width.ceil() as usize;
+
+        let table = (0..table_phases)
+            .map(|phase| {
+                let fraction = phase as f64 / table_phases as f64;
+                let mut taps = (0..=2 * half_len)
+                    .map(|i| {
+                        let distance = i as f64 - half_len as f64 - fraction;
+                        windowed_sinc(distance, cutoff, half_width)
+                    })
+                    .collect::<Vec<_>>();
+
+                // Exact unity gain for DC
+                let sum = taps.iter().sum::<f64>();
+                if sum.abs() > f64::EPSILON {
+                    taps.iter_mut().for_each(|tap| *tap /= sum);
+                }
+
+                taps.into_iter().map(|tap| tap as f32).collect()
+            })
+            .collect();
+
+        Self {
+            phases,
+            step,
+            rounded,
+            half_len,
+            table,
+        }
+    }
+
+    // Input position and taps of the output frame
+    fn taps(&self, frame: u64) -> (i64, &[f32]) {
+        let position = frame * self.step;
+        let (mut index, remainder) = (position / self.phases, position % self.phases);
+
+        let phase = match self.rounded {
+            None => remainder as usize,
+            Some(count) => {
+                let phase = (remainder as f64 / self.phases as f64 * count as f64).round() as usize;
+                if phase == count {
+                    index += 1;
+                    0
+                } else {
+                    phase
                 }
-                Err(e) => bail!("Read file sample failed: {e}"),
             }
+        };
+
+        (index as i64, &self.table[phase])
+    }
+}
+
+fn windowed_sinc(distance: f64, cutoff: f64, half_width: f64) -> f64 {
+    if distance.abs() >= half_width {
+        return 0.0;
+    }
+
+    let x = std::f64::consts::PI * cutoff * distance;
+    let sinc = if x.abs() < 1e-9 { 1.0 } else { x.sin() / x };
+
+    let ratio = distance / half_width;
+    let window = bessel_i0(RESAMPLE_KAISER_BETA * (1.0 - ratio * ratio).sqrt())
+        / bessel_i0(RESAMPLE_KAISER_BETA);
+
+    cutoff * sinc * window
+}
+
+// Zeroth order modified Bessel function of the first kind
+fn bessel_i0(x: f64) -> f64 {
+    let (mut sum, mut term) = (1.0, 1.0);
+    let quarter_x2 = x * x / 4.0;
+
+    for k in 1..50 {
+        term *= quarter_x2 / (k * k) as f64;
+        sum += term;
+        if term < sum * 1e-12 {
+            break;
         }
-    };
+    }
+
+    sum
+}
 
-    Ok(AudioData::new(samples, config))
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
 }
 
+pub fn read_file<P: AsRef<Path>>(path: P) -> Result<AudioData> {
+    let (header, mut reader) = WavHeader::open(path)?;
+
+    reader.seek(SeekFrom::Start(header.data_offset))?;
+    let samples = header.read_frames(&mut reader, header.frame_count())?;
+
+    Ok(AudioData::new(samples, header.audio_config()))
+}
+
+/// A 16kHz mono wav file of any supported sample format is transcribed without converting it
 pub fn is_whisper_compatible(path: impl AsRef<Path>) -> Result<()> {
-    let reader = WavReader::open(path.as_ref())
-        .map_err(|e| anyhow!("Failed to open {}. Error: {e}", path.as_ref().display()))?;
-    let spec = reader.spec();
+    let (header, _) = WavHeader::open(path)?;
 
-    if spec.sample_rate != 16000 {
+    if header.sample_rate != WHISPER_SAMPLE_RATE {
         bail!(
             "Sample rate mismatch. Expected: 16000, actual: {}",
-            spec.sample_rate
+            header.sample_rate
         );
     }
 
-    if spec.channels != 1 {
-        bail!("Channel mismatch. Expected: 1, actual: {}", spec.channels);
+    if header.channels != 1 {
+        bail!("Channel mismatch. Expected: 1, actual: {}", header.channels);
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WavSampleFormat {
+    Int, // unsigned if 8-bit, signed otherwise
+    Float,
+}
+
+/// The format and the position of the samples of a RIFF, RF64 or BW64 wav file. PCM, IEEE
+/// float and `WAVE_FORMAT_EXTENSIBLE` formats are supported
+#[derive(Debug, Clone, PartialEq)]
+pub struct WavHeader {
+    pub sample_format: WavSampleFormat,
+    pub sample_rate: u32,
+    pub channels: u16,
+
+    // Bytes of a sample in the file, the valid bits are the upper `bits_per_sample` bits
+    pub container_bytes: u16,
+    pub bits_per_sample: u16,
+
+    pub data_offset: u64,
+    pub data_len: u64,
+}
+
+impl WavHeader {
+    pub fn open(path: impl AsRef<Path>) -> Result<(Self, BufReader<File>)> {
+        let path = path.as_ref();
+        if !path.exists() {
+            bail!("file not found {}", path.display());
+        }
+
+        let file =
+            File::open(path).with_context(|| format!("open wav file {} failed", path.display()))?;
+        let mut reader = BufReader::new(file);
+        let header = Self::read(&mut reader)
+            .with_context(|| format!("read wav header of {} failed", path.display()))?;
+
+        Ok((header, reader))
     }
 
-    if spec.bits_per_sample != 16 {
+    /// Parse the chunks up to the `data` chunk. Unknown chunks are skipped
+    pub fn read(reader: &mut (impl Read + Seek)) -> Result<Self> {
+        let stream_len = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(0))?;
+
+        let riff_id = read_id(reader)?;
+        let _riff_size = read_u32(reader)?;
+        if read_id(reader)? != *b"WAVE" {
+            bail!("not a WAVE file");
+        }
+
+        // The real sizes of RF64 and BW64 files larger than 4GB are in the `ds64` chunk
+        let is_rf64 = match &riff_id {
+            b"RIFF" => false,
+            b"RF64" | b"BW64" => true,
+            id => bail!("unsupported container: {}", String::from_utf8_lossy(id)),
+        };
+
+        let mut ds64_data_len = None;
+        let mut format = None;
+
+        loop {
+            let chunk_id = read_id(reader).context("no data chunk")?;
+            let chunk_size = read_u32(reader)? as u64;
+            let chunk_start = reader.stream_position()?;
+
+            match &chunk_id {
+                b"ds64" if is_rf64 => {
+                    let _riff_size = read_u64(reader)?;
+                    ds64_data_len = Some(read_u64(reader)?);
+                }
+                b"fmt " => format = Some(read_format(reader, chunk_size)?),
+                b"data" => {
+                    let Some(format) = format else {
+                        bail!("no fmt chunk before the data chunk");
+                    };
+
+                    let data_len = match ds64_data_len {
+                        Some(len) if chunk_size == u32::MAX as u64 => len,
+                        _ => chunk_size,
+                    };
+
+                    // Recorders that are stopped abruptly leave a wrong size
+                    let data_len = data_len.min(stream_len.saturating_sub(chunk_start));
+
+                    return Ok(Self {
+                        data_offset: chunk_start,
+                        data_len,
+                        ..format
+                    });
+                }
+                _ => (),
+            }
+
+            // Chunks are padded to an even size
+            let next = chunk_start + chunk_size + (chunk_size & 1);
+            if next > stream_len {
+                bail!("no data chunk");
+            }
+            reader.seek(SeekFrom::Start(next))?;
+        }
+    }
+
+    pub fn block_align(&self) -> u64 {
+        self.container_bytes as u64 * self.channels as u64
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        self.data_len / self.block_align()
+    }
+
+    pub fn duration_ms(&self) -> u64 {
+        self.frame_count() * 1000 / self.sample_rate as u64
+    }
+
+    pub fn audio_config(&self) -> AudioConfig {
+        AudioConfig::new(self.sample_rate, self.channels, self.bits_per_sample)
+    }
+
+    /// Decode `frames` frames from the current position, scaled to (-1.0, 1.0)
+    pub fn read_frames(&self, reader: &mut impl Read, frames: u64) -> Result<Vec<f32>> {
+        let container_bytes = self.container_bytes as usize;
+        let block_align = self.block_align() as usize;
+
+        let mut samples = Vec::with_capacity(frames as usize * self.channels as usize);
+        let mut buffer = vec![0; READ_BUFFER_FRAMES * block_align];
+        let mut remaining = frames as usize;
+
+        while remaining > 0 {
+            let count = remaining.min(READ_BUFFER_FRAMES);
+            let buffer = &mut buffer[..count * block_align];
+            reader
+                .read_exact(buffer)
+                .context("read wav samples failed")?;
+
+            samples.extend(
+                buffer
+                    .chunks_exact(container_bytes)
+                    .map(|bytes| self.decode_sample(bytes)),
+            );
+            remaining -= count;
+        }
+
+        Ok(samples)
+    }
+
+    fn decode_sample(&self, bytes: &[u8]) -> f32 {
+        match (self.sample_format, bytes.len()) {
+            (WavSampleFormat::Float, 4) => f32::from_le_bytes(bytes.try_into().unwrap()),
+            (WavSampleFormat::Float, _) => f64::from_le_bytes(bytes.try_into().unwrap()) as f32,
+            (WavSampleFormat::Int, 1) => (bytes[0] as f32 - 128.0) / 128.0,
+            (WavSampleFormat::Int, 2) => {
+                i16::from_le_bytes(bytes.try_into().unwrap()) as f32 / i16::MAX as f32
+            }
+            (WavSampleFormat::Int, 3) => {
+                let value = i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 8;
+                value as f32 / 8388607.0 // 2^23 - 1
+            }
+            (WavSampleFormat::Int, _) => {
+                i32::from_le_bytes(bytes.try_into().unwrap()) as f32 / i32::MAX as f32
+            }
+        }
+    }
+}
+
+// The header without the position of the samples
+fn read_format(reader: &mut impl Read, chunk_size: u64) -> Result<WavHeader> {
+    if chunk_size < 16 {
+        bail!("fmt chunk too short: {chunk_size} bytes");
+    }
+
+    let mut format_tag = read_u16(reader)?;
+    let channels = read_u16(reader)?;
+    let sample_rate = read_u32(reader)?;
+    let _byte_rate = read_u32(reader)?;
+    let block_align = read_u16(reader)?;
+    let bits_per_sample = read_u16(reader)?;
+    let mut valid_bits = bits_per_sample;
+
+    // The format is the first 2 bytes of the sub format GUID, and the valid bits may be fewer
+    // than the container, e.g. 20 bits in a 24-bit container
+    if format_tag == WAVE_FORMAT_EXTENSIBLE {
+        if chunk_size < 40 || read_u16(reader)? < 22 {
+            bail!("extensible fmt chunk too short: {chunk_size} bytes");
+        }
+
+        let valid = read_u16(reader)?;
+        let _channel_mask = read_u32(reader)?;
+        format_tag = read_u16(reader)?;
+        if valid != 0 {
+            valid_bits = valid;
+        }
+    }
+
+    if channels == 0 || sample_rate == 0 {
+        bail!("invalid format: {channels} channels, {sample_rate}Hz");
+    }
+
+    if block_align == 0 || !block_align.is_multiple_of(channels) {
+        bail!("invalid block align: {block_align} with {channels} channels");
+    }
+
+    let container_bytes = block_align / channels;
+    let sample_format = match (format_tag, container_bytes) {
+        (WAVE_FORMAT_PCM, 1..=4) => WavSampleFormat::Int,
+        (WAVE_FORMAT_IEEE_FLOAT, 4 | 8) => WavSampleFormat::Float,
+        _ => bail!(
+            "unsupported format: tag {format_tag:#06x}, {} bits per sample",
+            container_bytes * 8
+        ),
+    };
+
+    if valid_bits == 0 || valid_bits > container_bytes * 8 {
         bail!(
-            "Format not supported. Expected 16 bit PCM, actual {} bit PCM",
-            spec.bits_per_sample
+            "invalid bits per sample: {valid_bits} in a {} bits container",
+            container_bytes * 8
         );
     }
 
-    Ok(())
+    Ok(WavHeader {
+        sample_format,
+        sample_rate,
+        channels,
+        container_bytes,
+        bits_per_sample: valid_bits,
+        data_offset: 0,
+        data_len: 0,
+    })
+}
+
+fn read_id(reader: &mut impl Read) -> Result<[u8; 4]> {
+    let mut buf = [0; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn read_u16(reader: &mut impl Read) -> Result<u16> {
+    let mut buf = [0; 2];
+    reader.read_exact(&mut buf)?;
+    Ok(u16::from_le_bytes(buf))
+}
+
+fn read_u32(reader: &mut impl Read) -> Result<u32> {
+    let mut buf = [0; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64(reader: &mut impl Read) -> Result<u64> {
+    let mut buf = [0; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+/// Clean up of noisy recordings before inference. The stages run in the order of the fields,
+/// and a stage is skipped if it is None
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AudioPreprocess {
+    pub high_pass: Option<HighPass>,
+    pub noise_reduction: Option<NoiseReduction>,
+    pub compressor: Option<Compressor>,
+    pub normalization: Option<Normalization>,
+}
+
+impl AudioPreprocess {
+    pub fn with_high_pass(mut self, high_pass: HighPass) -> Self {
+        self.high_pass = Some(high_pass);
+        self
+    }
+
+    pub fn with_noise_reduction(mut self, noise_reduction: NoiseReduction) -> Self {
+        self.noise_reduction = Some(noise_reduction);
+        self
+    }
+
+    pub fn with_compressor(mut self, compressor: Compressor) -> Self {
+        self.compressor = Some(compressor);
+        self
+    }
+
+    pub fn with_normalization(mut self, normalization: Normalization) -> Self {
+        self.normalization = Some(normalization);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.high_pass.is_none()
+            && self.noise_reduction.is_none()
+            && self.compressor.is_none()
+            && self.normalization.is_none()
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if let Some(high_pass) = &self.high_pass {
+            high_pass.validate()?;
+        }
+
+        if let Some(noise_reduction) = &self.noise_reduction {
+            noise_reduction.validate()?;
+        }
+
+        if let Some(compressor) = &self.compressor {
+            compressor.validate()?;
+        }
+
+        if let Some(normalization) = &self.normalization {
+            normalization.validate()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn apply(&self, audio_data: &mut AudioData) -> Result<()> {
+        self.validate()?;
+
+        if let Some(high_pass) = &self.high_pass {
+            high_pass.apply(audio_data)?;
+        }
+
+        if let Some(noise_reduction) = &self.noise_reduction {
+            noise_reduction.apply(audio_data);
+        }
+
+        if let Some(compressor) = &self.compressor {
+            compressor.apply(audio_data);
+        }
+
+        if let Some(normalization) = &self.normalization {
+            normalization.apply(audio_data);
+        }
+
+        Ok(())
+    }
+}
+
+/// Second order Butterworth high-pass filter. It removes rumble, handling noise and mains hum
+/// below the voice
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct HighPass {
+    pub cutoff_hz: f32, // default 80
+}
+
+impl Default for HighPass {
+    fn default() -> Self {
+        Self { cutoff_hz: 80.0 }
+    }
+}
+
+impl HighPass {
+    pub fn new(cutoff_hz: f32) -> Self {
+        Self { cutoff_hz }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.cutoff_hz.is_nan() || self.cutoff_hz <= 0.0 {
+            bail!("high-pass cutoff should be greater than 0Hz");
+        }
+
+        Ok(())
+    }
+
+    fn apply(&self, audio_data: &mut AudioData) -> Result<()> {
+        let sample_rate = audio_data.config.sample_rate as f64;
+        let cutoff = self.cutoff_hz as f64;
+        if cutoff >= sample_rate / 2.0 {
+            bail!(
+                "high-pass cutoff {cutoff}Hz should be below the Nyquist frequency {}Hz",
+                sample_rate / 2.0
+            );
+        }
+
+        // Biquad of the audio EQ cookbook with Q = 1 / sqrt(2)
+        let w0 = 2.0 * std::f64::consts::PI * cutoff / sample_rate;
+        let (cos, alpha) = (w0.cos(), w0.sin() / 2.0f64.sqrt());
+        let a0 = 1.0 + alpha;
+        let b0 = (1.0 + cos) / 2.0 / a0;
+        let (b1, b2) = (-2.0 * b0, b0);
+        let (a1, a2) = (-2.0 * cos / a0, (1.0 - alpha) / a0);
+
+        for_each_channel(audio_data, |samples| {
+            let (mut x1, mut x2, mut y1, mut y2) = (0.0, 0.0, 0.0, 0.0);
+            for sample in samples.iter_mut() {
+                let x0 = *sample as f64;
+                let y0 = b0 * x0 + b1 * x1 + b2 * x2 - a1 * y1 - a2 * y2;
+                (x1, x2, y1, y2) = (x0, x1, y0, y1);
+                *sample = y0 as f32;
+            }
+        });
+
+        debug!("High-pass filtered audio: {cutoff}Hz");
+        Ok(())
+    }
+}
+
+/// Spectral gating. The frequencies of a frame that are close to their noise floor are turned
+/// down, and the ones well above it are kept. The noise floor is estimated from the audio
+/// itself, so it works best on steady noise like fans, hiss or traffic
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct NoiseReduction {
+    pub threshold_db: f32, // kept if this much above the noise floor, default 10
+    pub reduction_db: f32, // attenuation of the frequencies below the threshold, default 18
+}
+
+impl Default for NoiseReduction {
+    fn default() -> Self {
+        Self {
+            threshold_db: 10.0,
+            reduction_db: 18.0,
+        }
+    }
+}
+
+impl NoiseReduction {
+    pub fn validate(&self) -> Result<()> {
+        if self.threshold_db.is_nan() || self.threshold_db < 0.0 {
+            bail!("noise reduction threshold should not be less than 0dB");
+        }
+
+        if self.reduction_db.is_nan() || self.reduction_db < 0.0 {
+            bail!("noise reduction should not be less than 0dB");
+        }
+
+        Ok(())
+    }
+
+    fn apply(&self, audio_data: &mut AudioData) {
+        let threshold = db_to_gain(self.threshold_db);
+        let reduction = db_to_gain(-self.reduction_db);
+        let fft = Fft::new(DENOISE_FRAME_LEN);
+        let window = (0..DENOISE_FRAME_LEN)
+            .map(|i| {
+                let x = 2.0 * std::f32::consts::PI * i as f32 / DENOISE_FRAME_LEN as f32;
+                0.5 - 0.5 * x.cos()
+            })
+            .collect::<Vec<_>>();
+
+        for_each_channel(audio_data, |samples| {
+            // Too short to tell noise from the voice
+            if samples.len() < DENOISE_FRAME_LEN * 2 {
+                return;
+            }
+
+            let noise_floor = noise_floor(samples, &fft, &window);
+
+            // Every sample is in 4 frames after padding both sides
+            let pad = DENOISE_FRAME_LEN - DENOISE_HOP_LEN;
+            let mut padded = vec![0.0; pad + samples.len() + DENOISE_FRAME_LEN];
+            padded[pad..pad + samples.len()].copy_from_slice(samples);
+
+            let mut output = vec![0.0; padded.len()];
+            let mut window_sum = vec![0.0; padded.len()];
+            let mut gains = vec![1.0; DENOISE_FRAME_LEN / 2 + 1];
+            let (mut re, mut im) = (vec![0.0; DENOISE_FRAME_LEN], vec![0.0; DENOISE_FRAME_LEN]);
+
+            for start in (0..=padded.len() - DENOISE_FRAME_LEN).step_by(DENOISE_HOP_LEN) {
+                for i in 0..DENOISE_FRAME_LEN {
+                    (re[i], im[i]) = (padded[start + i] * window[i], 0.0);
+                }
+                fft.transform(&mut re, &mut im, false);
+
+                // Gains are smoothed over frames, so single noisy frequencies don't pop up as
+                // musical tones
+                for (k, gain) in gains.iter_mut().enumerate() {
+                    let magnitude = (re[k] * re[k] + im[k] * im[k]).sqrt();
+                    let target = if magnitude > noise_floor[k] * threshold {
+                        1.0
+                    } else {
+                        reduction
+                    };
+                    *gain = 0.5 * (*gain + target);
+
+                    re[k] *= *gain;
+                    im[k] *= *gain;
+                    if k > 0 && k < DENOISE_FRAME_LEN / 2 {
+                        re[DENOISE_FRAME_LEN - k] *= *gain;
+                        im[DENOISE_FRAME_LEN - k] *= *gain;
+                    }
+                }
+
+                fft.transform(&mut re, &mut im, true);
+                for i in 0..DENOISE_FRAME_LEN {
+                    output[start + i] += re[i] / DENOISE_FRAME_LEN as f32 * window[i];
+                    window_sum[start + i] += window[i] * window[i];
+                }
+            }
+
+            for (i, sample) in samples.iter_mut().enumerate() {
+                *sample = output[pad + i] / window_sum[pad + i].max(f32::EPSILON);
+            }
+        });
+
+        debug!(
+            "Reduced noise: threshold {}dB, reduction {}dB",
+            self.threshold_db, self.reduction_db
+        );
+    }
+}
+
+// Magnitude of every frequency that `DENOISE_NOISE_PERCENTILE` of the frames are below
+fn noise_floor(samples: &[f32], fft: &Fft, window: &[f32]) -> Vec<f32> {
+    let frame_count = (samples.len() - DENOISE_FRAME_LEN) / DENOISE_HOP_LEN + 1;
+    let step = frame_count.div_ceil(DENOISE_PROFILE_FRAMES);
+
+    let mut magnitudes = vec![vec![]; DENOISE_FRAME_LEN / 2 + 1];
+    let (mut re, mut im) = (vec![0.0; DENOISE_FRAME_LEN], vec![0.0; DENOISE_FRAME_LEN]);
+
+    for frame in (0..frame_count).step_by(step) {
+        let start = frame * DENOISE_HOP_LEN;
+        for i in 0..DENOISE_FRAME_LEN {
+            (re[i], im[i]) = (samples[start + i] * window[i], 0.0);
+        }
+        fft.transform(&mut re, &mut im, false);
+
+        for (k, items) in magnitudes.iter_mut().enumerate() {
+            items.push((re[k] * re[k] + im[k] * im[k]).sqrt());
+        }
+    }
+
+    magnitudes
+        .into_iter()
+        .map(|mut items| {
+            let index = ((items.len() - 1) as f32 * DENOISE_NOISE_PERCENTILE) as usize;
+            *items.select_nth_unstable_by(index, f32::total_cmp).1
+        })
+        .collect()
+}
+
+/// Feed-forward compressor on the peak level of all channels. Loud passages are turned down,
+/// so quiet and loud speakers are closer after normalization
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Compressor {
+    pub threshold_db: f32, // default -24
+    pub ratio: f32,        // default 4, 4dB above the threshold is turned into 1dB
+    pub attack_ms: f32,    // default 5
+    pub release_ms: f32,   // default 150
+}
+
+impl Default for Compressor {
+    fn default() -> Self {
+        Self {
+            threshold_db: -24.0,
+            ratio: 4.0,
+            attack_ms: 5.0,
+            release_ms: 150.0,
+        }
+    }
+}
+
+impl Compressor {
+    pub fn validate(&self) -> Result<()> {
+        if self.threshold_db.is_nan() || self.threshold_db > 0.0 {
+            bail!("compressor threshold should not be greater than 0dB");
+        }
+
+        if self.ratio.is_nan() || self.ratio < 1.0 {
+            bail!("compressor ratio should not be less than 1.0");
+        }
+
+        if self.attack_ms.is_nan() || self.attack_ms <= 0.0 {
+            bail!("compressor attack should be greater than 0ms");
+        }
+
+        if self.release_ms.is_nan() || self.release_ms <= 0.0 {
+            bail!("compressor release should be greater than 0ms");
+        }
+
+        Ok(())
+    }
+
+    fn apply(&self, audio_data: &mut AudioData) {
+        let channels = audio_data.config.channels.max(1) as usize;
+        let frames_per_ms = audio_data.config.sample_rate as f32 / 1000.0;
+        let attack = (-1.0 / (self.attack_ms * frames_per_ms)).exp();
+        let release = (-1.0 / (self.release_ms * frames_per_ms)).exp();
+        let slope = 1.0 - 1.0 / self.ratio;
+
+        let mut envelope = 0.0;
+        for frame in audio_data.samples.chunks_exact_mut(channels) {
+            let level = frame.iter().fold(0.0f32, |level, s| level.max(s.abs()));
+            let coeff = if level > envelope { attack } else { release };
+            envelope = coeff * envelope + (1.0 - coeff) * level;
+
+            let over_db = gain_to_db(envelope) - self.threshold_db;
+            if over_db > 0.0 {
+                let gain = db_to_gain(-over_db * slope);
+                frame.iter_mut().for_each(|s| *s *= gain);
+            }
+        }
+
+        debug!(
+            "Compressed audio: threshold {}dB, ratio {}",
+            self.threshold_db, self.ratio
+        );
+    }
+}
+
+/// Loudness of the audio after the other stages, in dBFS
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Normalization {
+    Peak { target_db: f32 }, // the loudest sample
+    Rms { target_db: f32 },  // the average loudness, peaks above 0dBFS are clipped
+}
+
+impl Normalization {
+    pub fn peak() -> Self {
+        Normalization::Peak { target_db: -1.0 }
+    }
+
+    pub fn rms() -> Self {
+        Normalization::Rms { target_db: -20.0 }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        let (Normalization::Peak { target_db } | Normalization::Rms { target_db }) = *self;
+        if target_db.is_nan() || target_db > 0.0 {
+            bail!("normalization target should not be greater than 0dB");
+        }
+
+        Ok(())
+    }
+
+    fn apply(&self, audio_data: &mut AudioData) {
+        let samples = &audio_data.samples;
+        let (level, target_db) = match *self {
+            Normalization::Peak { target_db } => {
+                let peak = samples.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+                (peak, target_db)
+            }
+            Normalization::Rms { target_db } => {
+                let sum = samples.iter().map(|&s| s as f64 * s as f64).sum::<f64>();
+                let rms = (sum / samples.len().max(1) as f64).sqrt() as f32;
+                (rms, target_db)
+            }
+        };
+
+        // Silence stays silent
+        if level > 0.0 {
+            audio_data.apply_gain(target_db - gain_to_db(level));
+        }
+    }
+}
+
+// Run `f` on the samples of every channel
+fn for_each_channel(audio_data: &mut AudioData, mut f: impl FnMut(&mut [f32])) {
+    let channels = audio_data.config.channels.max(1) as usize;
+    if channels == 1 {
+        f(&mut audio_data.samples);
+        return;
+    }
+
+    for channel in 0..channels {
+        let mut samples = audio_data
+            .samples
+            .iter()
+            .skip(channel)
+            .step_by(channels)
+            .copied()
+            .collect::<Vec<_>>();
+        f(&mut samples);
+
+        for (frame, sample) in samples.into_iter().enumerate() {
+            audio_data.samples[frame * channels + channel] = sample;
+        }
+    }
+}
+
+fn db_to_gain(db: f32) -> f32 {
+    10.0f32.powf(db / 20.0)
+}
+
+fn gain_to_db(gain: f32) -> f32 {
+    20.0 * gain.max(1e-10).log10()
+}
+
+// Iterative radix-2 FFT of a power of two length
+struct Fft {
+    twiddles: Vec<(f32, f32)>, // e^(-2πik/n) for k < n / 2
+}
+
+impl Fft {
+    fn new(len: usize) -> Self {
+        let twiddles = (0..len / 2)
+            .map(|k| {
+                let angle = -2.0 * std::f64::consts::PI * k as f64 / len as f64;
+                (angle.cos() as f32, angle.sin() as f32)
+            })
+            .collect();
+
+        Self { twiddles }
+    }
+
+    // In place and unscaled, the inverse has to be divided by the length
+    fn transform(&self, re: &mut [f32], im: &mut [f32], inverse: bool) {
+        let len = re.len();
+
+        let mut j = 0;
+        for i in 1..len {
+            let mut bit = len >> 1;
+            while j & bit != 0 {
+                j ^= bit;
+                bit >>= 1;
+            }
+            j |= bit;
+
+            if i < j {
+                re.swap(i, j);
+                im.swap(i, j);
+            }
+        }
+
+        let mut size = 2;
+        while size <= len {
+            let (half, step) = (size / 2, len / size);
+            for start in (0..len).step_by(size) {
+                for k in 0..half {
+                    let (w_re, mut w_im) = self.twiddles[k * step];
+                    if inverse {
+                        w_im = -w_im;
+                    }
+
+                    let (a, b) = (start + k, start + k + half);
+                    let t_re = re[b] * w_re - im[b] * w_im;
+                    let t_im = re[b] * w_im + im[b] * w_re;
+                    (re[b], im[b]) = (re[a] - t_re, im[a] - t_im);
+                    re[a] += t_re;
+                    im[a] += t_im;
+                }
+            }
+            size <<= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(frequency: f32, sample_rate: u32, seconds: f32) -> Vec<f32> {
+        (0..(sample_rate as f32 * seconds) as usize)
+            .map(|i| {
+                0.5 * (2.0 * std::f32::consts::PI * frequency * i as f32 / sample_rate as f32).sin()
+            })
+            .collect()
+    }
+
+    // RMS without the first and last 100ms, where the filter sees the edges of the audio
+    fn middle_rms(audio_data: &AudioData) -> f32 {
+        let edge = audio_data.config.sample_rate as usize / 10;
+        let samples = &audio_data.samples[edge..audio_data.samples.len() - edge];
+        (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    // cargo test test_resample_duration -- --no-capture
+    #[test]
+    fn test_resample_duration() -> Result<()> {
+        for (source_rate, target_rate) in [
+            (44100, 16000),
+            (48000, 16000),
+            (8000, 16000),
+            (22050, 16000),
+            (16000, 44100),
+        ] {
+            let audio_data = AudioData::new(
+                sine(440.0, source_rate, 1.5),
+                AudioConfig::new(source_rate, 1, 16),
+            );
+            let resampled = audio_data.resample(target_rate)?;
+
+            assert_eq!(resampled.config.sample_rate, target_rate);
+            assert!(
+                (resampled.duration() - audio_data.duration()).abs() <= 1.0 / target_rate as f64,
+                "{source_rate} -> {target_rate}: {} != {}",
+                resampled.duration(),
+                audio_data.duration()
+            );
+        }
+
+        Ok(())
+    }
+
+    // cargo test test_resample_frequency_response -- --no-capture
+    #[test]
+    fn test_resample_frequency_response() -> Result<()> {
+        let expected_rms = 0.5 / 2.0f32.sqrt();
+
+        // Tones in the passband keep their amplitude
+        for (source_rate, frequency) in [(48000, 1000.0), (44100, 3000.0), (8000, 1000.0)] {
+            let audio_data = AudioData::new(
+                sine(frequency, source_rate, 1.0),
+                AudioConfig::new(source_rate, 1, 16),
+            );
+            let rms = middle_rms(&audio_data.resample(16000)?);
+
+            assert!(
+                (rms - expected_rms).abs() < expected_rms * 0.02,
+                "{frequency}Hz at {source_rate}Hz: rms {rms}"
+            );
+        }
+
+        // Tones above the new Nyquist frequency are removed instead of aliasing
+        for (source_rate, frequency) in [(48000, 12000.0), (44100, 10000.0)] {
+            let audio_data = AudioData::new(
+                sine(frequency, source_rate, 1.0),
+                AudioConfig::new(source_rate, 1, 16),
+            );
+            let rms = middle_rms(&audio_data.resample(16000)?);
+
+            assert!(
+                rms < expected_rms * 0.01,
+                "{frequency}Hz at {source_rate}Hz: rms {rms}"
+            );
+        }
+
+        Ok(())
+    }
+
+    // cargo test test_to_whisper_compatible -- --no-capture
+    #[test]
+    fn test_to_whisper_compatible() -> Result<()> {
+        let samples = sine(1000.0, 44100, 1.0)
+            .into_iter()
+            .flat_map(|s| [s, s])
+            .collect::<Vec<_>>();
+
+        let audio_data = AudioData::new(samples, AudioConfig::new(44100, 2, 24));
+        let compatible = audio_data.to_whisper_compatible()?;
+
+        assert!(compatible.is_whisper_compatible());
+        assert!((compatible.duration() - 1.0).abs() < 0.001);
+        assert!(audio_data.resample(0).is_err());
+
+        Ok(())
+    }
+
+    // cargo test test_read_range -- --no-capture
+    #[test]
+    fn test_read_range() -> Result<()> {
+        let path = std::env::temp_dir().join("transcribe-test-read-range.wav");
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 8000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+
+        let mut writer = hound::WavWriter::create(&path, spec)?;
+        for i in 0..16000 {
+            writer.write_sample((i % 1000) as i16)?;
+        }
+        writer.finalize()?;
+
+        let audio_data = read_file(&path)?;
+        for (start_ms, end_ms) in [(0, 1000), (250, 500), (900, 5000), (2000, 3000), (500, 100)] {
+            let range = AudioData::read_range(&path, start_ms, end_ms)?;
+            assert_eq!(range.config.channels, 2);
+            assert_eq!(
+                range.samples,
+                audio_data.slice(start_ms, end_ms).samples,
+                "{start_ms}ms - {end_ms}ms"
+            );
+        }
+
+        assert_eq!(AudioData::read_range(&path, 250, 500)?.frame_count(), 2000);
+
+        _ = std::fs::remove_file(&path);
+        Ok(())
+    }
+
+    // `fmt ` chunk, `WAVE_FORMAT_EXTENSIBLE` if there are fewer valid bits than the container
+    fn fmt_chunk(
+        format_tag: u16,
+        channels: u16,
+        sample_rate: u32,
+        container_bytes: u16,
+        valid_bits: Option<u16>,
+    ) -> Vec<u8> {
+        let block_align = channels * container_bytes;
+        let tag = if valid_bits.is_some() {
+            WAVE_FORMAT_EXTENSIBLE
+        } else {
+            format_tag
+        };
+
+        let mut chunk = vec![];
+        chunk.extend(tag.to_le_bytes());
+        chunk.extend(channels.to_le_bytes());
+        chunk.extend(sample_rate.to_le_bytes());
+        chunk.extend((sample_rate * block_align as u32).to_le_bytes());
+        chunk.extend(block_align.to_le_bytes());
+        chunk.extend((container_bytes * 8).to_le_bytes());
+
+        if let Some(valid_bits) = valid_bits {
+            chunk.extend(22u16.to_le_bytes());
+            chunk.extend(valid_bits.to_le_bytes());
+            chunk.extend(0u32.to_le_bytes()); // channel mask
+            chunk.extend(format_tag.to_le_bytes());
+            chunk.extend(b"\x00\x00\x00\x00\x10\x00\x80\x00\x00\xAA\x00\x38\x9B\x71");
+        }
+
+        chunk
+    }
+
+    // RIFF wav file with an odd sized chunk before the samples, or RF64 file with the sizes
+    // in the `ds64` chunk
+    fn write_wav(name: &str, rf64: bool, fmt: Vec<u8>, data: &[u8]) -> Result<std::path::PathBuf> {
+        let mut bytes = vec![];
+        let mut chunk = |id: &[u8; 4], size: u32, content: &[u8]| {
+            bytes.extend(id);
+            bytes.extend(size.to_le_bytes());
+            bytes.extend(content);
+            if content.len() % 2 == 1 {
+                bytes.push(0);
+            }
+        };
+
+        if rf64 {
+            let mut ds64 = vec![];
+            ds64.extend((data.len() as u64 + 100).to_le_bytes());
+            ds64.extend((data.len() as u64).to_le_bytes());
+            ds64.extend(0u64.to_le_bytes());
+            ds64.extend(0u32.to_le_bytes());
+
+            chunk(b"ds64", ds64.len() as u32, &ds64);
+            chunk(b"fmt ", fmt.len() as u32, &fmt);
+            chunk(b"data", u32::MAX, data);
+        } else {
+            chunk(b"fmt ", fmt.len() as u32, &fmt);
+            chunk(b"LIST", 3, b"abc");
+            chunk(b"data", data.len() as u32, data);
+        }
+
+        let mut file = if rf64 {
+            b"RF64".to_vec()
+        } else {
+            b"RIFF".to_vec()
+        };
+        let riff_size = if rf64 {
+            u32::MAX
+        } else {
+            bytes.len() as u32 + 4
+        };
+        file.extend(riff_size.to_le_bytes());
+        file.extend(b"WAVE");
+        file.extend(bytes);
+
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, file)?;
+        Ok(path)
+    }
+
+    fn assert_samples_eq(actual: &[f32], expected: &[f32], tolerance: f32) {
+        assert_eq!(actual.len(), expected.len());
+        for (i, (a, e)) in actual.iter().zip(expected).enumerate() {
+            assert!((a - e).abs() <= tolerance, "sample {i}: {a} != {e}");
+        }
+    }
+
+    // cargo test test_read_8bit -- --no-capture
+    #[test]
+    fn test_read_8bit() -> Result<()> {
+        let samples = sine(440.0, 8000, 0.5);
+        let data = samples
+            .iter()
+            .map(|s| (s * 128.0 + 128.0).round().clamp(0.0, 255.0) as u8)
+            .collect::<Vec<_>>();
+
+        let fmt = fmt_chunk(WAVE_FORMAT_PCM, 1, 8000, 1, None);
+        let path = write_wav("transcribe-test-8bit.wav", false, fmt, &data)?;
+
+        let audio_data = read_file(&path)?;
+        assert_eq!(audio_data.config.bit_depth, 8);
+        assert_samples_eq(&audio_data.samples, &samples, 1.0 / 128.0);
+
+        let (header, _) = WavHeader::open(&path)?;
+        assert_eq!(header.sample_format, WavSampleFormat::Int);
+        assert_eq!(header.frame_count(), 4000);
+
+        // 8kHz mono still needs resampling
+        assert!(is_whisper_compatible(&path).is_err());
+
+        _ = std::fs::remove_file(&path);
+        Ok(())
+    }
+
+    // cargo test test_read_extensible -- --no-capture
+    #[test]
+    fn test_read_extensible() -> Result<()> {
+        let channels = 3;
+        let samples = sine(1000.0, 16000, 0.25)
+            .into_iter()
+            .flat_map(|s| [s, -s, s / 2.0])
+            .collect::<Vec<_>>();
+
+        // 24 valid bits in a 32-bit container
+        let data = samples
+            .iter()
+            .flat_map(|s| (((s * 8388607.0).round() as i32) << 8).to_le_bytes())
+            .collect::<Vec<_>>();
+        let fmt = fmt_chunk(WAVE_FORMAT_PCM, channels, 16000, 4, Some(24));
+        let path = write_wav("transcribe-test-extensible.wav", false, fmt, &data)?;
+
+        let audio_data = read_file(&path)?;
+        assert_eq!(audio_data.config.channels, channels);
+        assert_eq!(audio_data.config.bit_depth, 24);
+        assert_samples_eq(&audio_data.samples, &samples, 1e-6);
+        assert_eq!(
+            AudioData::read_range(&path, 50, 100)?.samples,
+            audio_data.slice(50, 100).samples
+        );
+        _ = std::fs::remove_file(&path);
+
+        // 64-bit float
+        let data = samples
+            .iter()
+            .flat_map(|&s| (s as f64).to_le_bytes())
+            .collect::<Vec<_>>();
+        let fmt = fmt_chunk(WAVE_FORMAT_IEEE_FLOAT, channels, 16000, 8, Some(64));
+        let path = write_wav("transcribe-test-extensible-float.wav", false, fmt, &data)?;
+
+        let audio_data = read_file(&path)?;
+        assert_eq!(audio_data.samples, samples);
+        _ = std::fs::remove_file(&path);
+
+        Ok(())
+    }
+
+    // cargo test test_read_rf64 -- --no-capture
+    #[test]
+    fn test_read_rf64() -> Result<()> {
+        let samples = sine(440.0, 16000, 1.0)
+            .into_iter()
+            .flat_map(|s| [s, s / 4.0])
+            .collect::<Vec<_>>();
+        let data = samples
+            .iter()
+            .flat_map(|s| ((s * i16::MAX as f32).round() as i16).to_le_bytes())
+            .collect::<Vec<_>>();
+
+        let fmt = fmt_chunk(WAVE_FORMAT_PCM, 2, 16000, 2, None);
+        let path = write_wav("transcribe-test-rf64.wav", true, fmt, &data)?;
+
+        let (header, _) = WavHeader::open(&path)?;
+        assert_eq!(header.data_len, data.len() as u64);
+        assert_eq!(header.duration_ms(), 1000);
+
+        let audio_data = read_file(&path)?;
+        assert_samples_eq(&audio_data.samples, &samples, 1.0 / i16::MAX as f32);
+
+        for (start_ms, end_ms) in [(0, 1000), (250, 500), (900, 5000)] {
+            assert_eq!(
+                AudioData::read_range(&path, start_ms, end_ms)?.samples,
+                audio_data.slice(start_ms, end_ms).samples,
+                "{start_ms}ms - {end_ms}ms"
+            );
+        }
+
+        // Compatible once a channel is selected
+        assert!(is_whisper_compatible(&path).is_err());
+        assert!(
+            audio_data
+                .select_channel(AudioChannel::Right)?
+                .is_whisper_compatible()
+        );
+
+        _ = std::fs::remove_file(&path);
+        Ok(())
+    }
+
+    // cargo test test_select_channel -- --no-capture
+    #[test]
+    fn test_select_channel() -> Result<()> {
+        let samples = (0..12).map(|i| i as f32).collect::<Vec<_>>();
+        let audio_data = AudioData::new(samples, AudioConfig::new(16000, 3, 16));
+
+        let left = audio_data.select_channel(AudioChannel::Left)?;
+        assert_eq!(left.config.channels, 1);
+        assert_eq!(left.samples, [0.0, 3.0, 6.0, 9.0]);
+
+        let right = audio_data.select_channel(AudioChannel::Right)?;
+        assert_eq!(right.samples, [1.0, 4.0, 7.0, 10.0]);
+
+        let third = audio_data.select_channel(AudioChannel::Index(2))?;
+        assert_eq!(third.samples, [2.0, 5.0, 8.0, 11.0]);
+
+        let mix = audio_data.select_channel(AudioChannel::Mix)?;
+        assert_eq!(mix.samples, [1.0, 4.0, 7.0, 10.0]);
+
+        assert!(audio_data.select_channel(AudioChannel::Index(3)).is_err());
+
+        let mono = left.select_channel(AudioChannel::Left)?;
+        assert_eq!(mono.samples, left.samples);
+        assert!(left.select_channel(AudioChannel::Right).is_err());
+
+        Ok(())
+    }
+
+    // Deterministic white noise in (-amplitude, amplitude)
+    fn noise(amplitude: f32, len: usize) -> Vec<f32> {
+        let mut state = 0x2545f491u32;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                (state as f32 / u32::MAX as f32 * 2.0 - 1.0) * amplitude
+            })
+            .collect()
+    }
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    // cargo test test_preprocess_high_pass -- --no-capture
+    #[test]
+    fn test_preprocess_high_pass() -> Result<()> {
+        let preprocess = AudioPreprocess::default().with_high_pass(HighPass::new(200.0));
+
+        for (frequency, min_ratio, max_ratio) in [(30.0, 0.0, 0.05), (1000.0, 0.98, 1.02)] {
+            let mut audio_data =
+                AudioData::new(sine(frequency, 16000, 1.0), AudioConfig::new(16000, 1, 16));
+            let before = middle_rms(&audio_data);
+            preprocess.apply(&mut audio_data)?;
+
+            let ratio = middle_rms(&audio_data) / before;
+            assert!(
+                (min_ratio..=max_ratio).contains(&ratio),
+                "{frequency}Hz: ratio {ratio}"
+            );
+        }
+
+        let mut audio_data = AudioData::new(vec![0.0; 100], AudioConfig::new(16000, 1, 16));
+        assert!(
+            AudioPreprocess::default()
+                .with_high_pass(HighPass::new(8000.0))
+                .apply(&mut audio_data)
+                .is_err()
+        );
+
+        Ok(())
+    }
+
+    // cargo test test_preprocess_noise_reduction -- --no-capture
+    #[test]
+    fn test_preprocess_noise_reduction() -> Result<()> {
+        // A tone in the second second of 4 seconds of noise
+        let mut samples = noise(0.02, 64000);
+        for (i, s) in sine(1000.0, 16000, 1.0).into_iter().enumerate() {
+            samples[16000 + i] += s;
+        }
+
+        let mut audio_data = AudioData::new(samples.clone(), AudioConfig::new(16000, 1, 16));
+        AudioPreprocess::default()
+            .with_noise_reduction(NoiseReduction::default())
+            .apply(&mut audio_data)?;
+        assert_eq!(audio_data.samples.len(), samples.len());
+
+        let noise_ratio = rms(&audio_data.samples[40000..64000]) / rms(&samples[40000..64000]);
+        assert!(noise_ratio < 0.3, "noise ratio {noise_ratio}");
+
+        let tone_ratio = rms(&audio_data.samples[20000..28000]) / rms(&samples[20000..28000]);
+        assert!((tone_ratio - 1.0).abs() < 0.05, "tone ratio {tone_ratio}");
+
+        Ok(())
+    }
+
+    // cargo test test_preprocess_compressor_normalization -- --no-capture
+    #[test]
+    fn test_preprocess_compressor_normalization() -> Result<()> {
+        // A loud second followed by a quiet one
+        let samples = sine(440.0, 16000, 2.0)
+            .into_iter()
+            .enumerate()
+            .map(|(i, s)| if i < 16000 { s * 1.8 } else { s * 0.1 })
+            .collect::<Vec<_>>();
+        let audio_data = AudioData::new(samples, AudioConfig::new(16000, 1, 16));
+        let loudness_ratio = |audio_data: &AudioData| {
+            rms(&audio_data.samples[4000..16000]) / rms(&audio_data.samples[20000..32000])
+        };
+
+        let mut compressed = audio_data.clone();
+        AudioPreprocess::default()
+            .with_compressor(Compressor::default())
+            .with_normalization(Normalization::peak())
+            .apply(&mut compressed)?;
+
+        assert!(loudness_ratio(&compressed) < loudness_ratio(&audio_data) / 3.0);
+        let peak = compressed
+            .samples
+            .iter()
+            .fold(0.0f32, |p, s| p.max(s.abs()));
+        assert!((gain_to_db(peak) + 1.0).abs() < 0.01, "peak {peak}");
+
+        let mut normalized = audio_data.clone();
+        Normalization::rms().apply(&mut normalized);
+        assert!((gain_to_db(rms(&normalized.samples)) + 20.0).abs() < 0.01);
+
+        // Silence stays silent
+        let mut silence = AudioData::new(vec![0.0; 1000], AudioConfig::new(16000, 1, 16));
+        Normalization::peak().apply(&mut silence);
+        assert!(silence.samples.iter().all(|s| *s == 0.0));
+
+        Ok(())
+    }
+
+    // cargo test test_preprocess_validate -- --no-capture
+    #[test]
+    fn test_preprocess_validate() {
+        assert!(AudioPreprocess::default().is_empty());
+        assert!(AudioPreprocess::default().validate().is_ok());
+
+        let invalid = [
+            AudioPreprocess::default().with_high_pass(HighPass::new(0.0)),
+            AudioPreprocess::default().with_noise_reduction(NoiseReduction {
+                reduction_db: -6.0,
+                ..Default::default()
+            }),
+            AudioPreprocess::default().with_compressor(Compressor {
+                ratio: 0.5,
+                ..Default::default()
+            }),
+            AudioPreprocess::default().with_normalization(Normalization::Rms { target_db: 3.0 }),
+        ];
+
+        for preprocess in invalid {
+            assert!(!preprocess.is_empty());
+            assert!(preprocess.validate().is_err(), "{preprocess:?}");
+        }
+    }
+
+    // cargo test test_slice -- --no-capture
+    #[test]
+    fn test_slice() {
+        let samples = (0..32000).map(|i| i as f32).collect::<Vec<_>>();
+        let audio_data = AudioData::new(samples, AudioConfig::new(16000, 2, 16));
+
+        let slice = audio_data.slice(250, 500);
+        assert_eq!(slice.frame_count(), 4000);
+        assert_eq!(slice.samples[0], 8000.0);
+        assert_eq!(slice.samples[1], 8001.0);
+
+        assert_eq!(audio_data.slice(500, 2000).frame_count(), 8000);
+        assert_eq!(audio_data.slice(2000, 3000).frame_count(), 0);
+        assert_eq!(audio_data.slice(500, 100).frame_count(), 0);
+    }
 }
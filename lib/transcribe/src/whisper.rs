@@ -1,51 +1,248 @@
+use super::checkpoint::{self, CheckpointChunk, CheckpointStatus, TranscriptionCheckpoint};
+use super::chunking::{ChunkRange, ChunkSamples, ChunkSplitter, ChunkingStrategy, SplitReason};
+use super::glossary::{Glossary, GlossaryReplacement};
+use super::hallucination::{Hallucination, HallucinationFilter};
+use super::model_cache::ModelCache;
+use super::speaker::{self, SpeakerDetection};
 use super::vad::EnergyVAD;
-use super::wav::{self, AudioData};
+use super::wav::{self, AudioConfig, AudioData, AudioPreprocess};
+use super::whisper_lang::WhisperLang;
 use anyhow::{Context, Result, anyhow, bail};
-use log::debug;
+use log::{debug, warn};
 use serde::{Deserialize, Serialize};
 use std::{
-    fs,
+    cell::Cell,
+    collections::BTreeMap,
+    fs, mem,
     path::{Path, PathBuf},
-    sync::{Arc, atomic::AtomicBool},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{self, RecvTimeoutError},
+    },
+    thread,
+    time::Duration,
 };
 use whisper_rs::{
     FullParams, SamplingStrategy, SegmentCallbackData, WhisperContext, WhisperContextParameters,
-    WhisperState, WhisperVadParams,
+    WhisperState, WhisperVadContext, WhisperVadContextParams, WhisperVadParams,
 };
 
+// Whisper uses at most n_text_ctx / 2 tokens of the prompt
+const MAX_PROMPT_TOKENS: usize = 224;
+
+// Whisper detects the language from a 30s window
+const LANG_DETECT_WINDOW_MS: u64 = 30_000;
+
+// Share of the progress taken by decoding in `transcribe_media` without chunking
+const MEDIA_DECODE_PROGRESS: i32 = 10;
+
+// Samples pushed to the chunk splitter at once when the audio is already in memory
+const READ_BLOCK_SAMPLES: usize = 16000;
+
 const GGML_SILERO_VAD_MODEL: &'static [u8] = include_bytes!("../data/ggml-silero-v5.1.2.bin");
 
+// Writes of the bundled model by this process, to name the temporary files apart
+static VAD_MODEL_WRITES: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum WhisperSamplingStrategy {
+    // Pick the best of `best_of` candidates at each step. Fast
+    Greedy { best_of: i32 },
+
+    // Keep `beam_size` hypotheses. Slower but more accurate on hard audio.
+    // `patience` -1.0 uses the whisper.cpp default
+    BeamSearch { beam_size: i32, patience: f32 },
+}
+
+impl Default for WhisperSamplingStrategy {
+    fn default() -> Self {
+        Self::Greedy { best_of: 1 }
+    }
+}
+
+impl From<WhisperSamplingStrategy> for SamplingStrategy {
+    fn from(strategy: WhisperSamplingStrategy) -> Self {
+        match strategy {
+            WhisperSamplingStrategy::Greedy { best_of } => SamplingStrategy::Greedy { best_of },
+            WhisperSamplingStrategy::BeamSearch {
+                beam_size,
+                patience,
+            } => SamplingStrategy::BeamSearch {
+                beam_size,
+                patience,
+            },
+        }
+    }
+}
+
+/// Silero VAD parameters of whisper.cpp
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct VadParams {
+    pub threshold: f32,             // Speech probability of a frame, default 0.5
+    pub min_speech_ms: u32,         // Shorter speech is dropped as noise, default 250
+    pub min_silence_ms: u32,        // Silence ending a speech segment, default 100
+    pub speech_pad_ms: u32,         // Audio kept before and after each speech, default 30
+    pub max_speech_ms: Option<u64>, // Longer speech is split at a silence, default None
+}
+
+impl Default for VadParams {
+    fn default() -> Self {
+        Self {
+            threshold: 0.5,
+            min_speech_ms: 250,
+            min_silence_ms: 100,
+            speech_pad_ms: 30,
+            max_speech_ms: None,
+        }
+    }
+}
+
+impl VadParams {
+    pub fn with_threshold(mut self, threshold: f32) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    pub fn with_min_speech_ms(mut self, ms: u32) -> Self {
+        self.min_speech_ms = ms;
+        self
+    }
+
+    pub fn with_min_silence_ms(mut self, ms: u32) -> Self {
+        self.min_silence_ms = ms;
+        self
+    }
+
+    pub fn with_speech_pad_ms(mut self, ms: u32) -> Self {
+        self.speech_pad_ms = ms;
+        self
+    }
+
+    pub fn with_max_speech_ms(mut self, ms: u64) -> Self {
+        self.max_speech_ms = Some(ms);
+        self
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if !(0.0..=1.0).contains(&self.threshold) {
+            bail!("vad threshold should between 0.0 and 1.0");
+        }
+
+        if self.max_speech_ms == Some(0) {
+            bail!("max_speech_ms is 0");
+        }
+
+        Ok(())
+    }
+}
+
+impl From<VadParams> for WhisperVadParams {
+    fn from(vad_params: VadParams) -> Self {
+        let mut params = WhisperVadParams::default();
+        params.set_threshold(vad_params.threshold);
+        params.set_min_speech_duration(vad_params.min_speech_ms as i32);
+        params.set_min_silence_duration(vad_params.min_silence_ms as i32);
+        params.set_speech_pad(vad_params.speech_pad_ms as i32);
+
+        if let Some(ms) = vad_params.max_speech_ms {
+            params.set_max_speech_duration(ms as f32 / 1000.0);
+        }
+
+        params
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct WhisperConfig {
     pub model_path: PathBuf,
     pub vad_model_path: Option<PathBuf>,
+    pub vad_params: VadParams,    // used if `vad_model_path` is set
     pub language: Option<String>, // "zh", "en"，None is auto detect
     pub translate: bool,
     pub n_threads: i32,
     pub temperature: f32,
+    pub sampling_strategy: WhisperSamplingStrategy,
     pub max_segment_length: Option<u32>,
     pub initial_prompt: Option<String>,
     pub debug_mode: bool,
 
-    // Chunking configuration for long audio files to avoid timestamp drift
-    pub chunk_length_ms: Option<u64>, // Length of each chunk in milliseconds, default 60000 (60s)
-    pub chunk_overlap_ms: Option<u64>, // Overlap between chunks in milliseconds, default 1000 (1s)
+    // Temperature fallback. A chunk is decoded again with the next temperature of
+    // `temperature_fallback` when its average log probability is below `logprob_threshold`
+    // or its token entropy is below `entropy_threshold` (repetitive output), unless the
+    // no speech probability is above `no_speech_threshold` which means it is silence
+    pub temperature_fallback: Vec<f32>, // default [0.2, 0.4, 0.6, 0.8, 1.0], empty disables fallback
+    pub entropy_threshold: f32,         // default 2.4
+    pub logprob_threshold: f32,         // default -1.0
+    pub no_speech_threshold: f32,       // default 0.6
+
+    // Filters run on the 16kHz mono samples before inference
+    pub preprocess: Option<AudioPreprocess>, // default None
+
+    // How long audio files are split into chunks to avoid timestamp drift
+    pub chunking: Option<ChunkingStrategy>, // default None, not chunked
+
+    // Number of chunks decoded at the same time. Each one has its own whisper state
+    // and `n_threads / max_parallel_chunks` threads. Every state costs extra memory
+    pub max_parallel_chunks: usize, // default 1
+
+    // Number of tokens from the tail of the previous chunk's transcript used as the prompt of
+    // the next chunk, after `initial_prompt`. It keeps style and spelling consistent across
+    // chunks, but chunks have to be decoded one by one. 0 disables it
+    pub context_tokens: usize, // default 0, at most 224
+
+    // How `TranscriptionSegment.speaker` is assigned
+    pub speaker_detection: SpeakerDetection, // default None
+
+    // Finished chunks of a chunked transcription are saved to this file. If it exists when a
+    // transcription starts, its chunks are skipped. It is refused if the model, language or
+    // audio has changed. The file is removed when the transcription finishes
+    pub checkpoint_path: Option<PathBuf>, // default None
+
+    // Flag or drop repeated lines and stock phrases made up on music or silence
+    pub hallucination_filter: Option<HallucinationFilter>, // default None
+
+    // Terms listed in the prompt before `initial_prompt` as far as the prompt tokens allow,
+    // and near-miss spellings of them fixed in the result
+    pub glossary: Option<Glossary>, // default None
+
+    // Context parameters. Models are cached per model path and context parameters
+    pub use_gpu: bool,    // default true if whisper-rs is built with a GPU backend
+    pub flash_attn: bool, // default false
+    pub gpu_device: i32,  // default 0
 }
 
 impl Default for WhisperConfig {
     fn default() -> Self {
+        let ctx_params = WhisperContextParameters::default();
+
         Self {
             model_path: PathBuf::from("models/ggml-base.bin"),
             vad_model_path: None,
+            vad_params: VadParams::default(),
             language: None,
             translate: false,
             n_threads: num_cpus::get().min(8) as i32,
             temperature: 0.0,
+            sampling_strategy: WhisperSamplingStrategy::default(),
             max_segment_length: None,
             initial_prompt: None,
             debug_mode: false,
-            chunk_length_ms: None,
-            chunk_overlap_ms: None,
+            temperature_fallback: vec![0.2, 0.4, 0.6, 0.8, 1.0],
+            entropy_threshold: 2.4,
+            logprob_threshold: -1.0,
+            no_speech_threshold: 0.6,
+            preprocess: None,
+            chunking: None,
+            max_parallel_chunks: 1,
+            context_tokens: 0,
+            speaker_detection: SpeakerDetection::default(),
+            checkpoint_path: None,
+            hallucination_filter: None,
+            glossary: None,
+            use_gpu: ctx_params.use_gpu,
+            flash_attn: ctx_params.flash_attn,
+            gpu_device: ctx_params.gpu_device,
         }
     }
 }
@@ -63,6 +260,11 @@ impl WhisperConfig {
         self
     }
 
+    pub fn with_vad_params(mut self, vad_params: VadParams) -> Self {
+        self.vad_params = vad_params;
+        self
+    }
+
     pub fn with_language<S: Into<String>>(mut self, language: S) -> Self {
         self.language = Some(language.into());
         self
@@ -83,6 +285,22 @@ impl WhisperConfig {
         self
     }
 
+    pub fn with_sampling_strategy(mut self, strategy: WhisperSamplingStrategy) -> Self {
+        self.sampling_strategy = strategy;
+        self
+    }
+
+    pub fn with_greedy(self, best_of: i32) -> Self {
+        self.with_sampling_strategy(WhisperSamplingStrategy::Greedy { best_of })
+    }
+
+    pub fn with_beam_search(self, beam_size: i32, patience: f32) -> Self {
+        self.with_sampling_strategy(WhisperSamplingStrategy::BeamSearch {
+            beam_size,
+            patience,
+        })
+    }
+
     pub fn with_initial_prompt<S: Into<String>>(mut self, prompt: S) -> Self {
         self.initial_prompt = Some(prompt.into());
         self
@@ -93,18 +311,130 @@ impl WhisperConfig {
         self
     }
 
+    pub fn with_temperature_fallback(mut self, temperatures: Vec<f32>) -> Self {
+        self.temperature_fallback = temperatures
+            .into_iter()
+            .map(|t| t.clamp(0.0, 1.0))
+            .collect();
+        self
+    }
+
+    pub fn with_entropy_threshold(mut self, threshold: f32) -> Self {
+        self.entropy_threshold = threshold;
+        self
+    }
+
+    pub fn with_logprob_threshold(mut self, threshold: f32) -> Self {
+        self.logprob_threshold = threshold;
+        self
+    }
+
+    pub fn with_no_speech_threshold(mut self, threshold: f32) -> Self {
+        self.no_speech_threshold = threshold;
+        self
+    }
+
+    pub fn with_preprocess(mut self, preprocess: AudioPreprocess) -> Self {
+        self.preprocess = Some(preprocess);
+        self
+    }
+
+    pub fn with_chunking(mut self, chunking: ChunkingStrategy) -> Self {
+        self.chunking = Some(chunking);
+        self
+    }
+
+    // Set `chunking` to the default strategy if it is None
     pub fn with_chunk_length_ms(mut self, length_ms: u64) -> Self {
-        self.chunk_length_ms = Some(length_ms);
+        self.chunking.get_or_insert_default().chunk_length_ms = length_ms;
         self
     }
 
     pub fn with_chunk_overlap_ms(mut self, overlap_ms: u64) -> Self {
-        self.chunk_overlap_ms = Some(overlap_ms);
+        self.chunking.get_or_insert_default().overlap_ms = overlap_ms;
+        self
+    }
+
+    pub fn with_max_parallel_chunks(mut self, max_parallel_chunks: usize) -> Self {
+        self.max_parallel_chunks = max_parallel_chunks;
+        self
+    }
+
+    pub fn with_context_tokens(mut self, context_tokens: usize) -> Self {
+        self.context_tokens = context_tokens;
+        self
+    }
+
+    pub fn with_speaker_detection(mut self, detection: SpeakerDetection) -> Self {
+        self.speaker_detection = detection;
+        self
+    }
+
+    pub fn with_checkpoint_path<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.checkpoint_path = Some(path.into());
+        self
+    }
+
+    pub fn with_hallucination_filter(mut self, filter: HallucinationFilter) -> Self {
+        self.hallucination_filter = Some(filter);
+        self
+    }
+
+    pub fn with_glossary(mut self, glossary: Glossary) -> Self {
+        self.glossary = Some(glossary);
+        self
+    }
+
+    pub fn with_use_gpu(mut self, use_gpu: bool) -> Self {
+        self.use_gpu = use_gpu;
+        self
+    }
+
+    pub fn with_flash_attn(mut self, flash_attn: bool) -> Self {
+        self.flash_attn = flash_attn;
+        self
+    }
+
+    pub fn with_gpu_device(mut self, gpu_device: i32) -> Self {
+        self.gpu_device = gpu_device;
         self
     }
 
+    pub fn context_params(&self) -> WhisperContextParameters<'static> {
+        let mut ctx_params = WhisperContextParameters::default();
+        ctx_params.use_gpu = self.use_gpu;
+        ctx_params.flash_attn = self.flash_attn;
+        ctx_params.gpu_device = self.gpu_device;
+        ctx_params
+    }
+
+    pub fn is_auto_language(&self) -> bool {
+        self.language
+            .as_ref()
+            .is_none_or(|lang| lang == &WhisperLang::Auto.to_string())
+    }
+
     pub fn should_use_chunking(&self) -> bool {
-        self.chunk_length_ms.is_some() && self.chunk_length_ms.unwrap() > 0
+        self.chunking.is_some_and(|c| c.chunk_length_ms > 0)
+    }
+
+    pub fn use_tinydiarize(&self) -> bool {
+        match self.speaker_detection {
+            SpeakerDetection::TinyDiarize => true,
+            SpeakerDetection::Auto => speaker::is_tdrz_model(&self.model_path),
+            _ => false,
+        }
+    }
+
+    /// Temperatures tried in order when decoding a chunk, starting with `temperature`
+    pub fn temperature_schedule(&self) -> Vec<f32> {
+        let mut schedule = vec![self.temperature];
+        for t in self.temperature_fallback.iter() {
+            if *t > schedule[schedule.len() - 1] {
+                schedule.push(*t);
+            }
+        }
+        schedule
     }
 
     pub fn validate(&self) -> Result<()> {
@@ -112,14 +442,70 @@ impl WhisperConfig {
             bail!("model path not exist: {}", self.model_path.display());
         }
 
+        if let Some(path) = &self.vad_model_path
+            && !path.exists()
+        {
+            bail!("No found vad model path: {}", path.display());
+        }
+
+        self.vad_params.validate()?;
+
+        if let Some(preprocess) = &self.preprocess {
+            preprocess.validate()?;
+        }
+
+        if let Some(chunking) = &self.chunking {
+            chunking.validate()?;
+        }
+
         if self.n_threads <= 0 {
             bail!("n_threads is 0");
         }
 
+        if self.max_parallel_chunks == 0 {
+            bail!("max_parallel_chunks is 0");
+        }
+
+        if self.context_tokens > MAX_PROMPT_TOKENS {
+            bail!("context_tokens should not be greater than {MAX_PROMPT_TOKENS}");
+        }
+
         if !(0.0..=1.0).contains(&self.temperature) {
             bail!("temperature should between 0.0 and 1.0");
         }
 
+        match self.sampling_strategy {
+            WhisperSamplingStrategy::Greedy { best_of } => {
+                if best_of <= 0 {
+                    bail!("best_of should be greater than 0");
+                }
+            }
+            WhisperSamplingStrategy::BeamSearch {
+                beam_size,
+                patience,
+            } => {
+                if beam_size <= 0 {
+                    bail!("beam_size should be greater than 0");
+                }
+
+                if patience != -1.0 && (patience.is_nan() || patience <= 0.0) {
+                    bail!("patience should be greater than 0.0 or -1.0");
+                }
+            }
+        }
+
+        if self
+            .temperature_fallback
+            .iter()
+            .any(|t| !(0.0..=1.0).contains(t))
+        {
+            bail!("fallback temperature should between 0.0 and 1.0");
+        }
+
+        if !(0.0..=1.0).contains(&self.no_speech_threshold) {
+            bail!("no_speech_threshold should between 0.0 and 1.0");
+        }
+
         Ok(())
     }
 }
@@ -131,6 +517,31 @@ pub struct TranscriptionSegment {
     pub end_time: u64,   // ms
     pub text: String,
     pub confidence: f32, // (0.0-1.0)
+
+    #[serde(default)]
+    pub words: Vec<Word>,
+
+    // Temperature of the decoding that produced this segment. Unchunked audio falls back inside
+    // whisper.cpp, so it is the starting temperature there
+    #[serde(default)]
+    pub temperature: f32,
+
+    #[serde(default)]
+    pub speaker_turn_next: bool, // tinydiarize detected a speaker turn after this segment
+
+    #[serde(default)]
+    pub speaker: Option<String>, // e.g. "Speaker 1", None if speakers aren't detected
+
+    #[serde(default)]
+    pub no_speech_prob: f32, // (0.0-1.0) of the decoding window of this segment
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Word {
+    pub text: String,
+    pub start_time: u64,  // ms
+    pub end_time: u64,    // ms
+    pub probability: f32, // (0.0-1.0)
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -140,12 +551,131 @@ pub struct TranscriptionResult {
     pub segments: Vec<TranscriptionSegment>,
     pub processing_time: u64, // ms
     pub audio_duration: u64,  // ms
+
+    #[serde(default)]
+    pub hallucinations: Vec<Hallucination>, // found by `WhisperConfig.hallucination_filter`
+
+    #[serde(default)]
+    pub glossary_replacements: Vec<GlossaryReplacement>, // made by `WhisperConfig.glossary`
+
+    #[serde(default)]
+    pub chunks: Vec<ChunkRange>, // split by `WhisperConfig.chunking`, empty if not chunked
+
+    #[serde(default)]
+    pub checkpoint: Option<CheckpointStatus>, // None if not chunked or no `checkpoint_path`
 }
 
+// A chunk sent back by a worker with its result, the samples are dropped once it is decoded
 #[derive(Debug, Clone)]
-struct AudioChunk {
-    samples: Vec<f32>,
+struct ChunkInfo {
     start_offset_ms: u64,
+    end_offset_ms: u64,
+    next_start_offset_ms: Option<u64>, // Some if the next chunk overlaps this one
+    split: Option<SplitReason>,
+    advance_samples: usize, // samples not shared with the next chunk
+    audio_hash: String,
+    resumed: bool, // taken from the checkpoint
+}
+
+impl ChunkInfo {
+    fn new(chunk: &ChunkSamples) -> Self {
+        Self {
+            start_offset_ms: samples_to_ms(chunk.start),
+            end_offset_ms: samples_to_ms(chunk.end),
+            next_start_offset_ms: (chunk.overlap > 0)
+                .then(|| samples_to_ms(chunk.end - chunk.overlap)),
+            split: chunk.split.clone(),
+            advance_samples: chunk.end - chunk.overlap - chunk.start,
+            audio_hash: checkpoint::audio_hash(&chunk.samples),
+            resumed: false,
+        }
+    }
+}
+
+/// Splits the 16kHz mono audio read by `WhisperTranscriber::transcribe_chunked` into chunks and
+/// sends them to the workers. Pushing waits while every worker is busy
+struct ChunkFeeder<'a> {
+    splitter: Option<ChunkSplitter>,
+    sender: mpsc::SyncSender<(usize, ChunkSamples)>,
+    sent_chunks: usize,
+    read_samples: usize,
+    aborted: &'a Arc<AtomicBool>,
+    expected_samples: &'a AtomicUsize,
+}
+
+impl ChunkFeeder<'_> {
+    fn push(&mut self, samples: &[f32]) -> Result<()> {
+        let Some(splitter) = self.splitter.as_mut() else {
+            bail!("Audio was already finished");
+        };
+
+        self.read_samples += samples.len();
+        for chunk in splitter.push(samples)? {
+            self.send(chunk)?;
+        }
+
+        Ok(())
+    }
+
+    // Send the rest of the audio and return the number of read samples
+    fn finish(mut self) -> Result<usize> {
+        if let Some(splitter) = self.splitter.take() {
+            for chunk in splitter.finish()? {
+                self.send(chunk)?;
+            }
+        }
+
+        debug!("Split audio into {} chunks", self.sent_chunks);
+        Ok(self.read_samples)
+    }
+
+    fn send(&mut self, chunk: ChunkSamples) -> Result<()> {
+        if self.aborted.load(Ordering::Relaxed) {
+            bail!("Transcription aborted");
+        }
+
+        debug!(
+            "Created chunk {}: samples={}..{}, split={:?}",
+            self.sent_chunks, chunk.start, chunk.end, chunk.split
+        );
+
+        self.sender
+            .send((self.sent_chunks, chunk))
+            .map_err(|_| anyhow!("Chunk workers exited unexpectedly"))?;
+        self.sent_chunks += 1;
+
+        Ok(())
+    }
+}
+
+// A decoded whisper text token with timestamps in milliseconds
+#[derive(Debug, Clone)]
+struct TokenPiece {
+    bytes: Vec<u8>,
+    start_time: u64,
+    end_time: u64,
+    probability: f32,
+}
+
+// Decoding quality of a chunk, used to decide whether to decode it again with higher temperature
+#[derive(Debug, Clone, Copy)]
+struct DecodeQuality {
+    avg_logprob: f32,
+    entropy: f32,
+    no_speech_prob: f32,
+}
+
+impl DecodeQuality {
+    fn is_silence(&self, config: &WhisperConfig) -> bool {
+        self.no_speech_prob > config.no_speech_threshold
+            && self.avg_logprob < config.logprob_threshold
+    }
+
+    fn is_failed(&self, config: &WhisperConfig) -> bool {
+        !self.is_silence(config)
+            && (self.avg_logprob < config.logprob_threshold
+                || self.entropy < config.entropy_threshold)
+    }
 }
 
 impl TranscriptionResult {
@@ -185,6 +715,10 @@ impl TranscriptionResult {
             segments: filtered_segments,
             processing_time: self.processing_time,
             audio_duration: self.audio_duration,
+            hallucinations: self.hallucinations.clone(),
+            glossary_replacements: self.glossary_replacements.clone(),
+            chunks: self.chunks.clone(),
+            checkpoint: self.checkpoint.clone(),
         }
     }
 }
@@ -192,27 +726,135 @@ impl TranscriptionResult {
 pub struct WhisperTranscriber {
     context: Arc<WhisperContext>,
     config: WhisperConfig,
+    initial_prompt: Option<String>, // glossary terms followed by `config.initial_prompt`
 }
 
 impl WhisperTranscriber {
     pub fn new(config: WhisperConfig) -> Result<Self> {
         config.validate()?;
 
-        debug!("Load Whisper model: {}", config.model_path.display());
-
-        let ctx_params = WhisperContextParameters::default();
-        let context = WhisperContext::new_with_params(
-            config.model_path.to_string_lossy().as_ref(),
-            ctx_params,
-        )
-        .map_err(|e| anyhow!("Load Whisper model error: {e}"))?;
+        let context =
+            ModelCache::global().get_or_load(&config.model_path, config.context_params())?;
 
-        Ok(Self {
-            context: Arc::new(context),
+        let mut transcriber = Self {
+            context,
             config,
+            initial_prompt: None,
+        };
+        transcriber.initial_prompt = transcriber.build_initial_prompt()?;
+
+        Ok(transcriber)
+    }
+
+    /// The glossary terms that fit in the prompt tokens left by `initial_prompt` and
+    /// `context_tokens`, followed by `initial_prompt`
+    fn build_initial_prompt(&self) -> Result<Option<String>> {
+        let Some(glossary) = self.config.glossary.as_ref().filter(|g| !g.is_empty()) else {
+            return Ok(self.config.initial_prompt.clone());
+        };
+
+        let initial_prompt = self
+            .config
+            .initial_prompt
+            .clone()
+            .filter(|prompt| !prompt.trim().is_empty());
+
+        let reserved_tokens = match &initial_prompt {
+            Some(prompt) => self.tokenize(&format!(" {prompt}"))?.len(),
+            None => 0,
+        } + self.config.context_tokens;
+
+        let glossary_prompt = glossary
+            .prompt(MAX_PROMPT_TOKENS.saturating_sub(reserved_tokens), |text| {
+                Ok(self.tokenize(text)?.len())
+            })?;
+
+        match &glossary_prompt {
+            Some(prompt) => debug!("Glossary prompt: {prompt}"),
+            None => warn!("No prompt tokens left for the glossary"),
+        }
+
+        Ok(match (glossary_prompt, initial_prompt) {
+            (Some(glossary_prompt), Some(prompt)) => Some(format!("{glossary_prompt} {prompt}")),
+            (glossary_prompt, prompt) => glossary_prompt.or(prompt),
         })
     }
 
+    // A token has at least one byte, so the text length is always enough
+    fn tokenize(&self, text: &str) -> Result<Vec<i32>> {
+        self.context
+            .tokenize(text, text.len() + 1)
+            .map_err(|e| anyhow!("Tokenize prompt failed: {e}"))
+    }
+
+    /// Detect the spoken language from the first `sample_window_ms` of the audio. Every 30s of
+    /// the window is detected separately and the probabilities are averaged. Languages are
+    /// ranked by probability, highest first
+    pub fn detect_language(
+        &self,
+        audio_data: &AudioData,
+        sample_window_ms: u64,
+    ) -> Result<Vec<(WhisperLang, f32)>> {
+        let audio_samples = self.prepare_audio_samples(audio_data)?;
+
+        let window_ms = sample_window_ms
+            .max(1000)
+            .min(audio_samples.len() as u64 * 1000 / 16000);
+        if window_ms == 0 {
+            bail!("No audio samples to detect language");
+        }
+
+        let samples = &audio_samples[..(window_ms * 16) as usize];
+        let n_threads = self.config.n_threads.max(1) as usize;
+
+        let mut state = self
+            .context
+            .create_state()
+            .map_err(|e| anyhow!("Create whisper state failed: {e}"))?;
+
+        state
+            .pcm_to_mel(samples, n_threads)
+            .map_err(|e| anyhow!("Compute mel spectrogram failed: {e}"))?;
+
+        let mut total_probs: Vec<f32> = vec![];
+        let mut n_windows = 0;
+
+        // A short tail of the window is mostly padding, it isn't worth a detection
+        let offsets = (0..window_ms)
+            .step_by(LANG_DETECT_WINDOW_MS as usize)
+            .filter(|offset| *offset == 0 || window_ms - offset >= LANG_DETECT_WINDOW_MS / 3);
+
+        for offset_ms in offsets {
+            let (_, probs) = state
+                .lang_detect(offset_ms as usize, n_threads)
+                .map_err(|e| anyhow!("Detect language failed: {e}"))?;
+
+            total_probs.resize(probs.len(), 0.0);
+            for (total, p) in total_probs.iter_mut().zip(probs) {
+                *total += p;
+            }
+            n_windows += 1;
+        }
+
+        // Languages unknown to `WhisperLang` (e.g. Cantonese) are dropped
+        let mut languages = total_probs
+            .into_iter()
+            .enumerate()
+            .filter_map(|(id, p)| {
+                let lang = WhisperLang::from(whisper_rs::get_lang_str(id as i32)?);
+                (lang != WhisperLang::Auto).then_some((lang, p / n_windows as f32))
+            })
+            .collect::<Vec<_>>();
+
+        languages.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        if let Some((lang, p)) = languages.first() {
+            debug!("Detected language: {} ({p:.2})", lang.to_string());
+        }
+
+        Ok(languages)
+    }
+
     pub async fn transcribe_file<P: AsRef<Path>>(
         &self,
         audio_path: P,
@@ -234,54 +876,202 @@ impl WhisperTranscriber {
         }
     }
 
-    pub async fn transcribe_audio_data(
+    /// Transcribe the selected audio stream and channel of any media file that ffmpeg can read.
+    /// It is decoded to 16kHz mono PCM through a pipe, so no temporary wav file is written. With
+    /// chunking, chunks are decoded by whisper while ffmpeg still decodes the rest, and only the
+    /// audio of the current chunk is kept. Stereo speaker detection needs the channels, so it
+    /// isn't available here. Without chunking, the whole audio is decoded into memory first,
+    /// which takes the first `MEDIA_DECODE_PROGRESS` percent of the progress
+    pub async fn transcribe_media<P: AsRef<Path>>(
         &self,
-        audio_data: &AudioData,
-        progress_cb: impl FnMut(i32) + 'static,
+        media_path: P,
+        selection: ffmpeg::AudioSelection,
+        mut progress_cb: impl FnMut(i32) + 'static,
         segmemnt_cb: impl FnMut(SegmentCallbackData) + 'static,
-        abort_cb: impl FnMut() -> bool + 'static,
+        mut abort_cb: impl FnMut() -> bool + 'static,
     ) -> Result<TranscriptionResult> {
-        let start_time = std::time::Instant::now();
+        let media_path = media_path.as_ref();
+        debug!("Start transcribe media: {}", media_path.display());
 
-        let audio_samples = if !audio_data.is_whisper_compatible() {
-            self.prepare_audio_samples(audio_data)?
-        } else {
-            audio_data.samples.clone()
-        };
+        if self.config.should_use_chunking() {
+            let read_audio = |feeder: &mut ChunkFeeder| {
+                let (cancel, expected_samples) = (feeder.aborted.clone(), feeder.expected_samples);
+                let (decoded_samples, mut push_result) = (Cell::new(0), Ok(()));
+
+                ffmpeg::decode_to_whisper_compatible_pcm(
+                    media_path,
+                    selection,
+                    cancel.clone(),
+                    // The audio length is only known from the decoding progress
+                    |v| {
+                        if v > 0 {
+                            let expected = decoded_samples.get() * 100 / v as usize;
+                            expected_samples.store(expected, Ordering::Relaxed);
+                        }
+                    },
+                    |pcm| {
+                        decoded_samples.set(decoded_samples.get() + pcm.len());
+
+                        if push_result.is_ok() {
+                            push_result = feeder.push(pcm);
+                            if push_result.is_err() {
+                                cancel.store(true, Ordering::Relaxed);
+                            }
+                        }
+                    },
+                )?;
+
+                push_result?;
+                if cancel.load(Ordering::Relaxed) {
+                    bail!("Transcription aborted");
+                }
 
-        debug!(
-            "Start whisper infer，audio duration: {:.2}s",
-            audio_data.duration()
-        );
+                Ok(())
+            };
 
-        let mut state = self
+            return self
+                .transcribe_chunked(read_audio, 0, None, progress_cb, segmemnt_cb, abort_cb)
+                .await;
+        }
+
+        let cancel = Arc::new(AtomicBool::new(false));
+        let mut samples = vec![];
+
+        ffmpeg::decode_to_whisper_compatible_pcm(
+            media_path,
+            selection,
+            cancel.clone(),
+            |v| progress_cb(v * MEDIA_DECODE_PROGRESS / 100),
+            |pcm| {
+                samples.extend_from_slice(pcm);
+
+                if abort_cb() {
+                    cancel.store(true, Ordering::Relaxed);
+                }
+            },
+        )?;
+
+        if cancel.load(Ordering::Relaxed) {
+            bail!("Transcription aborted");
+        }
+
+        let audio_data = AudioData::new(samples, AudioConfig::whisper_optimized());
+        debug!("Decoded {:.2}s audio", audio_data.duration());
+
+        let progress_cb = move |v: i32| {
+            progress_cb(MEDIA_DECODE_PROGRESS + v * (100 - MEDIA_DECODE_PROGRESS) / 100)
+        };
+
+        self.transcribe_audio_data(&audio_data, progress_cb, segmemnt_cb, abort_cb)
+            .await
+    }
+
+    /// Transcribe the audio between `start_ms` and `end_ms` only, e.g. to redo a few bad
+    /// lines. Timestamps of the result and of `segmemnt_cb` are in the time of the whole audio
+    pub async fn transcribe_range(
+        &self,
+        audio_data: &AudioData,
+        start_ms: u64,
+        end_ms: u64,
+        progress_cb: impl FnMut(i32) + 'static,
+        mut segmemnt_cb: impl FnMut(SegmentCallbackData) + 'static,
+        abort_cb: impl FnMut() -> bool + 'static,
+    ) -> Result<TranscriptionResult> {
+        let duration_ms = (audio_data.duration() * 1000.0) as u64;
+        if start_ms >= end_ms || start_ms >= duration_ms {
+            bail!("Invalid time range: {start_ms}ms - {end_ms}ms, audio duration: {duration_ms}ms");
+        }
+
+        debug!("Transcribe range: {start_ms}ms - {end_ms}ms");
+
+        let range_audio_data = audio_data.slice(start_ms, end_ms);
+        let segmemnt_cb = move |segment: SegmentCallbackData| {
+            segmemnt_cb(SegmentCallbackData {
+                start_timestamp: segment.start_timestamp + (start_ms / 10) as i64,
+                end_timestamp: segment.end_timestamp + (start_ms / 10) as i64,
+                ..segment
+            })
+        };
+
+        let mut result = if self.config.should_use_chunking() {
+            self.transcribe_audio_data_chunked(
+                &range_audio_data,
+                progress_cb,
+                segmemnt_cb,
+                abort_cb,
+            )
+            .await?
+        } else {
+            self.transcribe_audio_data(&range_audio_data, progress_cb, segmemnt_cb, abort_cb)
+                .await?
+        };
+
+        result.segments = mem::take(&mut result.segments)
+            .into_iter()
+            .map(|segment| offset_segment(segment, start_ms))
+            .collect();
+
+        for item in result.hallucinations.iter_mut() {
+            item.segment = offset_segment(item.segment.clone(), start_ms);
+        }
+
+        for item in result.glossary_replacements.iter_mut() {
+            item.start_time += start_ms;
+        }
+
+        for item in result.chunks.iter_mut() {
+            item.start_ms += start_ms;
+            item.end_ms += start_ms;
+        }
+
+        Ok(result)
+    }
+
+    pub async fn transcribe_audio_data(
+        &self,
+        audio_data: &AudioData,
+        progress_cb: impl FnMut(i32) + 'static,
+        segmemnt_cb: impl FnMut(SegmentCallbackData) + 'static,
+        abort_cb: impl FnMut() -> bool + 'static,
+    ) -> Result<TranscriptionResult> {
+        let start_time = std::time::Instant::now();
+
+        let audio_samples = self.prepare_audio_samples(audio_data)?;
+
+        debug!(
+            "Start whisper infer，audio duration: {:.2}s",
+            audio_data.duration()
+        );
+
+        let mut state = self
             .context
             .create_state()
             .map_err(|e| anyhow!("Create whisper state failed: {e}"))?;
 
-        let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
+        let mut params = FullParams::new(self.config.sampling_strategy.into());
         params.set_n_threads(self.config.n_threads);
         params.set_translate(self.config.translate);
         params.set_debug_mode(self.config.debug_mode);
         params.set_temperature(self.config.temperature);
+
+        // Unchunked audio falls back inside whisper.cpp, per 30 seconds window
+        if self.config.temperature_fallback.is_empty() {
+            params.set_temperature_inc(0.0);
+        }
+
+        params.set_entropy_thold(self.config.entropy_threshold);
+        params.set_logprob_thold(self.config.logprob_threshold);
+        params.set_no_speech_thold(self.config.no_speech_threshold);
         params.set_language(self.config.language.as_ref().map(|x| x.as_str()));
         params.set_token_timestamps(true);
+        params.set_tdrz_enable(self.config.use_tinydiarize());
 
         params.set_progress_callback_safe(progress_cb);
         params.set_segment_callback_safe(segmemnt_cb);
         params.set_abort_callback_safe(abort_cb);
+        self.set_vad(&mut params);
 
-        if let Some(path) = &self.config.vad_model_path {
-            if !path.exists() {
-                bail!("No found vad model path: {}", path.display());
-            }
-
-            params.set_vad_model_path(Some(&path.to_string_lossy().to_string()));
-            params.set_vad_params(WhisperVadParams::default());
-            params.enable_vad(true);
-        }
-
-        if let Some(prompt) = &self.config.initial_prompt {
+        if let Some(prompt) = &self.initial_prompt {
             params.set_initial_prompt(prompt.as_str());
         }
 
@@ -289,8 +1079,17 @@ impl WhisperTranscriber {
             .full(params, &audio_samples)
             .map_err(|e| anyhow!("Whisper transcribe failed: {e}"))?;
 
-        let result =
+        let mut result =
             self.extract_transcription_result(&state, audio_data.duration(), start_time)?;
+        self.assign_speakers(Some(audio_data), &mut result.segments)?;
+
+        if let Some(filter) = &self.config.hallucination_filter {
+            filter.apply(&mut result);
+        }
+
+        if let Some(glossary) = &self.config.glossary {
+            glossary.apply(&mut result);
+        }
 
         debug!(
             "Transcript finished，real time factor: {:.2}x",
@@ -303,100 +1102,373 @@ impl WhisperTranscriber {
     async fn transcribe_audio_data_chunked(
         &self,
         audio_data: &AudioData,
+        progress_cb: impl FnMut(i32) + 'static,
+        segmemnt_cb: impl FnMut(SegmentCallbackData) + 'static,
+        abort_cb: impl FnMut() -> bool + 'static,
+    ) -> Result<TranscriptionResult> {
+        let compatible_audio;
+        let samples = if audio_data.is_whisper_compatible() {
+            &audio_data.samples
+        } else {
+            compatible_audio = audio_data.to_whisper_compatible()?;
+            debug!("Finished converting to 16kHz mono channel");
+            &compatible_audio.samples
+        };
+
+        let read_audio = |feeder: &mut ChunkFeeder| {
+            for block in samples.chunks(READ_BLOCK_SAMPLES) {
+                feeder.push(block)?;
+            }
+
+            Ok(())
+        };
+
+        self.transcribe_chunked(
+            read_audio,
+            samples.len(),
+            Some(audio_data),
+            progress_cb,
+            segmemnt_cb,
+            abort_cb,
+        )
+        .await
+    }
+
+    /// Transcribe the 16kHz mono audio pushed by `read_audio` in chunks. `read_audio` runs on its
+    /// own thread, and the chunks are decoded while it reads. `expected_samples` is the audio
+    /// length for the progress, 0 if unknown. `read_audio` may update it. `audio_data` is the
+    /// audio for the speaker detection, None if the audio is only read by `read_audio`
+    async fn transcribe_chunked(
+        &self,
+        read_audio: impl FnOnce(&mut ChunkFeeder) -> Result<()> + Send,
+        expected_samples: usize,
+        audio_data: Option<&AudioData>,
         mut progress_cb: impl FnMut(i32) + 'static,
         mut segmemnt_cb: impl FnMut(SegmentCallbackData) + 'static,
         mut abort_cb: impl FnMut() -> bool + 'static,
     ) -> Result<TranscriptionResult> {
         let start_time = std::time::Instant::now();
+        let chunking = self.config.chunking.unwrap_or_default();
+        let splitter = ChunkSplitter::new(chunking, STREAMING_SAMPLE_RATE as u32)?;
 
-        let audio_samples = if !audio_data.is_whisper_compatible() {
-            self.prepare_audio_samples(audio_data)?
-        } else {
-            audio_data.samples.clone()
+        debug!(
+            "Start chunked whisper infer，expected audio duration: {:.2}s",
+            expected_samples as f64 / STREAMING_SAMPLE_RATE as f64
+        );
+
+        let (mut checkpoint, checkpoint_status) = match &self.config.checkpoint_path {
+            Some(path) => {
+                let (checkpoint, status) = self.open_checkpoint(path)?;
+                (Some(checkpoint), Some(status))
+            }
+            None => (None, None),
         };
 
-        let audio_duration = audio_data.duration();
+        // Chunks finished by an earlier run, they are not decoded again
+        let resumable = checkpoint.clone();
+        let mut resumed_chunks = 0;
 
-        debug!(
-            "Start chunked whisper infer，audio duration: {:.2}s",
-            audio_duration
-        );
+        let mut all_segments = Vec::new();
+        let mut chunk_ranges = vec![];
+        let mut language = None;
+
+        // Segments of the previous chunk in its overlap with the current chunk. They are
+        // held back until the current chunk is decoded, so the duplicates can be removed
+        let mut held_back_segments: Vec<TranscriptionSegment> = vec![];
+
+        let mut emit_segment = |mut segment: TranscriptionSegment| {
+            segment.index = all_segments.len() as i32 + 1;
 
-        // Create a temporary audio data for chunking
-        let temp_audio_data = AudioData {
-            samples: audio_samples,
-            config: audio_data.config.clone(),
+            segmemnt_cb(SegmentCallbackData {
+                text: segment.text.clone(),
+                start_timestamp: (segment.start_time / 10) as i64,
+                end_timestamp: (segment.end_time / 10) as i64,
+                segment: segment.index - 1,
+            });
+
+            all_segments.push(segment);
         };
 
-        let chunks = self.split_audio_into_chunks(&temp_audio_data);
-        let total_chunks = chunks.len();
+        // The prompt of a chunk depends on the previous chunk when carrying context. Short audio
+        // of a known length doesn't need more workers than chunks
+        let parallel = if self.config.context_tokens > 0 {
+            1
+        } else if expected_samples > 0 {
+            let chunk_samples = ms_to_samples(chunking.chunk_length_ms);
+            self.config
+                .max_parallel_chunks
+                .clamp(1, expected_samples.div_ceil(chunk_samples.max(1)))
+        } else {
+            self.config.max_parallel_chunks.max(1)
+        };
+        let n_threads = (self.config.n_threads / parallel as i32).max(1);
+        let aborted = Arc::new(AtomicBool::new(false));
+        let expected_samples = AtomicUsize::new(expected_samples);
+
+        // The reader waits for a free worker, so chunks don't pile up in memory
+        let (chunk_tx, chunk_rx) = mpsc::sync_channel::<(usize, ChunkSamples)>(1);
+        let chunk_rx = Arc::new(Mutex::new(chunk_rx));
+        let (tx, rx) = mpsc::channel::<(usize, ChunkInfo, Result<TranscriptionResult>)>();
+
+        debug!("Decoding {parallel} chunks in parallel, {n_threads} threads per chunk");
+
+        // The reader splits the audio into chunks and workers take them in order to decode them
+        // with their own whisper state. Results are reassembled in order on the current thread,
+        // which owns the callbacks
+        let (result, read_samples) = thread::scope(|scope| {
+            let reader = {
+                let (aborted, expected_samples) = (&aborted, &expected_samples);
+
+                scope.spawn(move || {
+                    let mut feeder = ChunkFeeder {
+                        splitter: Some(splitter),
+                        sender: chunk_tx,
+                        sent_chunks: 0,
+                        read_samples: 0,
+                        aborted,
+                        expected_samples,
+                    };
+
+                    let result = read_audio(&mut feeder).and_then(|_| feeder.finish());
+                    if result.is_err() {
+                        aborted.store(true, Ordering::Relaxed);
+                    }
 
-        debug!("Transcribing in {} chunks", total_chunks);
+                    result
+                })
+            };
 
-        let mut all_segments = Vec::new();
-        let mut full_text = String::new();
-        let mut global_segment_index = 0i32;
+            for _ in 0..parallel {
+                let (tx, chunk_rx) = (tx.clone(), chunk_rx.clone());
+                let (resumable, aborted) = (&resumable, &aborted);
 
-        for (chunk_idx, chunk) in chunks.into_iter().enumerate() {
-            if abort_cb() {
-                bail!("Transcription aborted");
+                scope.spawn(move || {
+                    let mut previous_text = String::new();
+
+                    loop {
+                        let Ok((chunk_idx, chunk)) = chunk_rx.lock().unwrap().recv() else {
+                            break;
+                        };
+
+                        if aborted.load(Ordering::Relaxed) {
+                            break;
+                        }
+
+                        let mut info = ChunkInfo::new(&chunk);
+                        debug!(
+                            "Processing chunk {} (offset: {}ms, samples: {})",
+                            chunk_idx + 1,
+                            info.start_offset_ms,
+                            chunk.samples.len()
+                        );
+
+                        let finished = resumable.as_ref().and_then(|checkpoint| {
+                            checkpoint.find_chunk(
+                                chunk_idx,
+                                info.start_offset_ms,
+                                info.end_offset_ms,
+                                &info.audio_hash,
+                            )
+                        });
+
+                        let result = match finished {
+                            Some(finished) => {
+                                info.resumed = true;
+                                Ok(finished.result.clone())
+                            }
+                            None => self
+                                .prepare_chunk_samples(chunk.samples)
+                                .and_then(|samples| {
+                                    self.transcribe_chunk_with_fallback(
+                                        &samples,
+                                        &previous_text,
+                                        n_threads,
+                                        aborted,
+                                    )
+                                }),
+                        };
+                        let is_err = result.is_err();
+
+                        // Like the reference decoder, don't carry the text of a high temperature
+                        // decoding, which is likely garbage, into the next prompt
+                        if let Ok(result) = &result {
+                            previous_text = if result.segments.iter().any(|s| s.temperature > 0.5) {
+                                String::new()
+                            } else {
+                                result.text.clone()
+                            };
+                        }
+
+                        if tx.send((chunk_idx, info, result)).is_err() || is_err {
+                            break;
+                        }
+                    }
+                });
             }
+            drop((tx, chunk_rx));
 
-            debug!(
-                "Processing chunk {}/{} (offset: {}ms, samples: {})",
-                chunk_idx + 1,
-                total_chunks,
-                chunk.start_offset_ms,
-                chunk.samples.len()
-            );
+            let mut pending = BTreeMap::new();
+            let (mut next_emit, mut previous_chunk_end_ms) = (0, 0);
+            let (mut finished_samples, mut progress) = (0, 0);
 
-            let chunk_result = self
-                .transcribe_chunk_internal(&chunk.samples, global_segment_index)
-                .await?;
+            let result = loop {
+                if abort_cb() {
+                    break Err(anyhow!("Transcription aborted"));
+                }
 
-            global_segment_index += chunk_result.segments.len() as i32;
+                match rx.recv_timeout(Duration::from_millis(100)) {
+                    Ok((chunk_idx, info, Ok(chunk_result))) => {
+                        if info.resumed {
+                            resumed_chunks += 1;
+                        } else if let (Some(checkpoint), Some(path)) =
+                            (checkpoint.as_mut(), &self.config.checkpoint_path)
+                        {
+                            checkpoint.push_chunk(CheckpointChunk {
+                                index: chunk_idx,
+                                start_offset_ms: info.start_offset_ms,
+                                end_offset_ms: info.end_offset_ms,
+                                audio_hash: info.audio_hash.clone(),
+                                result: chunk_result.clone(),
+                            });
+
+                            // A failed checkpoint only costs the resume, not the transcription
+                            if let Err(e) = checkpoint.save(path) {
+                                warn!("{e:?}");
+                            }
+                        }
+
+                        finished_samples += info.advance_samples;
+                        pending.insert(chunk_idx, (info, chunk_result));
+                    }
+                    Ok((_, _, Err(e))) => break Err(e),
+                    Err(RecvTimeoutError::Timeout) => continue,
 
-            // Adjust segment timestamps with chunk offset and call callback
-            for segment in chunk_result.segments {
-                let adjusted_segment = TranscriptionSegment {
-                    start_time: segment.start_time + chunk.start_offset_ms,
-                    end_time: segment.end_time + chunk.start_offset_ms,
-                    ..segment
-                };
+                    // The workers exit when the reader has no more chunks or failed
+                    Err(RecvTimeoutError::Disconnected) => break Ok(()),
+                }
 
-                // Convert to SegmentCallbackData for callback
-                let callback_data = SegmentCallbackData {
-                    text: adjusted_segment.text.clone(),
-                    start_timestamp: (adjusted_segment.start_time / 10) as i64,
-                    end_timestamp: (adjusted_segment.end_time / 10) as i64,
-                    segment: adjusted_segment.index - 1,
-                };
+                while let Some((info, chunk_result)) = pending.remove(&next_emit) {
+                    // Each chunk detects the language on its own when it is auto.
+                    // The first chunk with speech decides
+                    if language.is_none() && !chunk_result.segments.is_empty() {
+                        language = chunk_result.language.clone();
+                    }
 
-                segmemnt_cb(callback_data);
-                all_segments.push(adjusted_segment);
-            }
+                    // Adjust segment timestamps with chunk offset
+                    let segments = chunk_result
+                        .segments
+                        .into_iter()
+                        .map(|segment| offset_segment(segment, info.start_offset_ms))
+                        .collect::<Vec<_>>();
+
+                    let segments = if held_back_segments.is_empty() {
+                        segments
+                    } else {
+                        reconcile_overlap_segments(
+                            mem::take(&mut held_back_segments),
+                            segments,
+                            previous_chunk_end_ms,
+                            info.start_offset_ms,
+                        )
+                    };
+
+                    for segment in segments {
+                        match info.next_start_offset_ms {
+                            Some(start) if segment.end_time > start => {
+                                held_back_segments.push(segment)
+                            }
+                            _ => emit_segment(segment),
+                        }
+                    }
 
-            if !full_text.is_empty() {
-                full_text.push(' ');
+                    previous_chunk_end_ms = info.end_offset_ms;
+                    chunk_ranges.push(ChunkRange {
+                        start_ms: info.start_offset_ms,
+                        end_ms: info.end_offset_ms,
+                        split: info.split,
+                    });
+                    next_emit += 1;
+                }
+
+                // Count finished chunks rather than emitted ones, so the progress keeps moving
+                // while an earlier chunk is still decoding. The expected length is an estimate
+                // while reading media, so the progress is kept monotonic
+                let expected = expected_samples.load(Ordering::Relaxed);
+                if expected > 0 {
+                    progress = progress.max((finished_samples * 100 / expected).min(99) as i32);
+                    progress_cb(progress);
+                }
+            };
+
+            if result.is_err() {
+                aborted.store(true, Ordering::Relaxed);
             }
-            full_text.push_str(&chunk_result.text);
 
-            progress_cb(((chunk_idx + 1) * 100 / total_chunks) as i32);
+            let read_samples = reader
+                .join()
+                .unwrap_or_else(|_| Err(anyhow!("Audio reader panicked")));
+
+            (result, read_samples)
+        });
+
+        result?;
+        let read_samples = read_samples?;
+
+        for segment in held_back_segments {
+            emit_segment(segment);
         }
 
+        let full_text = all_segments
+            .iter()
+            .map(|s| s.text.as_str())
+            .collect::<Vec<_>>()
+            .join(" ");
+
         let processing_time = start_time.elapsed().as_millis() as u64;
-        let audio_duration_ms = (audio_duration * 1000.0) as u64;
+        let audio_duration_ms = samples_to_ms(read_samples);
 
         progress_cb(100);
 
-        let result = TranscriptionResult {
+        self.assign_speakers(audio_data, &mut all_segments)?;
+
+        if let Some(path) = &self.config.checkpoint_path {
+            _ = fs::remove_file(path);
+        }
+
+        // A checkpoint of other audio has no chunk to resume
+        let checkpoint_status = checkpoint_status.map(|status| match status {
+            CheckpointStatus::Resumed { chunks } if resumed_chunks == 0 && chunks > 0 => {
+                CheckpointStatus::Discarded {
+                    reason: "Checkpoint was made with different audio".to_string(),
+                }
+            }
+            CheckpointStatus::Resumed { .. } if resumed_chunks == 0 => CheckpointStatus::Created,
+            CheckpointStatus::Resumed { .. } => CheckpointStatus::Resumed {
+                chunks: resumed_chunks,
+            },
+            status => status,
+        });
+
+        let mut result = TranscriptionResult {
             text: full_text,
-            language: self.config.language.clone(),
+            language: language.or_else(|| self.config.language.clone()),
             segments: all_segments,
             processing_time,
             audio_duration: audio_duration_ms,
+            hallucinations: vec![],
+            glossary_replacements: vec![],
+            chunks: chunk_ranges,
+            checkpoint: checkpoint_status,
         };
 
+        if let Some(filter) = &self.config.hallucination_filter {
+            filter.apply(&mut result);
+        }
+
+        if let Some(glossary) = &self.config.glossary {
+            glossary.apply(&mut result);
+        }
+
         debug!(
             "Chunked transcript finished，real time factor: {:.2}x",
             result.real_time_factor()
@@ -405,11 +1477,122 @@ impl WhisperTranscriber {
         Ok(result)
     }
 
-    async fn transcribe_chunk_internal(
+    /// Load the checkpoint at `path` to resume from. A new one is created if it doesn't exist, or
+    /// if `TranscriptionCheckpoint::check_config` refuses it. Its chunks are only resumed for the
+    /// same audio, which is checked chunk by chunk
+    fn open_checkpoint(&self, path: &Path) -> Result<(TranscriptionCheckpoint, CheckpointStatus)> {
+        let mut status = CheckpointStatus::Created;
+
+        if path.exists() {
+            match TranscriptionCheckpoint::load(path)
+                .and_then(|checkpoint| checkpoint.check_config(&self.config).map(|_| checkpoint))
+            {
+                Ok(checkpoint) => {
+                    debug!(
+                        "Resume transcription from {}ms, {} chunks finished",
+                        checkpoint.resume_offset_ms(),
+                        checkpoint.chunks.len()
+                    );
+
+                    let chunks = checkpoint.chunks.len();
+                    return Ok((checkpoint, CheckpointStatus::Resumed { chunks }));
+                }
+                Err(e) => {
+                    warn!("Refuse to resume transcription, start over: {e}");
+                    _ = fs::remove_file(path);
+
+                    status = CheckpointStatus::Discarded {
+                        reason: e.to_string(),
+                    };
+                }
+            }
+        }
+
+        Ok((TranscriptionCheckpoint::new(&self.config)?, status))
+    }
+
+    /// Decode a chunk with the temperatures of `temperature_schedule` until the decoding is good.
+    /// If all of them fail, the decoding with the highest average log probability is used
+    fn transcribe_chunk_with_fallback(
         &self,
         samples: &[f32],
-        start_segment_index: i32,
+        previous_text: &str,
+        n_threads: i32,
+        aborted: &Arc<AtomicBool>,
     ) -> Result<TranscriptionResult> {
+        let prompt_tokens = self.chunk_prompt_tokens(previous_text)?;
+        let schedule = self.config.temperature_schedule();
+        let mut best: Option<(TranscriptionResult, DecodeQuality)> = None;
+
+        for (attempt, temperature) in schedule.iter().enumerate() {
+            let (result, quality) = self.transcribe_chunk_internal(
+                samples,
+                &prompt_tokens,
+                *temperature,
+                n_threads,
+                aborted,
+            )?;
+
+            if !quality.is_failed(&self.config) {
+                return Ok(result);
+            }
+
+            debug!(
+                "Chunk decoding failed at temperature {temperature:.1} (attempt {}/{}): avg_logprob={:.2}, entropy={:.2}, no_speech_prob={:.2}",
+                attempt + 1,
+                schedule.len(),
+                quality.avg_logprob,
+                quality.entropy,
+                quality.no_speech_prob
+            );
+
+            if best
+                .as_ref()
+                .is_none_or(|(_, q)| quality.avg_logprob > q.avg_logprob)
+            {
+                best = Some((result, quality));
+            }
+        }
+
+        let Some((result, _)) = best else {
+            bail!("No temperature to decode chunk");
+        };
+
+        Ok(result)
+    }
+
+    /// Tokens of the initial prompt followed by at most `context_tokens` tokens from the tail of
+    /// `previous_text`. Empty if there is no context to carry, then the prompt is used as is
+    fn chunk_prompt_tokens(&self, previous_text: &str) -> Result<Vec<i32>> {
+        let previous_text = previous_text.trim();
+        if self.config.context_tokens == 0 || previous_text.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let prompt = match &self.initial_prompt {
+            Some(prompt) if !prompt.trim().is_empty() => self.tokenize(prompt)?,
+            _ => vec![],
+        };
+
+        let context = self.tokenize(&format!(" {previous_text}"))?;
+        let (tokens, dropped) = fit_prompt_tokens(&prompt, &context, self.config.context_tokens);
+        if dropped > 0 {
+            warn!(
+                "Initial prompt is too long, drop its first {dropped} tokens for the chunk context"
+            );
+        }
+
+        Ok(tokens)
+    }
+
+    fn transcribe_chunk_internal(
+        &self,
+        samples: &[f32],
+        prompt_tokens: &[i32],
+        temperature: f32,
+        n_threads: i32,
+        aborted: &Arc<AtomicBool>,
+    ) -> Result<(TranscriptionResult, DecodeQuality)> {
         let chunk_duration = samples.len() as f64 / 16000.0;
         let start_time = std::time::Instant::now();
 
@@ -418,27 +1601,32 @@ impl WhisperTranscriber {
             .create_state()
             .map_err(|e| anyhow!("Create whisper state for chunk failed: {e}"))?;
 
-        let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
-        params.set_n_threads(self.config.n_threads);
+        let mut params = FullParams::new(self.config.sampling_strategy.into());
+        params.set_n_threads(n_threads);
         params.set_translate(self.config.translate);
         params.set_debug_mode(self.config.debug_mode);
-        params.set_temperature(self.config.temperature);
+        params.set_temperature(temperature);
+        params.set_entropy_thold(self.config.entropy_threshold);
+        params.set_logprob_thold(self.config.logprob_threshold);
+        params.set_no_speech_thold(self.config.no_speech_threshold);
         params.set_language(self.config.language.as_ref().map(|x| x.as_str()));
         params.set_token_timestamps(true);
+        params.set_tdrz_enable(self.config.use_tinydiarize());
+
+        // Fallback is handled per chunk, so the recorded temperature is the one really used
+        params.set_temperature_inc(0.0);
 
         // No callbacks for internal chunk processing
         params.set_progress_callback_safe(|_| {});
         params.set_segment_callback_safe(|_| {});
 
-        if let Some(path) = &self.config.vad_model_path {
-            if path.exists() {
-                params.set_vad_model_path(Some(&path.to_string_lossy().to_string()));
-                params.set_vad_params(WhisperVadParams::default());
-                params.enable_vad(true);
-            }
-        }
+        let aborted = aborted.clone();
+        params.set_abort_callback_safe(move || aborted.load(Ordering::Relaxed));
+        self.set_vad(&mut params);
 
-        if let Some(prompt) = &self.config.initial_prompt {
+        if !prompt_tokens.is_empty() {
+            params.set_tokens(prompt_tokens);
+        } else if let Some(prompt) = &self.initial_prompt {
             params.set_initial_prompt(prompt.as_str());
         }
 
@@ -446,12 +1634,15 @@ impl WhisperTranscriber {
             .full(params, samples)
             .map_err(|e| anyhow!("Whisper transcribe chunk failed: {e}"))?;
 
-        self.extract_transcription_result_with_offset(
+        let result = self.extract_transcription_result_with_offset(
             &state,
             chunk_duration,
             start_time,
-            start_segment_index,
-        )
+            0,
+            temperature,
+        )?;
+
+        Ok((result, self.calculate_decode_quality(&state)))
     }
 
     fn extract_transcription_result_with_offset(
@@ -460,6 +1651,7 @@ impl WhisperTranscriber {
         audio_duration: f64,
         start_time: std::time::Instant,
         start_segment_index: i32,
+        temperature: f32,
     ) -> Result<TranscriptionResult> {
         let audio_duration_ms = (audio_duration * 1000.0) as u64;
 
@@ -482,6 +1674,7 @@ impl WhisperTranscriber {
             let start_time_ms = (segment.start_timestamp() as u64) * 10;
             let end_time_ms = (segment.end_timestamp() as u64) * 10;
             let confidence = self.calculate_segment_confidence(state, i)?;
+            let words = self.extract_segment_words(state, i, start_time_ms, end_time_ms);
 
             segments.push(TranscriptionSegment {
                 index: start_segment_index + i as i32 + 1,
@@ -489,6 +1682,11 @@ impl WhisperTranscriber {
                 end_time: end_time_ms,
                 text: segment_text.clone(),
                 confidence,
+                words,
+                temperature,
+                speaker_turn_next: segment.next_segment_speaker_turn(),
+                speaker: None,
+                no_speech_prob: segment.no_speech_probability(),
             });
 
             if !full_text.is_empty() {
@@ -500,193 +1698,96 @@ impl WhisperTranscriber {
         let processing_time = start_time.elapsed().as_millis() as u64;
         Ok(TranscriptionResult {
             text: full_text,
-            language: self.config.language.clone(),
+            language: self.result_language(state),
             segments,
             processing_time,
             audio_duration: audio_duration_ms,
+            hallucinations: vec![],
+            glossary_replacements: vec![],
+            chunks: vec![],
+            checkpoint: None,
         })
     }
 
-    fn prepare_audio_samples(&self, audio_data: &AudioData) -> Result<Vec<f32>> {
-        let mut samples = audio_data.samples.clone();
-
-        if audio_data.config.channels > 1 {
-            let mono_data = audio_data.to_mono();
-            samples = mono_data.samples;
-            debug!("Finished converting to mono channel");
-        }
-
-        if audio_data.config.sample_rate != 16000 {
-            bail!(
-                "Not compatible with whisper. Actual sample rate {}, expect 16kHz",
-                audio_data.config.sample_rate
-            );
+    // `audio_data` is the audio before it is mixed into mono, so stereo channels are still there.
+    // None if the audio was decoded into mono by ffmpeg
+    fn assign_speakers(
+        &self,
+        audio_data: Option<&AudioData>,
+        segments: &mut [TranscriptionSegment],
+    ) -> Result<()> {
+        match self.config.speaker_detection {
+            SpeakerDetection::None => (),
+            SpeakerDetection::TinyDiarize => speaker::assign_turn_speakers(segments),
+            SpeakerDetection::StereoChannel => match audio_data {
+                Some(audio_data) => speaker::assign_stereo_speakers(audio_data, segments)?,
+                None => {
+                    bail!("Stereo speaker detection needs the audio before it is mixed into mono")
+                }
+            },
+            SpeakerDetection::Auto => {
+                if self.config.use_tinydiarize() {
+                    speaker::assign_turn_speakers(segments);
+                } else if let Some(audio_data) = audio_data
+                    && audio_data.config.channels == 2
+                {
+                    speaker::assign_stereo_speakers(audio_data, segments)?;
+                } else {
+                    debug!("No tdrz model or stereo audio, speakers are not detected");
+                }
+            }
         }
 
-        Ok(samples)
+        Ok(())
     }
 
-    /// Split audio data into chunks for chunked transcription to avoid timestamp drift
-    /// Uses intelligent silence detection to split at natural pause points rather than cutting speech
-    fn split_audio_into_chunks(&self, audio_data: &AudioData) -> Vec<AudioChunk> {
-        let chunk_length_ms = self.config.chunk_length_ms.unwrap_or(60000);
-        let overlap_ms = self.config.chunk_overlap_ms.unwrap_or(1000);
-
-        let sample_rate = audio_data.config.sample_rate as f64;
-        let total_samples = audio_data.samples.len();
-        let total_duration_ms = (total_samples as f64 / sample_rate) * 1000.0;
-
-        // If audio is shorter than chunk length, return single chunk
-        if total_duration_ms <= chunk_length_ms as f64 {
-            debug!(
-                "Audio duration {:.2}s is shorter than chunk length {:.2}s, using single chunk",
-                total_duration_ms / 1000.0,
-                chunk_length_ms as f64 / 1000.0
-            );
-            return vec![AudioChunk {
-                samples: audio_data.samples.clone(),
-                start_offset_ms: 0,
-            }];
-        }
-
-        let chunk_samples = (chunk_length_ms as f64 * sample_rate / 1000.0) as usize;
-        let overlap_samples = (overlap_ms as f64 * sample_rate / 1000.0) as usize;
-
-        let mut chunks = Vec::new();
-        let mut current_start = 0;
-        let mut chunk_index = 0;
-
-        while current_start < total_samples {
-            // Default chunk end at target length
-            let ideal_chunk_end = std::cmp::min(current_start + chunk_samples, total_samples);
-
-            // Try to find a better split point using silence detection
-            let (chunk_end, is_split_in_slient_point) = if ideal_chunk_end < total_samples {
-                self.find_silence_split_point(
-                    &audio_data.samples,
-                    ideal_chunk_end,
-                    sample_rate,
-                    30_000, // Search up to 30 seconds forward
-                )
-            } else {
-                (ideal_chunk_end, false)
-            };
-
-            let chunk_samples_data = audio_data.samples[current_start..chunk_end].to_vec();
-            let offset_ms = ((current_start as f64 / sample_rate) * 1000.0) as u64;
-
-            chunks.push(AudioChunk {
-                samples: chunk_samples_data,
-                start_offset_ms: offset_ms,
-            });
-
-            debug!(
-                "Created chunk {}: start={}, end={}, offset={:.2}s, samples={}, duration={:.2}s",
-                chunk_index,
-                current_start,
-                chunk_end,
-                offset_ms as f32 / 1000.0,
-                chunk_end - current_start,
-                (chunk_end - current_start) as f64 / sample_rate
-            );
-
-            chunk_index += 1;
-            current_start = if is_split_in_slient_point {
-                chunk_end
-            } else {
-                chunk_end - overlap_samples // Move to next chunk with overlap
-            };
-
-            if current_start >= total_samples - overlap_samples {
-                break;
-            }
+    // The configured language, or the language whisper detected when it is auto
+    fn result_language(&self, state: &WhisperState) -> Option<String> {
+        if !self.config.is_auto_language() {
+            return self.config.language.clone();
         }
 
-        debug!(
-            "Split audio into {} chunks using silence detection",
-            chunks.len()
-        );
-        chunks
+        whisper_rs::get_lang_str(state.full_lang_id_from_state()).map(|lang| lang.to_string())
     }
 
-    fn find_silence_split_point(
-        &self,
-        samples: &[f32],
-        target_end: usize,
-        sample_rate: f64,
-        max_search_ms: u64,
-    ) -> (usize, bool) {
-        let max_search_samples = (max_search_ms as f64 * sample_rate / 1000.0) as usize;
-        let search_end = std::cmp::min(target_end + max_search_samples, samples.len());
-
-        let search_samples = &samples[target_end..search_end];
-        if search_samples.is_empty() {
-            debug!("No samples to search for silence, using target split position");
-            return (target_end, false);
+    // Skip the non-speech parts with the silero VAD of whisper.cpp if `vad_model_path` is set.
+    // The path is checked by `WhisperConfig::validate`
+    fn set_vad(&self, params: &mut FullParams) {
+        if let Some(path) = &self.config.vad_model_path {
+            params.set_vad_model_path(Some(&path.to_string_lossy().to_string()));
+            params.set_vad_params(self.config.vad_params.into());
+            params.enable_vad(true);
         }
+    }
 
-        // Create VAD with adaptive threshold based on the search region
-        let rms_threshold = EnergyVAD::calculate_rms(search_samples) * 0.5; // Use 50% of RMS as threshold
-
-        let vad = EnergyVAD::new(sample_rate as u32)
-            .with_threshold(rms_threshold)
-            .with_frame_size_ms(200)
-            .with_frame_shift_ms(100);
-
-        let frame_size = (sample_rate * vad.frame_size_ms as f64 / 1000.0) as usize;
-        let frame_shift = (sample_rate * vad.frame_shift_ms as f64 / 1000.0) as usize;
-
-        // Track silence segments
-        let mut silence_start_offset: Option<usize> = None;
-        let min_silence_duration_ms = 500u64; // Require 500ms of silence
-        let min_silence_samples = (min_silence_duration_ms as f64 * sample_rate / 1000.0) as usize;
-
-        for (offset, _) in (0..search_samples.len()).step_by(frame_shift).enumerate() {
-            let frame_offset = offset * frame_shift;
-            let frame_end = std::cmp::min(frame_offset + frame_size, search_samples.len());
-
-            if frame_offset >= search_samples.len() {
-                break;
-            }
-
-            let frame = &search_samples[frame_offset..frame_end];
-            let has_speech = vad.contain_speech(frame);
+    // 16kHz mono samples processed by `config.preprocess`
+    fn prepare_audio_samples(&self, audio_data: &AudioData) -> Result<Vec<f32>> {
+        let mut audio_data = if audio_data.is_whisper_compatible() {
+            audio_data.clone()
+        } else {
+            let audio_data = audio_data.to_whisper_compatible()?;
+            debug!("Finished converting to 16kHz mono channel");
+            audio_data
+        };
 
-            if !has_speech {
-                if silence_start_offset.is_none() {
-                    silence_start_offset = Some(frame_offset);
-                }
-            } else {
-                // Found speech, check if we had enough silence before it
-                if let Some(start_offset) = silence_start_offset {
-                    let silence_duration_samples = frame_offset - start_offset;
-                    if silence_duration_samples >= min_silence_samples {
-                        // Found a suitable split point at the middle of the silence
-                        let split_offset = start_offset + silence_duration_samples / 2;
-                        let split_pos = target_end + split_offset;
+        if let Some(preprocess) = &self.config.preprocess {
+            preprocess.apply(&mut audio_data)?;
+        }
 
-                        let silence_duration_ms =
-                            (silence_duration_samples as f64 / sample_rate * 1000.0) as u64;
+        Ok(audio_data.samples)
+    }
 
-                        debug!(
-                            "Found silence split point at {:.2}s ({}ms silence, {} samples)",
-                            split_pos as f64 / sample_rate,
-                            silence_duration_ms,
-                            silence_duration_samples
-                        );
+    // Chunk samples processed by `config.preprocess`. Each chunk is processed on its own, so
+    // the chunk splits are searched in the audio before preprocessing
+    fn prepare_chunk_samples(&self, samples: Vec<f32>) -> Result<Vec<f32>> {
+        let Some(preprocess) = &self.config.preprocess else {
+            return Ok(samples);
+        };
 
-                        return (std::cmp::min(split_pos, samples.len()), true);
-                    }
-                }
-                silence_start_offset = None;
-            }
-        }
+        let mut audio_data = AudioData::new(samples, AudioConfig::whisper_optimized());
+        preprocess.apply(&mut audio_data)?;
 
-        debug!(
-            "No suitable silence found, using target split position at {:.2}s",
-            target_end as f64 / sample_rate
-        );
-        (target_end, false)
+        Ok(audio_data.samples)
     }
 
     fn extract_transcription_result(
@@ -716,6 +1817,7 @@ impl WhisperTranscriber {
             let start_time = (segment.start_timestamp() as u64) * 10;
             let end_time = (segment.end_timestamp() as u64) * 10;
             let confidence = self.calculate_segment_confidence(state, i)?;
+            let words = self.extract_segment_words(state, i, start_time, end_time);
 
             segments.push(TranscriptionSegment {
                 index: i as i32 + 1,
@@ -723,6 +1825,11 @@ impl WhisperTranscriber {
                 end_time,
                 text: segment_text.clone(),
                 confidence,
+                words,
+                temperature: self.config.temperature,
+                speaker_turn_next: segment.next_segment_speaker_turn(),
+                speaker: None,
+                no_speech_prob: segment.no_speech_probability(),
             });
 
             if !full_text.is_empty() {
@@ -734,10 +1841,14 @@ impl WhisperTranscriber {
         let processing_time = start_time.elapsed().as_millis() as u64;
         Ok(TranscriptionResult {
             text: full_text,
-            language: self.config.language.clone(),
+            language: self.result_language(state),
             segments,
             processing_time,
             audio_duration: audio_duration_ms,
+            hallucinations: vec![],
+            glossary_replacements: vec![],
+            chunks: vec![],
+            checkpoint: None,
         })
     }
 
@@ -771,19 +1882,354 @@ impl WhisperTranscriber {
             Ok(0.5)
         }
     }
-}
 
-pub fn convert_to_compatible_audio(
-    input: impl AsRef<Path>,
-    output: impl AsRef<Path>,
-    cancel: Arc<AtomicBool>,
-    progress_cb: impl FnMut(i32) + 'static,
-) -> Result<()> {
-    is_valid_aduio_file(&output)?;
-    ffmpeg::convert_to_whisper_compatible_audio(&input, &output, cancel, progress_cb)?;
-    wav::is_whisper_compatible(&output)?;
+    /// Average log probability and the lowest entropy of every 32 text tokens,
+    /// the same measures as the reference whisper decoder
+    fn calculate_decode_quality(&self, state: &WhisperState) -> DecodeQuality {
+        const ENTROPY_WINDOW: usize = 32;
 
-    Ok(())
+        let token_eot = self.context.token_eot();
+        let (mut token_ids, mut total_logprob) = (vec![], 0.0);
+        let mut no_speech_prob = 0.0f32;
+
+        for segment in state.as_iter() {
+            no_speech_prob = no_speech_prob.max(segment.no_speech_probability());
+
+            for token_index in 0..segment.n_tokens() {
+                let Some(token) = segment.get_token(token_index) else {
+                    continue;
+                };
+
+                if token.token_id() >= token_eot {
+                    continue;
+                }
+
+                token_ids.push(token.token_id());
+                total_logprob += token.token_data().plog;
+            }
+        }
+
+        if token_ids.is_empty() {
+            return DecodeQuality {
+                avg_logprob: 0.0,
+                entropy: f32::MAX,
+                no_speech_prob,
+            };
+        }
+
+        // Short output can't be judged as repetitive
+        let entropy = if token_ids.len() > ENTROPY_WINDOW {
+            token_ids
+                .windows(ENTROPY_WINDOW)
+                .step_by(ENTROPY_WINDOW / 2)
+                .map(token_entropy)
+                .fold(f32::MAX, f32::min)
+        } else {
+            f32::MAX
+        };
+
+        DecodeQuality {
+            avg_logprob: total_logprob / token_ids.len() as f32,
+            entropy,
+            no_speech_prob,
+        }
+    }
+
+    /// Build word level timestamps from the text tokens of a segment.
+    /// Token timestamps are clamped into the segment time range
+    fn extract_segment_words(
+        &self,
+        state: &WhisperState,
+        segment_index: i32,
+        segment_start_ms: u64,
+        segment_end_ms: u64,
+    ) -> Vec<Word> {
+        let Some(segment) = state.get_segment(segment_index) else {
+            return vec![];
+        };
+
+        // Special tokens (eot, sot, language, timestamps...) have ids starting from eot
+        let token_eot = self.context.token_eot();
+
+        let mut pieces = vec![];
+        for token_index in 0..segment.n_tokens() {
+            let Some(token) = segment.get_token(token_index) else {
+                continue;
+            };
+
+            if token.token_id() >= token_eot {
+                continue;
+            }
+
+            let Ok(bytes) = token.to_bytes() else {
+                continue;
+            };
+
+            let data = token.token_data();
+            let start_time = ((data.t0.max(0) as u64) * 10).clamp(segment_start_ms, segment_end_ms);
+            let end_time = ((data.t1.max(0) as u64) * 10).clamp(start_time, segment_end_ms);
+
+            pieces.push(TokenPiece {
+                bytes: bytes.to_vec(),
+                start_time,
+                end_time,
+                probability: data.p,
+            });
+        }
+
+        group_tokens_into_words(&pieces)
+    }
+}
+
+/// Merge BPE tokens into words. A token starting with whitespace begins a new word,
+/// and every character of scripts written without spaces (CJK, Thai...) is a word.
+/// Bytes are accumulated before decoding because a multi-byte character may be
+/// split across tokens
+fn group_tokens_into_words(pieces: &[TokenPiece]) -> Vec<Word> {
+    fn push_word(words: &mut Vec<Word>, bytes: &[u8], pieces: &[&TokenPiece]) {
+        let text = String::from_utf8_lossy(bytes).trim().to_string();
+        if text.is_empty() || pieces.is_empty() {
+            return;
+        }
+
+        words.push(Word {
+            text,
+            start_time: pieces[0].start_time,
+            end_time: pieces[pieces.len() - 1].end_time,
+            probability: pieces.iter().map(|p| p.probability).sum::<f32>() / pieces.len() as f32,
+        });
+    }
+
+    let mut words = vec![];
+    let mut bytes: Vec<u8> = vec![];
+    let mut word_pieces: Vec<&TokenPiece> = vec![];
+
+    for piece in pieces {
+        let starts_with_space = piece.bytes.first().is_some_and(|b| b.is_ascii_whitespace());
+
+        let is_boundary = starts_with_space
+            || match (
+                std::str::from_utf8(&bytes),
+                std::str::from_utf8(&piece.bytes),
+            ) {
+                (Ok(current), Ok(next)) => {
+                    current.chars().last().is_some_and(is_spaceless_script)
+                        || next.chars().next().is_some_and(is_spaceless_script)
+                }
+                _ => false,
+            };
+
+        if is_boundary && !word_pieces.is_empty() {
+            push_word(&mut words, &bytes, &word_pieces);
+            bytes.clear();
+            word_pieces.clear();
+        }
+
+        bytes.extend_from_slice(&piece.bytes);
+        word_pieces.push(piece);
+    }
+
+    push_word(&mut words, &bytes, &word_pieces);
+    words
+}
+
+// Move the segment and its words from chunk time to audio time
+/// At most `context_tokens` tokens from the tail of `context` are reserved first, and `prompt`
+/// keeps the tokens of its tail that fit in the rest, as whisper.cpp does for a long prompt.
+/// Returns the tokens and the number of prompt tokens dropped
+fn fit_prompt_tokens(prompt: &[i32], context: &[i32], context_tokens: usize) -> (Vec<i32>, usize) {
+    let n_context = context_tokens.min(MAX_PROMPT_TOKENS).min(context.len());
+    let n_prompt = prompt.len().min(MAX_PROMPT_TOKENS - n_context);
+
+    let mut tokens = prompt[prompt.len() - n_prompt..].to_vec();
+    tokens.extend_from_slice(&context[context.len() - n_context..]);
+    (tokens, prompt.len() - n_prompt)
+}
+
+fn offset_segment(segment: TranscriptionSegment, offset_ms: u64) -> TranscriptionSegment {
+    TranscriptionSegment {
+        start_time: segment.start_time + offset_ms,
+        end_time: segment.end_time + offset_ms,
+        words: segment
+            .words
+            .into_iter()
+            .map(|word| Word {
+                start_time: word.start_time + offset_ms,
+                end_time: word.end_time + offset_ms,
+                ..word
+            })
+            .collect(),
+        ..segment
+    }
+}
+
+/// Keep one copy of the segments transcribed by both chunks in their overlap. `previous`
+/// are the segments of the previous chunk inside the overlap and `next` all segments of the
+/// next chunk, both with absolute timestamps. Two segments are the same if they overlap in
+/// time and their texts are similar, or one text contains the other because it was cut at
+/// the chunk boundary. The longer text wins a containment, otherwise the copy farther away
+/// from its chunk boundary wins because whisper has more context around it. A speaker turn
+/// after either copy is kept, the copy cut at the chunk end can't see the next speaker
+fn reconcile_overlap_segments(
+    previous: Vec<TranscriptionSegment>,
+    next: Vec<TranscriptionSegment>,
+    previous_chunk_end_ms: u64,
+    next_chunk_start_ms: u64,
+) -> Vec<TranscriptionSegment> {
+    const MIN_TIME_OVERLAP: f32 = 0.3;
+    const MIN_TEXT_SIMILARITY: f32 = 0.6;
+
+    let mut previous = previous.into_iter().map(Some).collect::<Vec<_>>();
+    let mut output = vec![];
+
+    for segment in next {
+        if segment.start_time >= previous_chunk_end_ms {
+            output.push(segment);
+            continue;
+        }
+
+        let text = normalize_text(&segment.text);
+        let duplicate = previous.iter().position(|item| {
+            let Some(item) = item else {
+                return false;
+            };
+
+            let item_text = normalize_text(&item.text);
+            if text.is_empty() || item_text.is_empty() {
+                return false;
+            }
+
+            time_overlap_ratio(item, &segment) >= MIN_TIME_OVERLAP
+                && (text_similarity(&item_text, &text) >= MIN_TEXT_SIMILARITY
+                    || item_text.contains(&text)
+                    || text.contains(&item_text))
+        });
+
+        let Some(duplicate) = duplicate else {
+            output.push(segment);
+            continue;
+        };
+
+        let item = previous[duplicate].take().unwrap();
+        let (item_len, len) = (
+            normalize_text(&item.text).chars().count(),
+            text.chars().count(),
+        );
+
+        let keep_previous = if item_len > len * 3 / 2 || len > item_len * 3 / 2 {
+            item_len > len
+        } else {
+            let previous_margin = previous_chunk_end_ms.saturating_sub(item.end_time);
+            let next_margin = segment.start_time.saturating_sub(next_chunk_start_ms);
+            previous_margin > next_margin
+                || (previous_margin == next_margin && item.confidence >= segment.confidence)
+        };
+
+        debug!(
+            "Remove duplicated overlap segment: {}",
+            if keep_previous {
+                &segment.text
+            } else {
+                &item.text
+            }
+        );
+
+        let speaker_turn_next = item.speaker_turn_next || segment.speaker_turn_next;
+        let mut kept = if keep_previous { item } else { segment };
+        kept.speaker_turn_next = speaker_turn_next;
+        output.push(kept);
+    }
+
+    output.extend(previous.into_iter().flatten());
+    output.sort_by_key(|s| s.start_time);
+    output
+}
+
+// Lowercase letters and digits only, so punctuation and spacing don't matter
+pub(crate) fn normalize_text(text: &str) -> String {
+    text.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(|c| c.to_lowercase())
+        .collect()
+}
+
+// Intersection of the two time ranges divided by the shorter duration
+fn time_overlap_ratio(a: &TranscriptionSegment, b: &TranscriptionSegment) -> f32 {
+    let start = a.start_time.max(b.start_time);
+    let end = a.end_time.min(b.end_time);
+    let shorter = (a.end_time - a.start_time)
+        .min(b.end_time - b.start_time)
+        .max(1);
+
+    end.saturating_sub(start) as f32 / shorter as f32
+}
+
+// 1.0 - levenshtein distance / length of the longer text
+fn text_similarity(a: &str, b: &str) -> f32 {
+    let (a, b) = (a.chars().collect::<Vec<_>>(), b.chars().collect::<Vec<_>>());
+    if a.is_empty() && b.is_empty() {
+        return 1.0;
+    }
+
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for i in 1..=a.len() {
+        let mut diagonal = row[0];
+        row[0] = i;
+
+        for j in 1..=b.len() {
+            let above = row[j];
+            row[j] = if a[i - 1] == b[j - 1] {
+                diagonal
+            } else {
+                1 + diagonal.min(above).min(row[j - 1])
+            };
+            diagonal = above;
+        }
+    }
+
+    1.0 - row[b.len()] as f32 / a.len().max(b.len()) as f32
+}
+
+fn token_entropy<T: Ord>(tokens: &[T]) -> f32 {
+    let mut counts = std::collections::BTreeMap::new();
+    for token in tokens {
+        *counts.entry(token).or_insert(0usize) += 1;
+    }
+
+    counts
+        .values()
+        .map(|count| {
+            let p = *count as f32 / tokens.len() as f32;
+            -p * p.ln()
+        })
+        .sum()
+}
+
+fn is_spaceless_script(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30FF}' // Hiragana, Katakana
+        | '\u{3400}'..='\u{4DBF}' // CJK Extension A
+        | '\u{4E00}'..='\u{9FFF}' // CJK Unified Ideographs
+        | '\u{F900}'..='\u{FAFF}' // CJK Compatibility Ideographs
+        | '\u{0E00}'..='\u{0EFF}' // Thai, Lao
+        | '\u{1000}'..='\u{109F}' // Myanmar
+        | '\u{1780}'..='\u{17FF}' // Khmer
+    )
+}
+
+/// Convert the selected audio stream and channel of any media file into a 16kHz mono wav file
+pub fn convert_to_compatible_audio(
+    input: impl AsRef<Path>,
+    output: impl AsRef<Path>,
+    selection: ffmpeg::AudioSelection,
+    cancel: Arc<AtomicBool>,
+    progress_cb: impl FnMut(i32) + 'static,
+) -> Result<()> {
+    is_valid_aduio_file(&output)?;
+    ffmpeg::convert_to_whisper_compatible_audio(&input, &output, selection, cancel, progress_cb)?;
+    wav::is_whisper_compatible(&output)?;
+
+    Ok(())
 }
 
 pub async fn transcribe_file(
@@ -799,6 +2245,57 @@ pub async fn transcribe_file(
         .await
 }
 
+pub async fn transcribe_media(
+    config: WhisperConfig,
+    media_path: impl AsRef<Path>,
+    selection: ffmpeg::AudioSelection,
+    progress_cb: impl FnMut(i32) + 'static,
+    segmemnt_cb: impl FnMut(SegmentCallbackData) + 'static,
+    abort_cb: impl FnMut() -> bool + 'static,
+) -> Result<TranscriptionResult> {
+    let transcriber = WhisperTranscriber::new(config)?;
+    transcriber
+        .transcribe_media(media_path, selection, progress_cb, segmemnt_cb, abort_cb)
+        .await
+}
+
+pub async fn transcribe_file_range(
+    config: WhisperConfig,
+    audio_path: impl AsRef<Path>,
+    start_ms: u64,
+    end_ms: u64,
+    progress_cb: impl FnMut(i32) + 'static,
+    segmemnt_cb: impl FnMut(SegmentCallbackData) + 'static,
+    abort_cb: impl FnMut() -> bool + 'static,
+) -> Result<TranscriptionResult> {
+    is_valid_aduio_file(&audio_path)?;
+
+    let audio_data = wav::read_file(&audio_path)?;
+    let transcriber = WhisperTranscriber::new(config)?;
+    transcriber
+        .transcribe_range(
+            &audio_data,
+            start_ms,
+            end_ms,
+            progress_cb,
+            segmemnt_cb,
+            abort_cb,
+        )
+        .await
+}
+
+pub fn detect_language(
+    config: WhisperConfig,
+    audio_path: impl AsRef<Path>,
+    sample_window_ms: u64,
+) -> Result<Vec<(WhisperLang, f32)>> {
+    is_valid_aduio_file(&audio_path)?;
+
+    let audio_data = wav::read_file(&audio_path)?;
+    let transcriber = WhisperTranscriber::new(config)?;
+    transcriber.detect_language(&audio_data, sample_window_ms)
+}
+
 pub fn save_ggml_silero_vad_model(path: impl AsRef<Path>) -> Result<()> {
     let path = path.as_ref();
     fs::write(&path, GGML_SILERO_VAD_MODEL)
@@ -807,6 +2304,75 @@ pub fn save_ggml_silero_vad_model(path: impl AsRef<Path>) -> Result<()> {
     Ok(())
 }
 
+/// Speech regions (start_ms, end_ms) of the audio found by the bundled silero model, without
+/// transcribing it. Cheaper than decoding and more robust to noise than `EnergyVAD`
+pub fn silero_speech_segments(
+    audio_data: &AudioData,
+    vad_params: &VadParams,
+) -> Result<Vec<(u64, u64)>> {
+    vad_params.validate()?;
+
+    let compatible_audio;
+    let samples = if audio_data.is_whisper_compatible() {
+        &audio_data.samples
+    } else {
+        compatible_audio = audio_data.to_whisper_compatible()?;
+        &compatible_audio.samples
+    };
+
+    let mut params = WhisperVadContextParams::default();
+    params.set_n_threads(num_cpus::get().min(4) as i32);
+
+    let model_path = bundled_silero_vad_model_path()?;
+    let mut context = WhisperVadContext::new(model_path.to_string_lossy().as_ref(), params)
+        .map_err(|e| anyhow!("Load silero vad model error: {e}"))?;
+
+    let segments = context
+        .segments_from_samples((*vad_params).into(), samples)
+        .map_err(|e| anyhow!("Silero detect speech failed: {e}"))?;
+
+    // Timestamps of whisper.cpp are in centiseconds
+    Ok(segments
+        .map(|segment| {
+            (
+                (segment.start.max(0.0) * 10.0) as u64,
+                (segment.end.max(0.0) * 10.0) as u64,
+            )
+        })
+        .collect())
+}
+
+/// Path of the bundled silero model, e.g. for `WhisperConfig::with_vad_model_path`.
+/// whisper.cpp loads models from files, so the bundled model is written to the temp directory
+// Other processes share the file, so it is written to a unique file and renamed into place, and
+// a file that isn't the bundled model, e.g. left by a crash, is written again
+pub fn bundled_silero_vad_model_path() -> Result<PathBuf> {
+    let path = std::env::temp_dir().join("whispercap-ggml-silero-v5.1.2.bin");
+    let is_bundled_model =
+        |path: &Path| fs::read(path).is_ok_and(|data| data == GGML_SILERO_VAD_MODEL);
+    if is_bundled_model(&path) {
+        return Ok(path);
+    }
+
+    let tmp_path = path.with_extension(format!(
+        "{}.{}.tmp",
+        std::process::id(),
+        VAD_MODEL_WRITES.fetch_add(1, Ordering::Relaxed)
+    ));
+    save_ggml_silero_vad_model(&tmp_path)?;
+
+    if let Err(e) = fs::rename(&tmp_path, &path) {
+        _ = fs::remove_file(&tmp_path);
+
+        // Another process may have just put the model in place
+        if !is_bundled_model(&path) {
+            return Err(e).with_context(|| format!("save {} failed", path.display()));
+        }
+    }
+
+    Ok(path)
+}
+
 fn is_valid_aduio_file(audio_path: impl AsRef<Path>) -> Result<()> {
     if !audio_path
         .as_ref()
@@ -820,3 +2386,822 @@ fn is_valid_aduio_file(audio_path: impl AsRef<Path>) -> Result<()> {
 
     Ok(())
 }
+
+#[derive(Clone, Debug)]
+pub enum StreamingVad {
+    // A frame is speech if its RMS energy is above `threshold`. Cheap but sensitive to noise
+    Energy {
+        threshold: f32,
+    },
+
+    // A frame is speech if its silero speech probability is above `threshold`.
+    // `model_path` None uses the bundled model
+    Silero {
+        model_path: Option<PathBuf>,
+        threshold: f32,
+    },
+}
+
+impl Default for StreamingVad {
+    fn default() -> Self {
+        Self::Energy { threshold: 0.02 }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct StreamingConfig {
+    pub vad: StreamingVad,
+    pub min_speech_ms: u64, // Windows with less speech are dropped as noise, default 250
+    pub min_silence_ms: u64, // Silence closing a window, default 500
+    pub max_window_ms: u64, // A window is closed at this length even in speech, default 30000
+    pub speech_pad_ms: u64, // Audio kept before the speech starts, default 200
+
+    // Interval of provisional decoding of the open window, 0 disables provisional results
+    pub provisional_interval_ms: u64, // default 1000
+}
+
+impl Default for StreamingConfig {
+    fn default() -> Self {
+        Self {
+            vad: StreamingVad::default(),
+            min_speech_ms: 250,
+            min_silence_ms: 500,
+            max_window_ms: 30_000,
+            speech_pad_ms: 200,
+            provisional_interval_ms: 1000,
+        }
+    }
+}
+
+impl StreamingConfig {
+    pub fn with_vad(mut self, vad: StreamingVad) -> Self {
+        self.vad = vad;
+        self
+    }
+
+    pub fn with_min_speech_ms(mut self, ms: u64) -> Self {
+        self.min_speech_ms = ms;
+        self
+    }
+
+    pub fn with_min_silence_ms(mut self, ms: u64) -> Self {
+        self.min_silence_ms = ms;
+        self
+    }
+
+    pub fn with_max_window_ms(mut self, ms: u64) -> Self {
+        self.max_window_ms = ms;
+        self
+    }
+
+    pub fn with_speech_pad_ms(mut self, ms: u64) -> Self {
+        self.speech_pad_ms = ms;
+        self
+    }
+
+    pub fn with_provisional_interval_ms(mut self, ms: u64) -> Self {
+        self.provisional_interval_ms = ms;
+        self
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.min_silence_ms == 0 {
+            bail!("min_silence_ms is 0");
+        }
+
+        if self.max_window_ms <= self.min_silence_ms {
+            bail!("max_window_ms should be greater than min_silence_ms");
+        }
+
+        if let StreamingVad::Silero {
+            model_path: Some(path),
+            ..
+        } = &self.vad
+        {
+            if !path.exists() {
+                bail!("No found vad model path: {}", path.display());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum StreamingEvent {
+    // Hypothesis of the speech window that is still open. It is replaced by the next
+    // `Provisional` or `Final` event of the same window
+    Provisional {
+        window: usize,
+        segments: Vec<TranscriptionSegment>,
+    },
+
+    // Transcription of a closed speech window. It doesn't change anymore and its segment
+    // indexes continue from the previous window
+    Final {
+        window: usize,
+        segments: Vec<TranscriptionSegment>,
+    },
+}
+
+/// Transcribe 16kHz mono samples as they arrive, e.g. from a microphone. The samples are cut
+/// into speech windows by a VAD and every window is decoded on a worker thread. Events are sent
+/// to the receiver returned by `new`, with timestamps relative to the first pushed sample
+pub struct StreamingTranscriber {
+    sender: Option<mpsc::Sender<Vec<f32>>>,
+    worker: Option<thread::JoinHandle<Result<()>>>,
+    aborted: Arc<AtomicBool>,
+}
+
+impl StreamingTranscriber {
+    pub fn new(
+        config: WhisperConfig,
+        streaming_config: StreamingConfig,
+    ) -> Result<(Self, mpsc::Receiver<StreamingEvent>)> {
+        streaming_config.validate()?;
+
+        let transcriber = WhisperTranscriber::new(config)?;
+        let aborted = Arc::new(AtomicBool::new(false));
+        let (sender, samples_rx) = mpsc::channel();
+        let (events_tx, receiver) = mpsc::channel();
+
+        let worker_aborted = aborted.clone();
+        let worker = thread::spawn(move || {
+            run_streaming_worker(
+                transcriber,
+                streaming_config,
+                samples_rx,
+                events_tx,
+                worker_aborted,
+            )
+        });
+
+        Ok((
+            Self {
+                sender: Some(sender),
+                worker: Some(worker),
+                aborted,
+            },
+            receiver,
+        ))
+    }
+
+    pub fn push_samples(&self, samples: &[f32]) -> Result<()> {
+        let Some(sender) = &self.sender else {
+            bail!("Streaming transcriber is finished");
+        };
+
+        sender
+            .send(samples.to_vec())
+            .map_err(|_| anyhow!("Streaming transcriber worker exited"))
+    }
+
+    /// Decode the pending window and wait for the worker to exit
+    pub fn finish(mut self) -> Result<()> {
+        self.sender.take();
+
+        match self.worker.take() {
+            Some(worker) => worker
+                .join()
+                .map_err(|_| anyhow!("Streaming transcriber worker panicked"))?,
+            None => Ok(()),
+        }
+    }
+
+    /// Stop decoding as soon as possible. Pending samples are dropped
+    pub fn abort(&self) {
+        self.aborted.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Drop for StreamingTranscriber {
+    fn drop(&mut self) {
+        self.abort();
+        self.sender.take();
+
+        if let Some(worker) = self.worker.take() {
+            _ = worker.join();
+        }
+    }
+}
+
+fn run_streaming_worker(
+    transcriber: WhisperTranscriber,
+    streaming_config: StreamingConfig,
+    samples_rx: mpsc::Receiver<Vec<f32>>,
+    events_tx: mpsc::Sender<StreamingEvent>,
+    aborted: Arc<AtomicBool>,
+) -> Result<()> {
+    // The silero context can't be sent to another thread, so it is created here
+    let detector = SpeechDetector::new(&streaming_config.vad)?;
+    let mut windower = SpeechWindower::new(streaming_config, detector);
+
+    let n_threads = transcriber.config.n_threads;
+    let (mut window, mut segment_index) = (0, 0);
+    let mut previous_text = String::new();
+
+    loop {
+        let (mut actions, mut finished) = match samples_rx.recv() {
+            Ok(samples) => (windower.push(&samples)?, false),
+            Err(_) => (vec![], true),
+        };
+
+        // Take all queued samples at once, so slow decoding doesn't fall further behind
+        while !finished {
+            match samples_rx.try_recv() {
+                Ok(samples) => actions.extend(windower.push(&samples)?),
+                Err(mpsc::TryRecvError::Empty) => break,
+                Err(mpsc::TryRecvError::Disconnected) => finished = true,
+            }
+        }
+
+        if finished {
+            actions.extend(windower.flush()?);
+        }
+
+        // Only the newest provisional result is still useful
+        let n_actions = actions.len();
+        for (i, action) in actions.into_iter().enumerate() {
+            if aborted.load(Ordering::Relaxed) {
+                return Ok(());
+            }
+
+            let event = match action {
+                WindowAction::Provisional {
+                    start_sample,
+                    samples,
+                } => {
+                    if i + 1 < n_actions {
+                        continue;
+                    }
+
+                    let (result, _) = transcriber.transcribe_chunk_internal(
+                        &samples,
+                        &[],
+                        transcriber.config.temperature,
+                        n_threads,
+                        &aborted,
+                    )?;
+
+                    let offset_ms = start_sample * 1000 / STREAMING_SAMPLE_RATE as u64;
+                    StreamingEvent::Provisional {
+                        window,
+                        segments: result
+                            .segments
+                            .into_iter()
+                            .map(|segment| offset_segment(segment, offset_ms))
+                            .collect(),
+                    }
+                }
+                WindowAction::Final {
+                    start_sample,
+                    samples,
+                } => {
+                    let result = transcriber.transcribe_chunk_with_fallback(
+                        &samples,
+                        &previous_text,
+                        n_threads,
+                        &aborted,
+                    )?;
+
+                    previous_text = result.text;
+
+                    let offset_ms = start_sample * 1000 / STREAMING_SAMPLE_RATE as u64;
+                    let segments = result
+                        .segments
+                        .into_iter()
+                        .map(|segment| {
+                            segment_index += 1;
+                            TranscriptionSegment {
+                                index: segment_index,
+                                ..offset_segment(segment, offset_ms)
+                            }
+                        })
+                        .collect();
+
+                    window += 1;
+                    StreamingEvent::Final {
+                        window: window - 1,
+                        segments,
+                    }
+                }
+            };
+
+            // The receiver is gone, nobody wants the transcription anymore
+            if events_tx.send(event).is_err() {
+                return Ok(());
+            }
+        }
+
+        if finished {
+            return Ok(());
+        }
+    }
+}
+
+const STREAMING_SAMPLE_RATE: usize = 16000;
+
+// 32ms, the frame size of the silero model
+const VAD_FRAME_SAMPLES: usize = 512;
+
+// Silero resets its state on every call, so frames are classified in blocks with the
+// previous block as context
+const SILERO_BLOCK_FRAMES: usize = 16;
+
+enum SpeechDetector {
+    Energy(EnergyVAD),
+    Silero {
+        context: WhisperVadContext,
+        threshold: f32,
+        history: Vec<f32>,
+    },
+}
+
+impl SpeechDetector {
+    fn new(vad: &StreamingVad) -> Result<Self> {
+        match vad {
+            StreamingVad::Energy { threshold } => Ok(Self::Energy(
+                EnergyVAD::new(STREAMING_SAMPLE_RATE as u32).with_threshold(*threshold),
+            )),
+            StreamingVad::Silero {
+                model_path,
+                threshold,
+            } => {
+                let model_path = match model_path {
+                    Some(path) => path.clone(),
+                    None => bundled_silero_vad_model_path()?,
+                };
+
+                let mut params = WhisperVadContextParams::default();
+                params.set_n_threads(1);
+
+                let context = WhisperVadContext::new(model_path.to_string_lossy().as_ref(), params)
+                    .map_err(|e| anyhow!("Load silero vad model error: {e}"))?;
+
+                Ok(Self::Silero {
+                    context,
+                    threshold: *threshold,
+                    history: vec![],
+                })
+            }
+        }
+    }
+
+    // Samples of a single `classify` call
+    fn block_samples(&self) -> usize {
+        match self {
+            Self::Energy(_) => VAD_FRAME_SAMPLES,
+            Self::Silero { .. } => VAD_FRAME_SAMPLES * SILERO_BLOCK_FRAMES,
+        }
+    }
+
+    // Whether each `VAD_FRAME_SAMPLES` frame of `samples` is speech
+    fn classify(&mut self, samples: &[f32]) -> Result<Vec<bool>> {
+        let n_frames = samples.len() / VAD_FRAME_SAMPLES;
+
+        match self {
+            Self::Energy(vad) => Ok(samples
+                .chunks_exact(VAD_FRAME_SAMPLES)
+                .map(|frame| vad.contain_speech(frame))
+                .collect()),
+            Self::Silero {
+                context,
+                threshold,
+                history,
+            } => {
+                let input = [history.as_slice(), samples].concat();
+                context
+                    .detect_speech(&input)
+                    .map_err(|e| anyhow!("Silero detect speech failed: {e}"))?;
+
+                let probs = context.probabilities();
+                let decisions = (0..n_frames)
+                    .map(|i| {
+                        probs
+                            .get(probs.len().saturating_sub(n_frames) + i)
+                            .is_some_and(|p| p > threshold)
+                    })
+                    .collect();
+
+                let keep = VAD_FRAME_SAMPLES * SILERO_BLOCK_FRAMES;
+                *history = input[input.len().saturating_sub(keep)..].to_vec();
+                Ok(decisions)
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+enum WindowAction {
+    Provisional {
+        start_sample: u64,
+        samples: Vec<f32>,
+    },
+    Final {
+        start_sample: u64,
+        samples: Vec<f32>,
+    },
+}
+
+// Cut pushed samples into speech windows. A window starts `speech_pad_ms` before the first
+// speech frame and ends after `min_silence_ms` of silence or at `max_window_ms`
+struct SpeechWindower {
+    config: StreamingConfig,
+    detector: SpeechDetector,
+    unclassified: Vec<f32>,
+    window: Vec<f32>,
+    window_start: u64, // Sample index of the window start in the stream
+    in_speech: bool,
+    speech_samples: usize,
+    silence_samples: usize,
+    provisional_samples: usize, // Samples since the last provisional decoding
+}
+
+impl SpeechWindower {
+    fn new(config: StreamingConfig, detector: SpeechDetector) -> Self {
+        Self {
+            config,
+            detector,
+            unclassified: vec![],
+            window: vec![],
+            window_start: 0,
+            in_speech: false,
+            speech_samples: 0,
+            silence_samples: 0,
+            provisional_samples: 0,
+        }
+    }
+
+    fn push(&mut self, samples: &[f32]) -> Result<Vec<WindowAction>> {
+        self.unclassified.extend_from_slice(samples);
+
+        let block = self.detector.block_samples();
+        let n = self.unclassified.len() / block * block;
+        if n == 0 {
+            return Ok(vec![]);
+        }
+
+        let samples = self.unclassified.drain(..n).collect::<Vec<_>>();
+        let decisions = self.detector.classify(&samples)?;
+
+        let mut actions = vec![];
+        for (frame, is_speech) in samples.chunks_exact(VAD_FRAME_SAMPLES).zip(decisions) {
+            if let Some(action) = self.push_frame(frame, is_speech) {
+                actions.push(action);
+            }
+        }
+
+        Ok(actions)
+    }
+
+    // Classify the remaining samples padded with silence and close the open window
+    fn flush(&mut self) -> Result<Vec<WindowAction>> {
+        let mut actions = vec![];
+
+        if !self.unclassified.is_empty() {
+            let block = self.detector.block_samples();
+            let padding = block - self.unclassified.len() % block;
+            actions = self.push(&vec![0.0; padding])?;
+        }
+
+        actions.extend(self.close_window());
+        Ok(actions)
+    }
+
+    fn push_frame(&mut self, frame: &[f32], is_speech: bool) -> Option<WindowAction> {
+        self.window.extend_from_slice(frame);
+
+        if is_speech {
+            self.in_speech = true;
+            self.speech_samples += frame.len();
+            self.silence_samples = 0;
+        } else if self.in_speech {
+            self.silence_samples += frame.len();
+        } else {
+            let pad = ms_to_samples(self.config.speech_pad_ms);
+            if self.window.len() > pad {
+                let n = self.window.len() - pad;
+                self.window.drain(..n);
+                self.window_start += n as u64;
+            }
+            return None;
+        }
+
+        self.provisional_samples += frame.len();
+
+        if self.silence_samples >= ms_to_samples(self.config.min_silence_ms)
+            || self.window.len() >= ms_to_samples(self.config.max_window_ms)
+        {
+            return self.close_window();
+        }
+
+        if self.config.provisional_interval_ms > 0
+            && self.provisional_samples >= ms_to_samples(self.config.provisional_interval_ms)
+        {
+            self.provisional_samples = 0;
+            return Some(WindowAction::Provisional {
+                start_sample: self.window_start,
+                samples: self.window.clone(),
+            });
+        }
+
+        None
+    }
+
+    fn close_window(&mut self) -> Option<WindowAction> {
+        let is_speech =
+            self.in_speech && self.speech_samples >= ms_to_samples(self.config.min_speech_ms);
+
+        let samples = mem::take(&mut self.window);
+        let start_sample = self.window_start;
+        self.window_start += samples.len() as u64;
+
+        self.in_speech = false;
+        self.speech_samples = 0;
+        self.silence_samples = 0;
+        self.provisional_samples = 0;
+
+        is_speech.then_some(WindowAction::Final {
+            start_sample,
+            samples,
+        })
+    }
+}
+
+fn ms_to_samples(ms: u64) -> usize {
+    ms as usize * STREAMING_SAMPLE_RATE / 1000
+}
+
+fn samples_to_ms(samples: usize) -> u64 {
+    (samples * 1000 / STREAMING_SAMPLE_RATE) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn piece(text: &[u8], start_time: u64, end_time: u64, probability: f32) -> TokenPiece {
+        TokenPiece {
+            bytes: text.to_vec(),
+            start_time,
+            end_time,
+            probability,
+        }
+    }
+
+    // cargo test test_group_tokens_into_words -- --no-capture
+    #[test]
+    fn test_group_tokens_into_words() {
+        let pieces = vec![
+            piece(b" Hel", 0, 100, 0.8),
+            piece(b"lo", 100, 200, 0.6),
+            piece(b",", 200, 220, 0.9),
+            piece(b" world", 300, 600, 1.0),
+        ];
+
+        let words = group_tokens_into_words(&pieces);
+        assert_eq!(words.len(), 2);
+        assert_eq!(words[0].text, "Hello,");
+        assert_eq!((words[0].start_time, words[0].end_time), (0, 220));
+        assert!((words[0].probability - 0.7666).abs() < 0.001);
+        assert_eq!(words[1].text, "world");
+        assert_eq!((words[1].start_time, words[1].end_time), (300, 600));
+    }
+
+    // cargo test test_group_tokens_into_words_cjk -- --no-capture
+    #[test]
+    fn test_group_tokens_into_words_cjk() {
+        let text = "你好".as_bytes();
+
+        // The first character is split across two tokens
+        let pieces = vec![
+            piece(&text[..2], 0, 100, 0.5),
+            piece(&text[2..3], 100, 200, 0.5),
+            piece(&text[3..], 200, 300, 1.0),
+        ];
+
+        let words = group_tokens_into_words(&pieces);
+        assert_eq!(words.len(), 2);
+        assert_eq!(words[0].text, "你");
+        assert_eq!((words[0].start_time, words[0].end_time), (0, 200));
+        assert_eq!(words[1].text, "好");
+        assert_eq!((words[1].start_time, words[1].end_time), (200, 300));
+    }
+
+    // cargo test test_token_entropy -- --no-capture
+    #[test]
+    fn test_token_entropy() {
+        let repetitive = [1, 2, 1, 2, 1, 2, 1, 2];
+        assert!((token_entropy(&repetitive) - 2.0f32.ln()).abs() < 0.0001);
+
+        let distinct = (0..32).collect::<Vec<i32>>();
+        assert!((token_entropy(&distinct) - 32.0f32.ln()).abs() < 0.0001);
+    }
+
+    // cargo test test_temperature_schedule -- --no-capture
+    #[test]
+    fn test_temperature_schedule() {
+        let config = WhisperConfig::default()
+            .with_temperature(0.2)
+            .with_temperature_fallback(vec![0.0, 0.2, 0.4, 0.8, 0.6, 1.5]);
+
+        assert_eq!(config.temperature_schedule(), vec![0.2, 0.4, 0.8, 1.0]);
+
+        // Bad chunks fall back unless it is disabled
+        assert_eq!(
+            WhisperConfig::default().temperature_schedule(),
+            vec![0.0, 0.2, 0.4, 0.6, 0.8, 1.0]
+        );
+        assert_eq!(
+            WhisperConfig::default()
+                .with_temperature_fallback(vec![])
+                .temperature_schedule(),
+            vec![0.0]
+        );
+    }
+
+    fn segment(start_time: u64, end_time: u64, text: &str) -> TranscriptionSegment {
+        TranscriptionSegment {
+            index: 0,
+            start_time,
+            end_time,
+            text: text.to_string(),
+            confidence: 0.8,
+            words: vec![],
+            temperature: 0.0,
+            speaker_turn_next: false,
+            speaker: None,
+            no_speech_prob: 0.0,
+        }
+    }
+
+    // cargo test test_fit_prompt_tokens -- --no-capture
+    #[test]
+    fn test_fit_prompt_tokens() {
+        let context = (1000..1100).collect::<Vec<i32>>();
+
+        let (tokens, dropped) = fit_prompt_tokens(&[1, 2, 3], &context, 2);
+        assert_eq!(tokens, vec![1, 2, 3, 1098, 1099]);
+        assert_eq!(dropped, 0);
+
+        // The context is reserved first, the prompt keeps its tail
+        let prompt = (0..MAX_PROMPT_TOKENS as i32).collect::<Vec<i32>>();
+        let (tokens, dropped) = fit_prompt_tokens(&prompt, &context, 32);
+        assert_eq!(tokens.len(), MAX_PROMPT_TOKENS);
+        assert_eq!(dropped, 32);
+        assert_eq!(tokens[0], 32);
+        assert_eq!(&tokens[MAX_PROMPT_TOKENS - 32..], &context[100 - 32..]);
+    }
+
+    // cargo test test_text_similarity -- --no-capture
+    #[test]
+    fn test_text_similarity() {
+        assert_eq!(text_similarity("hello", "hello"), 1.0);
+        assert_eq!(text_similarity("abcd", "abed"), 0.75);
+        assert_eq!(text_similarity("", "abc"), 0.0);
+        assert_eq!(normalize_text("Hello, World!"), "helloworld");
+    }
+
+    // cargo test test_reconcile_overlap_segments -- --no-capture
+    #[test]
+    fn test_reconcile_overlap_segments() {
+        // Previous chunk ends at 61s, next chunk starts at 60s
+        let previous = vec![segment(59_500, 60_600, "See you tomorrow.")];
+        let next = vec![
+            segment(60_050, 60_700, "see you tomorrow"),
+            segment(61_200, 63_000, "Good night."),
+        ];
+
+        let output = reconcile_overlap_segments(previous, next, 61_000, 60_000);
+        assert_eq!(output.len(), 2);
+
+        // The previous copy is 400ms from its chunk end, the next copy is only 50ms from its start
+        assert_eq!(output[0].text, "See you tomorrow.");
+        assert_eq!(output[1].text, "Good night.");
+    }
+
+    // cargo test test_reconcile_overlap_segments_truncated -- --no-capture
+    #[test]
+    fn test_reconcile_overlap_segments_truncated() {
+        let previous = vec![
+            segment(58_000, 59_800, "First sentence."),
+            segment(59_900, 61_000, "The quick brown"),
+        ];
+        let next = vec![segment(
+            60_100,
+            62_500,
+            "The quick brown fox jumps over the dog.",
+        )];
+
+        let output = reconcile_overlap_segments(previous, next, 61_000, 60_000);
+        assert_eq!(output.len(), 2);
+        assert_eq!(output[0].text, "First sentence.");
+        assert_eq!(output[1].text, "The quick brown fox jumps over the dog.");
+    }
+
+    // cargo test test_reconcile_overlap_segments_speaker_turn -- --no-capture
+    #[test]
+    fn test_reconcile_overlap_segments_speaker_turn() {
+        // Only the next chunk sees the answer after the question
+        let previous = vec![segment(58_000, 60_600, "How are you?")];
+        let mut next = vec![
+            segment(60_100, 60_800, "are you"),
+            segment(61_200, 63_000, "Fine, thanks."),
+        ];
+        next[0].speaker_turn_next = true;
+
+        let mut output = reconcile_overlap_segments(previous, next, 61_000, 60_000);
+        assert_eq!(output.len(), 2);
+        assert_eq!(output[0].text, "How are you?");
+        assert!(output[0].speaker_turn_next);
+
+        speaker::assign_turn_speakers(&mut output);
+        assert_eq!(output[0].speaker.as_deref(), Some("Speaker 1"));
+        assert_eq!(output[1].speaker.as_deref(), Some("Speaker 2"));
+    }
+
+    // cargo test test_silero_speech_segments -- --no-capture
+    #[test]
+    fn test_silero_speech_segments() -> Result<()> {
+        let audio_data = wav::read_file("./examples/data/test-20.wav")?;
+        let segments = silero_speech_segments(&audio_data, &VadParams::default())?;
+        for (start_ms, end_ms) in segments.iter() {
+            println!("speech: {start_ms}ms -> {end_ms}ms");
+        }
+
+        // The file starts with about 2s of silence and has several pauses
+        assert!(segments.len() > 1);
+        assert!(segments[0].0 > 1000);
+        assert!(segments.windows(2).all(|w| w[0].0 < w[1].0));
+        assert!(segments.iter().all(|(start, end)| start < end));
+
+        // Longer speech is split
+        let params = VadParams::default().with_max_speech_ms(2000);
+        let short_segments = silero_speech_segments(&audio_data, &params)?;
+        assert!(short_segments.len() >= segments.len());
+
+        assert!(VadParams::default().with_threshold(1.5).validate().is_err());
+
+        Ok(())
+    }
+
+    // cargo test test_speech_windower -- --no-capture
+    #[test]
+    fn test_speech_windower() -> Result<()> {
+        let audio_data = wav::read_file("./examples/data/test-20.wav")?;
+        assert_eq!(audio_data.config.sample_rate, STREAMING_SAMPLE_RATE as u32);
+
+        let config = StreamingConfig::default();
+        let detector = SpeechDetector::new(&config.vad)?;
+        let mut windower = SpeechWindower::new(config, detector);
+
+        // Replay the file in 100ms blocks like a microphone
+        let mut actions = vec![];
+        for block in audio_data.samples.chunks(1600) {
+            actions.extend(windower.push(block)?);
+        }
+        actions.extend(windower.flush()?);
+
+        let (mut finals, mut next_start) = (0, 0);
+        for action in actions.iter() {
+            match action {
+                WindowAction::Provisional {
+                    start_sample,
+                    samples,
+                } => {
+                    // A provisional window grows from the start of the next final window
+                    assert!(*start_sample >= next_start);
+                    assert!(!samples.is_empty());
+                }
+                WindowAction::Final {
+                    start_sample,
+                    samples,
+                } => {
+                    println!(
+                        "window {finals}: {:.2}s -> {:.2}s",
+                        *start_sample as f64 / 16000.0,
+                        (*start_sample as usize + samples.len()) as f64 / 16000.0
+                    );
+
+                    assert!(*start_sample >= next_start);
+                    assert!(samples.len() <= ms_to_samples(30_000));
+                    next_start = *start_sample + samples.len() as u64;
+                    finals += 1;
+                }
+            }
+        }
+
+        // The file starts with about 2s of silence and has several pauses
+        assert!(finals > 1);
+        let Some(WindowAction::Final { start_sample, .. }) = actions
+            .iter()
+            .find(|action| matches!(action, WindowAction::Final { .. }))
+        else {
+            unreachable!();
+        };
+        assert!(*start_sample > 16000);
+        assert!(next_start <= audio_data.samples.len() as u64 + VAD_FRAME_SAMPLES as u64);
+
+        Ok(())
+    }
+}
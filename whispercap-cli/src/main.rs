@@ -0,0 +1,610 @@
+use anyhow::{Context, Result, bail};
+use clap::{Parser, ValueEnum};
+use log::debug;
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Instant,
+};
+use transcribe::{
+    SegmentCallbackData, checkpoint,
+    chunking::{ChunkSplitMode, ChunkingStrategy},
+    glossary::Glossary,
+    hallucination::{HallucinationAction, HallucinationFilter},
+    subtitle::{self, Subtitle},
+    wav::{self, AudioPreprocess, Compressor, HighPass, NoiseReduction, Normalization},
+    whisper::{self, VadParams, WhisperConfig},
+    whisper_lang::WhisperLang,
+};
+
+mod report;
+use report::{ProgressMode, Reporter, Stage, Summary};
+
+// Exit codes
+const EXIT_FAILED: u8 = 1;
+const EXIT_USAGE: u8 = 2;
+const EXIT_CANCELLED: u8 = 130;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum HallucinationMode {
+    /// Keep every segment
+    Off,
+
+    /// Keep the segments and report them
+    Flag,
+
+    /// Remove the segments and report them
+    Drop,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ChunkSplit {
+    /// Split in the silence closest to the chunk length
+    Silence,
+
+    /// Split at the chunk length with overlap
+    Fixed,
+
+    /// Split in the gap between speech detected by silero VAD
+    Vad,
+}
+
+impl From<ChunkSplit> for ChunkSplitMode {
+    fn from(split: ChunkSplit) -> Self {
+        match split {
+            ChunkSplit::Silence => ChunkSplitMode::Silence,
+            ChunkSplit::Fixed => ChunkSplitMode::FixedOverlap,
+            ChunkSplit::Vad => ChunkSplitMode::Vad,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Normalize {
+    /// The loudest sample at -1dBFS
+    Peak,
+
+    /// The average loudness at -20dBFS
+    Rms,
+}
+
+impl From<Normalize> for Normalization {
+    fn from(normalize: Normalize) -> Self {
+        match normalize {
+            Normalize::Peak => Normalization::peak(),
+            Normalize::Rms => Normalization::rms(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, ValueEnum)]
+enum OutputFormat {
+    Srt,
+    Vtt,
+    Txt,
+}
+
+impl OutputFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Srt => "srt",
+            OutputFormat::Vtt => "vtt",
+            OutputFormat::Txt => "txt",
+        }
+    }
+
+    fn save(&self, subtitles: &[Subtitle], path: impl AsRef<Path>) -> Result<()> {
+        match self {
+            OutputFormat::Srt => subtitle::save_as_srt(subtitles, path),
+            OutputFormat::Vtt => subtitle::save_as_vtt(subtitles, path),
+            OutputFormat::Txt => subtitle::save_as_txt(subtitles, path),
+        }
+    }
+}
+
+#[derive(Parser, Debug)]
+#[command(
+    name = "whispercap-cli",
+    version,
+    about = "Transcribe audio and video files into subtitles without a display.",
+    long_about = None
+)]
+struct Args {
+    /// Media files or glob patterns, e.g. "videos/**/*.mp4"
+    #[arg(required = true)]
+    inputs: Vec<String>,
+
+    /// Whisper model path, e.g. ggml-base.bin
+    #[arg(short, long)]
+    model: PathBuf,
+
+    /// Language code, e.g. en, zh. `auto` detects the language
+    #[arg(short, long, default_value = "auto")]
+    language: String,
+
+    /// Translate the transcription into English
+    #[arg(long)]
+    translate: bool,
+
+    /// Number of threads used by whisper
+    #[arg(short, long)]
+    threads: Option<i32>,
+
+    /// Initial decoding temperature
+    #[arg(long, default_value_t = 0.0)]
+    temperature: f32,
+
+    /// Temperatures tried in order when a chunk decodes badly, separated by comma.
+    /// A temperature not above --temperature disables fallback, e.g. 0
+    #[arg(long, value_delimiter = ',', default_value = "0.2,0.4,0.6,0.8,1.0")]
+    temperature_fallback: Vec<f32>,
+
+    /// Use beam search with this beam size instead of greedy sampling
+    #[arg(long)]
+    beam_size: Option<i32>,
+
+    /// Number of candidates of greedy sampling
+    #[arg(long, default_value_t = 1, conflicts_with = "beam_size")]
+    best_of: i32,
+
+    /// Initial prompt passed to whisper
+    #[arg(long)]
+    initial_prompt: Option<String>,
+
+    /// Product names and jargon, separated by comma. They are put into the prompt and
+    /// near-miss spellings of them are replaced
+    #[arg(long, value_delimiter = ',')]
+    glossary: Vec<String>,
+
+    /// Length of each chunk in milliseconds, 0 disables chunking
+    #[arg(long, default_value_t = 60000)]
+    chunk_length_ms: u64,
+
+    /// Overlap between chunks in milliseconds, when a chunk isn't split in a pause
+    #[arg(long, default_value_t = 1000)]
+    chunk_overlap_ms: u64,
+
+    /// How chunks are split
+    #[arg(long, value_enum, default_value = "silence")]
+    chunk_split: ChunkSplit,
+
+    /// Search a pause this many milliseconds before the chunk length
+    #[arg(long, default_value_t = 0)]
+    chunk_search_back_ms: u64,
+
+    /// Search a pause this many milliseconds after the chunk length
+    #[arg(long, default_value_t = 30000)]
+    chunk_search_forward_ms: u64,
+
+    /// Skip the audio without speech with the bundled silero VAD model before decoding
+    #[arg(long)]
+    vad: bool,
+
+    /// Silero VAD model path, e.g. ggml-silero-v5.1.2.bin. Implies --vad
+    #[arg(long)]
+    vad_model: Option<PathBuf>,
+
+    /// Speech probability of a VAD frame, of --vad and --chunk-split vad
+    #[arg(long, default_value_t = 0.5)]
+    vad_threshold: f32,
+
+    /// Shorter speech is dropped as noise by the VAD
+    #[arg(long, default_value_t = 250)]
+    vad_min_speech_ms: u32,
+
+    /// Silence ending a speech segment of the VAD
+    #[arg(long, default_value_t = 100)]
+    vad_min_silence_ms: u32,
+
+    /// Audio kept before and after each speech segment of the VAD
+    #[arg(long, default_value_t = 30)]
+    vad_speech_pad_ms: u32,
+
+    /// Longer speech is split at a silence by the VAD
+    #[arg(long)]
+    vad_max_speech_ms: Option<u64>,
+
+    /// Number of chunks decoded in parallel. They share the whisper threads
+    #[arg(short = 'j', long, default_value_t = 1)]
+    parallel_chunks: usize,
+
+    /// Number of tokens from the previous chunk used as the prompt of the next chunk.
+    /// 0 disables it. Chunks are decoded one by one when it is enabled
+    #[arg(long, default_value_t = 0)]
+    context_tokens: usize,
+
+    /// Remove rumble and hum below this frequency in Hz before transcribing
+    #[arg(long)]
+    high_pass_hz: Option<f32>,
+
+    /// Reduce steady background noise like fans or hiss before transcribing
+    #[arg(long)]
+    denoise: bool,
+
+    /// Turn loud passages down so quiet and loud speakers are closer
+    #[arg(long)]
+    compress: bool,
+
+    /// Normalize the loudness before transcribing
+    #[arg(long, value_enum)]
+    normalize: Option<Normalize>,
+
+    /// Output formats, separated by comma
+    #[arg(short, long, value_enum, value_delimiter = ',', default_value = "srt")]
+    format: Vec<OutputFormat>,
+
+    /// Write subtitles into this directory instead of next to each input
+    #[arg(short, long)]
+    output_dir: Option<PathBuf>,
+
+    /// Repeated lines and stock phrases made up on music or silence
+    #[arg(long, value_enum, default_value = "off")]
+    hallucinations: HallucinationMode,
+
+    /// Save finished chunks into this directory, and resume an interrupted transcription
+    /// from them. Resume is refused if the model, language or audio has changed
+    #[arg(long)]
+    checkpoint_dir: Option<PathBuf>,
+
+    /// Overwrite existing subtitle files
+    #[arg(long)]
+    overwrite: bool,
+
+    /// Convert traditional Chinese into simplified Chinese
+    #[arg(long)]
+    simplified_chinese: bool,
+
+    /// Progress output. `json` prints one event per line on stdout
+    #[arg(short, long, value_enum, default_value = "text")]
+    progress: ProgressMode,
+}
+
+enum Outcome {
+    Finished,
+    Skipped,
+    Cancelled,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    env_logger::init();
+
+    let args = Args::parse();
+
+    let inputs = match validate_args(&args).and_then(|_| expand_inputs(&args.inputs)) {
+        Ok(inputs) => inputs,
+        Err(e) => {
+            report::usage_error(args.progress, &format!("{e:#}"));
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+
+    let cancel = Arc::new(AtomicBool::new(false));
+    let cancel_signal = cancel.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            cancel_signal.store(true, Ordering::Relaxed);
+        }
+    });
+
+    let mut summary = Summary {
+        total: inputs.len(),
+        ..Default::default()
+    };
+
+    for (index, input) in inputs.iter().enumerate() {
+        let reporter = Reporter::new(
+            args.progress,
+            input.display().to_string(),
+            index + 1,
+            inputs.len(),
+        );
+
+        reporter.start();
+        match transcribe_media(&args, input, &reporter, cancel.clone()).await {
+            Ok(Outcome::Finished) => summary.finished += 1,
+            Ok(Outcome::Skipped) => summary.skipped += 1,
+            Ok(Outcome::Cancelled) => {
+                reporter.cancelled();
+                summary.cancelled = true;
+                break;
+            }
+            Err(e) => {
+                reporter.failed(&e);
+                summary.failed += 1;
+            }
+        }
+    }
+
+    report::summary(args.progress, &summary);
+
+    if summary.cancelled {
+        ExitCode::from(EXIT_CANCELLED)
+    } else if summary.failed > 0 {
+        ExitCode::from(EXIT_FAILED)
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+fn validate_args(args: &Args) -> Result<()> {
+    if !args.model.is_file() {
+        bail!("model not found: {}", args.model.display());
+    }
+
+    if args.language != WhisperLang::Auto.to_string()
+        && !WhisperLang::all_languages()
+            .iter()
+            .any(|(_, code, _)| *code == args.language)
+    {
+        bail!("unsupported language: {}", args.language);
+    }
+
+    if args.chunk_length_ms > 0 && args.chunk_overlap_ms >= args.chunk_length_ms {
+        bail!("chunk overlap should be less than chunk length");
+    }
+
+    if args.chunk_length_ms > 0 && args.chunk_search_back_ms >= args.chunk_length_ms {
+        bail!("chunk backward search should be less than chunk length");
+    }
+
+    if let Some(path) = &args.vad_model
+        && !path.is_file()
+    {
+        bail!("vad model not found: {}", path.display());
+    }
+
+    vad_params(args).validate()?;
+
+    if let Some(dir) = &args.output_dir {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("create output directory {} failed", dir.display()))?;
+    }
+
+    if let Some(dir) = &args.checkpoint_dir {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("create checkpoint directory {} failed", dir.display()))?;
+    }
+
+    Ok(())
+}
+
+fn vad_params(args: &Args) -> VadParams {
+    let params = VadParams::default()
+        .with_threshold(args.vad_threshold)
+        .with_min_speech_ms(args.vad_min_speech_ms)
+        .with_min_silence_ms(args.vad_min_silence_ms)
+        .with_speech_pad_ms(args.vad_speech_pad_ms);
+
+    match args.vad_max_speech_ms {
+        Some(ms) => params.with_max_speech_ms(ms),
+        None => params,
+    }
+}
+
+fn expand_inputs(patterns: &[String]) -> Result<Vec<PathBuf>> {
+    let mut inputs = vec![];
+    let mut seen = HashSet::new();
+
+    for pattern in patterns {
+        let paths = if pattern.contains(['*', '?', '[']) {
+            let mut paths = vec![];
+            for entry in glob::glob(pattern).with_context(|| format!("invalid glob {pattern}"))? {
+                let path = entry?;
+                if path.is_file() {
+                    paths.push(path);
+                }
+            }
+
+            if paths.is_empty() {
+                bail!("no file matches {pattern}");
+            }
+            paths
+        } else {
+            let path = PathBuf::from(pattern);
+            if !path.is_file() {
+                bail!("file not found: {pattern}");
+            }
+            vec![path]
+        };
+
+        for path in paths {
+            if seen.insert(path.clone()) {
+                inputs.push(path);
+            }
+        }
+    }
+
+    Ok(inputs)
+}
+
+fn output_path(input: &Path, output_dir: Option<&Path>, format: OutputFormat) -> PathBuf {
+    let dir = output_dir
+        .or_else(|| input.parent())
+        .unwrap_or_else(|| Path::new("."));
+
+    let stem = input.file_stem().unwrap_or(input.as_os_str());
+    dir.join(format!("{}.{}", stem.to_string_lossy(), format.extension()))
+}
+
+async fn transcribe_media(
+    args: &Args,
+    input: &Path,
+    reporter: &Reporter,
+    cancel: Arc<AtomicBool>,
+) -> Result<Outcome> {
+    let start_time = Instant::now();
+
+    let outputs = args
+        .format
+        .iter()
+        .map(|format| {
+            (
+                *format,
+                output_path(input, args.output_dir.as_deref(), *format),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    if !args.overwrite && outputs.iter().all(|(_, path)| path.exists()) {
+        reporter.skipped("subtitles already exist");
+        return Ok(Outcome::Skipped);
+    }
+
+    let checkpoint_path = args
+        .checkpoint_dir
+        .as_ref()
+        .map(|dir| dir.join(checkpoint::checkpoint_file_name(input)));
+
+    let result = transcribe_audio(args, input, checkpoint_path, reporter, cancel.clone()).await;
+
+    if cancel.load(Ordering::Relaxed) {
+        return Ok(Outcome::Cancelled);
+    }
+
+    let mut subtitles = result?;
+
+    if args.simplified_chinese {
+        for item in subtitles.iter_mut() {
+            item.text = subtitle::convert_traditional_to_simplified_chinese(&item.text);
+        }
+    }
+
+    let mut saved = vec![];
+    for (format, path) in outputs {
+        format
+            .save(&subtitles, &path)
+            .with_context(|| format!("save {} failed", path.display()))?;
+        saved.push(path);
+    }
+
+    reporter.finished(&saved, start_time.elapsed().as_millis());
+    Ok(Outcome::Finished)
+}
+
+async fn transcribe_audio(
+    args: &Args,
+    input: &Path,
+    checkpoint_path: Option<PathBuf>,
+    reporter: &Reporter,
+    cancel: Arc<AtomicBool>,
+) -> Result<Vec<Subtitle>> {
+    let mut config = WhisperConfig::new(&args.model)
+        .with_language(&args.language)
+        .with_translate(args.translate)
+        .with_chunking(
+            ChunkingStrategy::new(args.chunk_split.into())
+                .with_chunk_length_ms(args.chunk_length_ms)
+                .with_overlap_ms(args.chunk_overlap_ms)
+                .with_backward_search_ms(args.chunk_search_back_ms)
+                .with_forward_search_ms(args.chunk_search_forward_ms)
+                .with_vad_params(vad_params(args)),
+        )
+        .with_vad_params(vad_params(args))
+        .with_max_parallel_chunks(args.parallel_chunks)
+        .with_context_tokens(args.context_tokens)
+        .with_temperature(args.temperature)
+        .with_temperature_fallback(args.temperature_fallback.clone());
+
+    config = match args.beam_size {
+        Some(beam_size) => config.with_beam_search(beam_size, -1.0),
+        None => config.with_greedy(args.best_of),
+    };
+
+    if let Some(path) = &args.vad_model {
+        config = config.with_vad_model_path(path);
+    } else if args.vad {
+        config = config.with_vad_model_path(whisper::bundled_silero_vad_model_path()?);
+    }
+
+    if let Some(n_threads) = args.threads {
+        config = config.with_threads(n_threads);
+    }
+
+    if let Some(prompt) = &args.initial_prompt {
+        config = config.with_initial_prompt(prompt);
+    }
+
+    if !args.glossary.is_empty() {
+        config = config.with_glossary(Glossary::new(&args.glossary));
+    }
+
+    if let Some(path) = checkpoint_path {
+        config = config.with_checkpoint_path(path);
+    }
+
+    let preprocess = AudioPreprocess {
+        high_pass: args.high_pass_hz.map(HighPass::new),
+        noise_reduction: args.denoise.then(NoiseReduction::default),
+        compressor: args.compress.then(Compressor::default),
+        normalization: args.normalize.map(Normalization::from),
+    };
+
+    if !preprocess.is_empty() {
+        config = config.with_preprocess(preprocess);
+    }
+
+    match args.hallucinations {
+        HallucinationMode::Off => (),
+        HallucinationMode::Flag => {
+            config = config.with_hallucination_filter(
+                HallucinationFilter::default().with_action(HallucinationAction::Flag),
+            )
+        }
+        HallucinationMode::Drop => {
+            config = config.with_hallucination_filter(
+                HallucinationFilter::default().with_action(HallucinationAction::Drop),
+            )
+        }
+    }
+
+    let is_compatible = input
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("wav"))
+        && wav::is_whisper_compatible(input).is_ok();
+
+    let (progress_reporter, segment_reporter) = (reporter.clone(), reporter.clone());
+    let progress_cb = move |v: i32| progress_reporter.progress(Stage::Transcribe, v);
+    let segment_cb = move |segment: SegmentCallbackData| segment_reporter.segment(&segment.into());
+    let abort_cb = move || cancel.load(Ordering::Relaxed);
+
+    // Other media is decoded through an ffmpeg pipe, without a temporary wav file
+    let transcription = if is_compatible {
+        whisper::transcribe_file(config, input, progress_cb, segment_cb, abort_cb).await?
+    } else {
+        debug!("decode {} with ffmpeg", input.display());
+        whisper::transcribe_media(
+            config,
+            input,
+            ffmpeg::AudioSelection::default(),
+            progress_cb,
+            segment_cb,
+            abort_cb,
+        )
+        .await?
+    };
+
+    if let Some(status) = &transcription.checkpoint {
+        reporter.checkpoint(status);
+    }
+
+    for item in transcription.hallucinations.iter() {
+        reporter.hallucination(item);
+    }
+
+    for item in transcription.glossary_replacements.iter() {
+        reporter.glossary_replacement(item);
+    }
+
+    for item in transcription.chunks.iter() {
+        reporter.chunk(item);
+    }
+
+    Ok(subtitle::transcription_to_subtitle(&transcription))
+}
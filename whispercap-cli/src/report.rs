@@ -0,0 +1,282 @@
+use clap::ValueEnum;
+use serde_json::{Value, json};
+use std::{
+    io::{self, Write},
+    path::PathBuf,
+};
+use transcribe::{
+    checkpoint::CheckpointStatus,
+    chunking::{ChunkRange, SplitReason},
+    glossary::GlossaryReplacement,
+    hallucination::{Hallucination, HallucinationReason},
+    subtitle::Subtitle,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ProgressMode {
+    /// Human readable progress on stderr
+    Text,
+
+    /// One JSON object per line on stdout
+    Json,
+
+    /// Only print errors
+    Quiet,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Transcribe,
+}
+
+impl Stage {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Stage::Transcribe => "transcribe",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Summary {
+    pub total: usize,
+    pub finished: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    pub cancelled: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Reporter {
+    mode: ProgressMode,
+    file: String,
+    index: usize,
+    total: usize,
+}
+
+impl Reporter {
+    pub fn new(mode: ProgressMode, file: impl Into<String>, index: usize, total: usize) -> Self {
+        Self {
+            mode,
+            file: file.into(),
+            index,
+            total,
+        }
+    }
+
+    pub fn start(&self) {
+        match self.mode {
+            ProgressMode::Text => eprintln!("{} start", self.prefix()),
+            ProgressMode::Json => self.emit(json!({ "event": "start" })),
+            ProgressMode::Quiet => (),
+        }
+    }
+
+    pub fn progress(&self, stage: Stage, percent: i32) {
+        let percent = percent.clamp(0, 100);
+
+        match self.mode {
+            ProgressMode::Text => eprintln!("{} {} {percent}%", self.prefix(), stage.as_str()),
+            ProgressMode::Json => self.emit(json!({
+                "event": "progress",
+                "stage": stage.as_str(),
+                "percent": percent,
+            })),
+            ProgressMode::Quiet => (),
+        }
+    }
+
+    pub fn segment(&self, subtitle: &Subtitle) {
+        if self.mode == ProgressMode::Json {
+            self.emit(json!({
+                "event": "segment",
+                "start_ms": subtitle.start_timestamp,
+                "end_ms": subtitle.end_timestamp,
+                "text": subtitle.text.trim(),
+            }));
+        }
+    }
+
+    pub fn hallucination(&self, item: &Hallucination) {
+        let reason = match &item.reason {
+            HallucinationReason::Duplicate => "duplicate".to_string(),
+            HallucinationReason::CompressionRatio(ratio) => format!("compression ratio {ratio:.2}"),
+            HallucinationReason::NoSpeech(prob) => format!("no speech {prob:.2}"),
+            HallucinationReason::Blocklist(phrase) => format!("blocklist \"{phrase}\""),
+        };
+
+        match self.mode {
+            ProgressMode::Text => eprintln!(
+                "{} {} hallucination at {}ms ({reason}): {}",
+                self.prefix(),
+                if item.dropped { "dropped" } else { "flagged" },
+                item.segment.start_time,
+                item.segment.text.trim()
+            ),
+            ProgressMode::Json => self.emit(json!({
+                "event": "hallucination",
+                "start_ms": item.segment.start_time,
+                "end_ms": item.segment.end_time,
+                "text": item.segment.text.trim(),
+                "reason": reason,
+                "dropped": item.dropped,
+            })),
+            ProgressMode::Quiet => (),
+        }
+    }
+
+    pub fn glossary_replacement(&self, item: &GlossaryReplacement) {
+        match self.mode {
+            ProgressMode::Text => eprintln!(
+                "{} replaced at {}ms ({:.2}): {} -> {}",
+                self.prefix(),
+                item.start_time,
+                item.similarity,
+                item.original,
+                item.replacement
+            ),
+            ProgressMode::Json => self.emit(json!({
+                "event": "glossary_replacement",
+                "start_ms": item.start_time,
+                "original": item.original,
+                "replacement": item.replacement,
+                "similarity": item.similarity,
+            })),
+            ProgressMode::Quiet => (),
+        }
+    }
+
+    pub fn chunk(&self, item: &ChunkRange) {
+        let split = match &item.split {
+            None => "end of audio".to_string(),
+            Some(SplitReason::Silence { silence_ms }) => format!("{silence_ms}ms silence"),
+            Some(SplitReason::SpeechGap { gap_ms }) => format!("{gap_ms}ms speech gap"),
+            Some(SplitReason::Fixed) => "fixed with overlap".to_string(),
+        };
+
+        match self.mode {
+            ProgressMode::Text => eprintln!(
+                "{} chunk {}ms-{}ms, split at {split}",
+                self.prefix(),
+                item.start_ms,
+                item.end_ms
+            ),
+            ProgressMode::Json => self.emit(json!({
+                "event": "chunk",
+                "start_ms": item.start_ms,
+                "end_ms": item.end_ms,
+                "split": split,
+            })),
+            ProgressMode::Quiet => (),
+        }
+    }
+
+    pub fn checkpoint(&self, status: &CheckpointStatus) {
+        let (status, detail) = match status {
+            CheckpointStatus::Created => ("created", String::new()),
+            CheckpointStatus::Resumed { chunks } => ("resumed", format!("{chunks} chunks")),
+            CheckpointStatus::Discarded { reason } => ("discarded", reason.clone()),
+        };
+
+        match self.mode {
+            ProgressMode::Text if !detail.is_empty() => {
+                eprintln!("{} checkpoint {status}: {detail}", self.prefix())
+            }
+            ProgressMode::Json => self.emit(json!({
+                "event": "checkpoint",
+                "status": status,
+                "detail": detail,
+            })),
+            _ => (),
+        }
+    }
+
+    pub fn skipped(&self, reason: &str) {
+        match self.mode {
+            ProgressMode::Text => eprintln!("{} skipped: {reason}", self.prefix()),
+            ProgressMode::Json => self.emit(json!({ "event": "skipped", "reason": reason })),
+            ProgressMode::Quiet => (),
+        }
+    }
+
+    pub fn finished(&self, outputs: &[PathBuf], elapsed_ms: u128) {
+        match self.mode {
+            ProgressMode::Text => {
+                for output in outputs {
+                    eprintln!("{} saved {}", self.prefix(), output.display());
+                }
+                eprintln!("{} finished in {elapsed_ms}ms", self.prefix());
+            }
+            ProgressMode::Json => self.emit(json!({
+                "event": "finished",
+                "outputs": outputs.iter().map(|p| p.display().to_string()).collect::<Vec<_>>(),
+                "elapsed_ms": elapsed_ms as u64,
+            })),
+            ProgressMode::Quiet => (),
+        }
+    }
+
+    pub fn failed(&self, err: &anyhow::Error) {
+        match self.mode {
+            ProgressMode::Json => self.emit(json!({
+                "event": "failed",
+                "error": format!("{err:#}"),
+            })),
+            _ => eprintln!("{} failed: {err:#}", self.prefix()),
+        }
+    }
+
+    pub fn cancelled(&self) {
+        match self.mode {
+            ProgressMode::Text => eprintln!("{} cancelled", self.prefix()),
+            ProgressMode::Json => self.emit(json!({ "event": "cancelled" })),
+            ProgressMode::Quiet => (),
+        }
+    }
+
+    fn prefix(&self) -> String {
+        format!("[{}/{}] {}:", self.index, self.total, self.file)
+    }
+
+    fn emit(&self, mut event: Value) {
+        event["file"] = json!(self.file);
+        event["index"] = json!(self.index);
+        event["total"] = json!(self.total);
+        print_json_line(&event);
+    }
+}
+
+pub fn usage_error(mode: ProgressMode, msg: &str) {
+    match mode {
+        ProgressMode::Json => print_json_line(&json!({ "event": "error", "error": msg })),
+        _ => eprintln!("error: {msg}"),
+    }
+}
+
+pub fn summary(mode: ProgressMode, summary: &Summary) {
+    match mode {
+        ProgressMode::Text => eprintln!(
+            "total: {}, finished: {}, skipped: {}, failed: {}{}",
+            summary.total,
+            summary.finished,
+            summary.skipped,
+            summary.failed,
+            if summary.cancelled { ", cancelled" } else { "" }
+        ),
+        ProgressMode::Json => print_json_line(&json!({
+            "event": "summary",
+            "total": summary.total,
+            "finished": summary.finished,
+            "skipped": summary.skipped,
+            "failed": summary.failed,
+            "cancelled": summary.cancelled,
+        })),
+        ProgressMode::Quiet => (),
+    }
+}
+
+fn print_json_line(value: &Value) {
+    let mut stdout = io::stdout().lock();
+    _ = writeln!(stdout, "{value}");
+    _ = stdout.flush();
+}
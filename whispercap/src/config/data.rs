@@ -52,6 +52,11 @@ pub struct Preference {
     pub no_frame: bool,
 
     pub is_dark: bool,
+
+    // Subtitles transcribed with a lower confidence are marked for review
+    #[derivative(Default(value = "0.6"))]
+    #[serde(default = "low_confidence_threshold_default")]
+    pub low_confidence_threshold: f32,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
@@ -64,3 +69,7 @@ pub struct Model {
 pub fn appid_default() -> String {
     Uuid::new_v4().to_string()
 }
+
+pub fn low_confidence_threshold_default() -> f32 {
+    0.6
+}
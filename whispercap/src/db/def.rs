@@ -1,13 +1,15 @@
 use crate::slint_generatedAppWindow::{
     MediaType as UIMediaType, ModelEntry as UIModelEntry, ModelSource, ModelStatus,
     SubtitleEntry as UISubtitleEntry, SubtitleSetting as UISubtitleSetting,
-    TextListEntry as UITextListEntry, TranscribeEntry as UITranscribeEntry,
+    SubtitleWord as UISubtitleWord, TextListEntry as UITextListEntry,
+    TranscribeEntry as UITranscribeEntry,
 };
 use ffmpeg::MediaType;
 use serde::de::{self, Visitor};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use slint::{Model, ModelRc, VecModel};
 use std::fmt;
+use transcribe::whisper::Word;
 
 pub const TRANSCRIBE_TABLE: &str = "transcribe";
 pub const MODEL_TABLE: &str = "model";
@@ -24,6 +26,23 @@ pub struct SubtitleEntry {
     pub end_timestamp: String,
     pub original_text: String,
     pub translation_text: String,
+
+    #[serde(default)]
+    pub speaker: String,
+
+    #[serde(default)]
+    pub confidence: Option<f32>,
+
+    // Reason of a flagged hallucination
+    #[serde(default)]
+    pub hallucination: Option<String>,
+
+    // Replaced glossary terms
+    #[serde(default)]
+    pub glossary: Option<String>,
+
+    #[serde(default)]
+    pub words: Vec<Word>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
@@ -41,6 +60,44 @@ pub struct TranscribeEntry {
     pub media_type: MediaType,
     pub model_name: String,
     pub lang: String,
+
+    #[serde(default)]
+    pub detect_speakers: bool,
+
+    #[serde(default)]
+    pub glossary: Vec<String>,
+
+    #[serde(default)]
+    pub chunk_mode: String,
+
+    #[serde(default)]
+    pub skip_non_speech: bool,
+
+    #[serde(default)]
+    pub vad_sensitivity: String,
+
+    #[serde(default)]
+    pub high_pass: bool,
+
+    #[serde(default)]
+    pub denoise: bool,
+
+    #[serde(default)]
+    pub compress: bool,
+
+    #[serde(default)]
+    pub normalize: String,
+
+    #[serde(default)]
+    pub drop_hallucinations: bool,
+
+    // None is ffmpeg's default stream and a mix of every channel
+    #[serde(default)]
+    pub audio_stream: Option<u32>,
+
+    #[serde(default)]
+    pub audio_channel: Option<u16>,
+
     pub sidebar_entry: TextListEntry,
     pub subtitle_entries: Vec<SubtitleEntry>,
     pub subtitle_setting: SubtitleSetting,
@@ -72,6 +129,12 @@ impl From<UISubtitleEntry> for SubtitleEntry {
             end_timestamp: entry.end_timestamp.into(),
             original_text: entry.original_text.into(),
             translation_text: entry.translation_text.into(),
+            speaker: entry.speaker.into(),
+            confidence: (entry.confidence > 0.0).then_some(entry.confidence),
+            hallucination: (!entry.hallucination.is_empty())
+                .then(|| entry.hallucination.to_string()),
+            glossary: (!entry.glossary.is_empty()).then(|| entry.glossary.to_string()),
+            words: entry.words.iter().map(|item| item.into()).collect(),
         }
     }
 }
@@ -83,12 +146,45 @@ impl From<SubtitleEntry> for UISubtitleEntry {
             end_timestamp: entry.end_timestamp.into(),
             original_text: entry.original_text.into(),
             translation_text: entry.translation_text.into(),
+            speaker: entry.speaker.into(),
+            confidence: entry.confidence.unwrap_or_default(),
+            hallucination: entry.hallucination.unwrap_or_default().into(),
+            glossary: entry.glossary.unwrap_or_default().into(),
+            words: ModelRc::new(VecModel::from(
+                entry
+                    .words
+                    .into_iter()
+                    .map(|item| item.into())
+                    .collect::<Vec<UISubtitleWord>>(),
+            )),
             sound_data: ModelRc::new(VecModel::from_slice(&[])),
             ..Default::default()
         }
     }
 }
 
+impl From<UISubtitleWord> for Word {
+    fn from(entry: UISubtitleWord) -> Self {
+        Self {
+            text: entry.text.into(),
+            start_time: entry.start_ms.max(0) as u64,
+            end_time: entry.end_ms.max(0) as u64,
+            probability: entry.probability,
+        }
+    }
+}
+
+impl From<Word> for UISubtitleWord {
+    fn from(entry: Word) -> Self {
+        Self {
+            text: entry.text.into(),
+            start_ms: entry.start_time as i32,
+            end_ms: entry.end_time as i32,
+            probability: entry.probability,
+        }
+    }
+}
+
 impl From<UISubtitleSetting> for SubtitleSetting {
     fn from(entry: UISubtitleSetting) -> Self {
         Self {
@@ -119,6 +215,23 @@ impl From<UITranscribeEntry> for TranscribeEntry {
             model_name: entry.model_name.into(),
             media_type: entry.media_type.into(),
             lang: entry.lang.into(),
+            detect_speakers: entry.detect_speakers,
+            glossary: entry
+                .glossary
+                .lines()
+                .map(|line| line.trim().to_string())
+                .filter(|line| !line.is_empty())
+                .collect(),
+            chunk_mode: entry.chunk_mode.into(),
+            skip_non_speech: entry.skip_non_speech,
+            vad_sensitivity: entry.vad_sensitivity.into(),
+            high_pass: entry.high_pass,
+            denoise: entry.denoise,
+            compress: entry.compress,
+            normalize: entry.normalize.into(),
+            drop_hallucinations: entry.drop_hallucinations,
+            audio_stream: (entry.audio_stream >= 0).then_some(entry.audio_stream as u32),
+            audio_channel: (entry.audio_channel >= 0).then_some(entry.audio_channel as u16),
             sidebar_entry: entry.sidebar_entry.into(),
             subtitle_entries: entry
                 .subtitle_entries
@@ -138,6 +251,18 @@ impl From<TranscribeEntry> for UITranscribeEntry {
             model_name: entry.model_name.into(),
             media_type: entry.media_type.into(),
             lang: entry.lang.into(),
+            detect_speakers: entry.detect_speakers,
+            glossary: entry.glossary.join("\n").into(),
+            chunk_mode: entry.chunk_mode.into(),
+            skip_non_speech: entry.skip_non_speech,
+            vad_sensitivity: entry.vad_sensitivity.into(),
+            high_pass: entry.high_pass,
+            denoise: entry.denoise,
+            compress: entry.compress,
+            normalize: entry.normalize.into(),
+            drop_hallucinations: entry.drop_hallucinations,
+            audio_stream: entry.audio_stream.map_or(-1, |v| v as i32),
+            audio_channel: entry.audio_channel.map_or(-1, |v| v as i32),
             sidebar_entry: entry.sidebar_entry.into(),
             subtitle_entries: ModelRc::new(
                 entry
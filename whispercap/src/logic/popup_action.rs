@@ -85,6 +85,10 @@ pub fn init(ui: &AppWindow) {
                 "adjust-overlap-timestamp" => {
                     global_logic!(ui).invoke_adjust_overlap_timestamp();
                 }
+                "show-low-confidence-threshold-dialog" => {
+                    global_logic!(ui)
+                        .invoke_switch_popup(crate::PopupIndex::LowConfidenceThreshold);
+                }
 
                 // ============= subtitle entry ================ //
                 "split-subtitle" => {
@@ -101,6 +105,10 @@ pub fn init(ui: &AppWindow) {
                     global_logic!(ui)
                         .invoke_switch_popup(crate::PopupIndex::SubtitlesShiftTimestamp);
                 }
+                "show-retranscribe-subtitles" => {
+                    let index = user_data.parse::<i32>().unwrap_or_default();
+                    global_logic!(ui).invoke_show_retranscribe_subtitles_dialog(index);
+                }
                 "insert-above-subtitle" => {
                     let index = user_data.parse::<i32>().unwrap_or_default();
                     global_logic!(ui).invoke_insert_above_subtitle(index);
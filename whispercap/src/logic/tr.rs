@@ -342,6 +342,57 @@ fn cn() -> &'static HashMap<&'static str, &'static str> {
             ("Shift Timestamps (ms)", "平移时间戳（毫秒）"),
             ("Shift timestamp", "平移时间戳"),
             ("lowercase", "转成小写"),
+            ("Detected language", "检测到的语言"),
+            ("Detect speakers", "检测说话人"),
+            ("Detect speakers failed", "检测说话人失败"),
+            ("Rename Speaker", "重命名说话人"),
+            ("rename speaker successfully", "重命名说话人成功"),
+            ("Resumed chunks from checkpoint", "从检查点恢复的分段"),
+            ("Discarded checkpoint", "已丢弃检查点"),
+            ("Removed hallucinated subtitles", "已移除幻觉字幕"),
+            ("Re-transcribe", "重新转录"),
+            ("Re-transcribe Subtitles", "重新转录字幕"),
+            ("From subtitle", "起始字幕"),
+            ("To subtitle", "结束字幕"),
+            ("Invalid subtitle range", "无效的字幕范围"),
+            ("Subtitles changed while re-transcribing", "重新转录期间字幕已被修改"),
+            ("Re-transcribed subtitles", "已重新转录字幕"),
+            ("next low confidence or hallucinated subtitle", "下一条低置信度或疑似幻觉字幕"),
+            ("low confidence threshold", "低置信度阈值"),
+            ("Low Confidence Threshold (%)", "低置信度阈值 (%)"),
+            ("No low confidence or hallucinated subtitles", "没有低置信度或疑似幻觉字幕"),
+            ("Glossary", "术语表"),
+            ("Product names and jargon, one per line", "产品名称和术语，每行一个"),
+            ("Fixed glossary terms", "已修正术语"),
+            ("Split long audio at", "长音频分段位置"),
+            ("Silence", "静音处"),
+            ("Fixed length with overlap", "固定长度并重叠"),
+            ("Speech gaps (VAD)", "语音间隙 (VAD)"),
+            ("Speech detection (VAD)", "语音检测 (VAD)"),
+            ("Skip audio without speech", "跳过无语音的音频"),
+            ("Normal sensitivity", "普通灵敏度"),
+            ("High sensitivity, for quiet speech", "高灵敏度，适合轻声说话"),
+            ("Low sensitivity, for noisy audio", "低灵敏度，适合嘈杂的音频"),
+            ("Clean up audio", "音频清理"),
+            ("Remove low rumble and hum", "去除低频隆隆声和嗡嗡声"),
+            ("Reduce background noise", "降低背景噪音"),
+            ("Even out loud and quiet voices", "平衡响亮和轻柔的人声"),
+            ("Normalize volume", "音量标准化"),
+            ("Off", "关闭"),
+            ("Peak", "峰值"),
+            ("Average loudness (RMS)", "平均响度 (RMS)"),
+            ("Audio track", "音轨"),
+            ("Audio channel", "声道"),
+            ("Default", "默认"),
+            ("Mix all channels", "混合所有声道"),
+            ("Left", "左声道"),
+            ("Right", "右声道"),
+            ("Channel", "声道"),
+            ("Possibly hallucinated subtitles", "疑似幻觉字幕"),
+            ("possible hallucination", "疑似幻觉"),
+            ("fixed glossary terms", "已修正术语"),
+            ("uncertain words", "不确定的词"),
+            ("Remove lines made up on music or silence", "移除音乐或静音处编造的字幕"),
         ])
     })
 }
@@ -12,9 +12,11 @@ use crate::{
     slint_generatedAppWindow::{
         AiHandleSubtitleSetting as UIAiHandleSubtitleSetting, AppWindow,
         ExportVideoSetting as UIExportVideoSetting, MediaType as UIMediaType, PopupIndex,
-        ProgressType, SubtitleEntry as UISubtitleEntry, SubtitleSetting as UISubtitleSetting,
-        SystemFontInfo as UISystemFontInfo, TextListEntry as UITextListEntry,
-        TranscribeEntry as UITranscribeEntry, VideoPlayerSetting as UIVideoPlayerSetting,
+        ProgressType, RetranscribeSetting as UIRetranscribeSetting,
+        SubtitleEntry as UISubtitleEntry, SubtitleSetting as UISubtitleSetting,
+        SubtitleWord as UISubtitleWord, SystemFontInfo as UISystemFontInfo,
+        TextListEntry as UITextListEntry, TranscribeEntry as UITranscribeEntry,
+        VideoPlayerSetting as UIVideoPlayerSetting,
     },
     toast_info, toast_success, toast_warn,
 };
@@ -27,8 +29,8 @@ use async_openai::{
     },
 };
 use ffmpeg::{
-    MediaType, SubtitleConfig, VideoExitStatus, VideoFramesIterConfig, VideoMetadata,
-    VideoResolution,
+    AudioSelection, AudioStreamInfo, MediaType, SubtitleConfig, VideoExitStatus,
+    VideoFramesIterConfig, VideoMetadata, VideoResolution,
 };
 use kittyaudio::{Mixer, Sound, SoundHandle};
 use log::{debug, info, trace, warn};
@@ -46,7 +48,16 @@ use std::{
 use tokio::{sync::mpsc, task::AbortHandle};
 use transcribe::{
     SegmentCallbackData,
+    audio_cache::AudioCache,
+    checkpoint::CheckpointStatus,
+    chunking::{ChunkSplitMode, ChunkingStrategy},
+    glossary::Glossary,
+    hallucination::{HallucinationAction, HallucinationFilter},
+    peaks::{self, WaveformPeaks},
+    speaker::SpeakerDetection,
     subtitle::{self, Subtitle, ms_to_srt_timestamp, srt_timestamp_to_ms},
+    wav::{AudioPreprocess, Compressor, HighPass, NoiseReduction, Normalization},
+    whisper::{self, VadParams, WhisperConfig},
     whisper_lang::WhisperLang,
 };
 use uuid::Uuid;
@@ -236,6 +247,16 @@ pub fn init(ui: &AppWindow) {
         start_transcribe(&ui, entry);
     });
 
+    let ui_weak = ui.as_weak();
+    global_logic!(ui).on_probe_audio_streams(move |file_path, stream| {
+        probe_audio_streams(&ui_weak.unwrap(), file_path.into(), stream);
+    });
+
+    let ui_weak = ui.as_weak();
+    global_logic!(ui).on_select_audio_stream(move |stream| {
+        select_audio_stream(&ui_weak.unwrap(), stream);
+    });
+
     let ui_weak = ui.as_weak();
     global_logic!(ui).on_update_progress(move |id, progress| {
         let ui = ui_weak.unwrap();
@@ -400,6 +421,55 @@ pub fn init(ui: &AppWindow) {
         adjust_overlap_timestamp(&ui_weak.unwrap());
     });
 
+    let ui_weak = ui.as_weak();
+    global_logic!(ui).on_show_rename_speaker_dialog(move |speaker| {
+        let ui = ui_weak.unwrap();
+        global_store!(ui).set_rename_speaker_name(speaker);
+        global_logic!(ui).invoke_switch_popup(PopupIndex::SpeakerRename);
+    });
+
+    let ui_weak = ui.as_weak();
+    global_logic!(ui).on_rename_speaker(move |old_name, new_name| {
+        rename_speaker(&ui_weak.unwrap(), old_name, new_name);
+    });
+
+    let ui_weak = ui.as_weak();
+    global_logic!(ui).on_show_retranscribe_subtitles_dialog(move |index| {
+        let ui = ui_weak.unwrap();
+        let entry = global_logic!(ui).invoke_current_transcribe_entry();
+
+        global_store!(ui).set_edit_retranscribe_setting(UIRetranscribeSetting {
+            start_index: index,
+            end_index: index,
+            model_name: entry.model_name,
+            prompt: Default::default(),
+        });
+        global_logic!(ui).invoke_switch_popup(PopupIndex::RetranscribeSubtitles);
+    });
+
+    let ui_weak = ui.as_weak();
+    global_logic!(ui).on_retranscribe_subtitles(move |setting| {
+        let ui = ui_weak.unwrap();
+
+        if get_progressing() {
+            toast_warn!(ui, tr("Already runing whisper transcription"));
+            return;
+        }
+
+        global_logic!(ui).invoke_switch_popup(PopupIndex::None);
+        retranscribe_subtitles(&ui, setting);
+    });
+
+    let ui_weak = ui.as_weak();
+    global_logic!(ui).on_next_low_confidence_subtitle(move || {
+        next_low_confidence_subtitle(&ui_weak.unwrap());
+    });
+
+    let ui_weak = ui.as_weak();
+    global_logic!(ui).on_set_low_confidence_threshold(move |threshold| {
+        set_low_confidence_threshold(&ui_weak.unwrap(), threshold);
+    });
+
     let ui_weak = ui.as_weak();
     global_logic!(ui).on_split_subtitle(move |index| {
         split_subtitle(&ui_weak.unwrap(), index as usize);
@@ -448,6 +518,9 @@ pub fn init(ui: &AppWindow) {
     global_logic!(ui)
         .on_is_valid_subtitle_timestamp(|timestamp| subtitle::valid_srt_timestamp(&timestamp));
 
+    global_logic!(ui)
+        .on_uncertain_words(|words, threshold| uncertain_words(&words, threshold).into());
+
     let ui_weak = ui.as_weak();
     global_logic!(ui).on_video_player_start(move |timestamp| {
         let ui = ui_weak.unwrap();
@@ -625,6 +698,7 @@ fn inner_init(ui: &AppWindow) {
     store_transcribe_entries!(ui).set_vec(vec![]);
     global_store!(ui).set_selected_transcribe_sidebar_index(-1);
     global_store!(ui).set_ffmpeg_is_installed(ffmpeg::is_installed());
+    global_store!(ui).set_low_confidence_threshold(config::preference().low_confidence_threshold);
 
     let ui = ui.as_weak();
     tokio::spawn(async move {
@@ -716,6 +790,8 @@ fn new_transcribe_entry(ui: &AppWindow) {
             entry.is_file_exist = true;
             entry.media_type = media_type.into();
             entry.lang = "Auto detect".into();
+            entry.audio_stream = -1;
+            entry.audio_channel = -1;
             entry.subtitle_entries = ModelRc::new(VecModel::from_slice(&vec![]));
             entry.video_player_setting.volume = 1.0;
 
@@ -753,6 +829,7 @@ fn new_transcribe_entry(ui: &AppWindow) {
             let (ui_weak, id) = (ui.as_weak(), entry.id.clone().to_string());
             let (input_media_path, output_audio_path, output_audio_path_tmp) =
                 get_convert_to_audio_paths(&entry);
+            let selection = audio_selection(&entry);
 
             tokio::spawn(async move {
                 convert_to_whisper_compatible_audio(
@@ -761,6 +838,7 @@ fn new_transcribe_entry(ui: &AppWindow) {
                     &input_media_path,
                     &output_audio_path,
                     &output_audio_path_tmp,
+                    selection,
                 );
             });
         });
@@ -1123,6 +1201,7 @@ fn update_audio_player_setting_when_switch(ui: &AppWindow, entry: &UITranscribeE
     let ui_weak = ui.as_weak();
     let id = entry.id.clone().to_string();
     let is_media_audio = entry.media_type == UIMediaType::Audio;
+    let selection = audio_selection(entry);
 
     if is_media_audio && entry.video_player_setting.end_time <= 0.0 && output_audio_path.exists() {
         let audio_path = output_audio_path.as_path().to_string_lossy().to_string();
@@ -1142,6 +1221,7 @@ fn update_audio_player_setting_when_switch(ui: &AppWindow, entry: &UITranscribeE
                 &input_media_path,
                 &output_audio_path,
                 &output_audio_path_tmp,
+                selection,
             );
 
             set_progressing(false);
@@ -1202,7 +1282,17 @@ fn start_transcribe(ui: &AppWindow, entry: UITranscribeEntry) {
         return;
     };
 
+    let options = TranscribeOptions::new(&entry);
+    let selection = options.selection;
+    let is_media_audio = entry.media_type == UIMediaType::Audio;
     let index = global_store!(ui).get_selected_transcribe_sidebar_index();
+
+    // The cached audio was converted from another stream or channel
+    let old_entry = store_transcribe_entries!(ui).row_data(index as usize);
+    if old_entry.is_some_and(|old_entry| audio_selection(&old_entry) != selection) {
+        remove_converted_audio(&id, &output_audio_path);
+    }
+
     store_transcribe_subtitle_entries!(entry).set_vec(vec![]);
     store_transcribe_entries!(ui).set_row_data(index as usize, entry.clone());
     update_db_entry(ui, entry.into());
@@ -1211,21 +1301,42 @@ fn start_transcribe(ui: &AppWindow, entry: UITranscribeEntry) {
         set_progressing(true);
         set_progress_cancel_signal(false);
 
-        if !output_audio_path.exists()
-            && !convert_to_whisper_compatible_audio(
+        // The media is decoded through an ffmpeg pipe while it is transcribed. The wav file is
+        // only converted afterwards for the player and the sound wave
+        let transcribed = transcribe(
+            ui_weak.clone(),
+            id.clone(),
+            &model_path,
+            &input_media_path,
+            lang,
+            options,
+        )
+        .await;
+
+        if transcribed
+            && !output_audio_path.exists()
+            && convert_to_whisper_compatible_audio(
                 ui_weak.clone(),
                 id.clone(),
                 &input_media_path,
                 &output_audio_path,
                 &output_audio_path_tmp,
+                selection,
             )
         {
-            set_progressing(false);
-            return;
-        }
+            let ui = ui_weak.clone();
+            _ = slint::invoke_from_event_loop(move || {
+                let ui = ui.unwrap();
+                global_logic!(ui).invoke_init_current_sound_waves(MAX_SOUND_WAVE_FORM_SIZE);
+            });
 
-        if !progress_cancelled() {
-            transcribe(ui_weak, id, &model_path, &output_audio_path, lang).await;
+            if is_media_audio {
+                async_update_audio_player_setting_when_switch(
+                    ui_weak,
+                    id,
+                    output_audio_path.as_path().to_string_lossy().to_string(),
+                );
+            }
         }
 
         set_progressing(false);
@@ -1290,6 +1401,7 @@ fn convert_to_whisper_compatible_audio(
     input_media_path: &PathBuf,
     output_audio_path: &PathBuf,
     output_audio_path_tmp: &PathBuf,
+    selection: AudioSelection,
 ) -> bool {
     debug!("Convert to whisper compatible audio file...");
 
@@ -1303,6 +1415,7 @@ fn convert_to_whisper_compatible_audio(
     match transcribe::whisper::convert_to_compatible_audio(
         &input_media_path,
         &output_audio_path_tmp,
+        selection,
         get_progress_cancel_signal(),
         move |v| {
             debug!("convert to auido progress: {v}%");
@@ -1333,6 +1446,11 @@ fn convert_to_whisper_compatible_audio(
             } else {
                 _ = fs::rename(&output_audio_path_tmp, &output_audio_path);
 
+                CACHE.lock().unwrap().waveform_peaks = None;
+                if let Err(e) = peaks::generate_peaks_file(&output_audio_path) {
+                    warn!("generate waveform peaks failed: {e}");
+                }
+
                 let (ui, id_duplicate) = (ui_weak.clone(), id.clone());
                 _ = slint::invoke_from_event_loop(move || {
                     let ui = ui.unwrap();
@@ -1350,30 +1468,261 @@ fn convert_to_whisper_compatible_audio(
     true
 }
 
+// The checkpoint and the stereo audio were made from the same stream and channel
+fn remove_converted_audio(id: &str, audio_path: &Path) {
+    AudioCache::global().evict(audio_path);
+    CACHE.lock().unwrap().waveform_peaks = None;
+
+    _ = fs::remove_file(peaks::peaks_path(audio_path));
+    _ = fs::remove_file(audio_path);
+    _ = fs::remove_file(config::cache_dir().join(format!("{id}.checkpoint.json")));
+    _ = fs::remove_file(config::cache_dir().join(format!("{id}.stereo.wav")));
+}
+
+// Lines made up on music or silence are only reported, unless removing them is checked
+fn hallucination_filter(entry: &UITranscribeEntry) -> HallucinationFilter {
+    HallucinationFilter::default().with_action(if entry.drop_hallucinations {
+        HallucinationAction::Drop
+    } else {
+        HallucinationAction::Flag
+    })
+}
+
+// -1 of `TranscribeEntry.audio_stream` and `TranscribeEntry.audio_channel` is None
+fn audio_selection(entry: &UITranscribeEntry) -> AudioSelection {
+    AudioSelection {
+        stream: (entry.audio_stream >= 0).then_some(entry.audio_stream as u32),
+        channel: (entry.audio_channel >= 0).then_some(entry.audio_channel as u16),
+    }
+}
+
+// ffprobe runs on a task when the transcribe setting opens, and once for each file. The names
+// are put into the store when it exits
+fn probe_audio_streams(ui: &AppWindow, file_path: String, stream: i32) {
+    let cached = CACHE.lock().unwrap().audio_streams.clone();
+    if let Some((path, streams)) = cached
+        && path == file_path
+    {
+        set_audio_stream_names(ui, &streams, stream);
+        return;
+    }
+
+    global_store!(ui).set_audio_stream_names(ModelRc::default());
+    global_store!(ui).set_audio_channel_names(ModelRc::default());
+
+    let ui = ui.as_weak();
+    tokio::spawn(async move {
+        let streams = ffmpeg::audio_streams(&file_path).unwrap_or_else(|e| {
+            warn!("get audio streams of {file_path} failed: {e}");
+            vec![]
+        });
+
+        _ = slint::invoke_from_event_loop(move || {
+            let ui = ui.unwrap();
+            CACHE.lock().unwrap().audio_streams = Some((file_path.clone(), streams.clone()));
+
+            // The setting of another entry may be open now
+            let entry = global_logic!(ui).invoke_current_transcribe_entry();
+            if entry.file_path == file_path {
+                set_audio_stream_names(&ui, &streams, stream);
+            }
+        });
+    });
+}
+
+fn select_audio_stream(ui: &AppWindow, stream: i32) {
+    let streams = CACHE
+        .lock()
+        .unwrap()
+        .audio_streams
+        .clone()
+        .map(|(_, streams)| streams)
+        .unwrap_or_default();
+
+    global_store!(ui).set_audio_channel_names(audio_channel_names(&streams, stream));
+}
+
+fn set_audio_stream_names(ui: &AppWindow, streams: &[AudioStreamInfo], stream: i32) {
+    global_store!(ui).set_audio_stream_names(audio_stream_names(streams));
+    global_store!(ui).set_audio_channel_names(audio_channel_names(streams, stream));
+}
+
+// ffmpeg's default stream first, then e.g. "#2 · eng · 5.1(side) · Commentary"
+fn audio_stream_names(streams: &[AudioStreamInfo]) -> ModelRc<SharedString> {
+    let mut names = vec![SharedString::from(tr("Default"))];
+
+    for (index, stream) in streams.iter().enumerate() {
+        let mut parts = vec![format!("#{}", index + 1)];
+        parts.extend(stream.language.clone());
+        parts.push(if stream.channel_layout.is_empty() {
+            format!("{}ch", stream.channels)
+        } else {
+            stream.channel_layout.clone()
+        });
+        parts.extend(stream.title.clone());
+
+        names.push(parts.join(" · ").into());
+    }
+
+    ModelRc::new(VecModel::from_slice(&names))
+}
+
+// A mix of every channel first, then the channels of the stream. -1 is the stream a selected
+// channel is taken from, see `AudioSelection::resolve`
+fn audio_channel_names(streams: &[AudioStreamInfo], stream: i32) -> ModelRc<SharedString> {
+    let selection = AudioSelection {
+        stream: (stream >= 0).then_some(stream as u32),
+        channel: None,
+    };
+
+    let channels = selection
+        .selected_stream(streams)
+        .map_or(0, |(_, info)| info.channels);
+    let mut names = vec![SharedString::from(tr("Mix all channels"))];
+    names.extend((0..channels).map(|channel| {
+        let name = match (channels, channel) {
+            (2, 0) => tr("Left"),
+            (2, 1) => tr("Right"),
+            _ => format!("{} {}", tr("Channel"), channel + 1),
+        };
+        SharedString::from(name)
+    }));
+
+    ModelRc::new(VecModel::from_slice(&names))
+}
+
+// Settings of a transcribe entry, taken before the entry is saved
+struct TranscribeOptions {
+    detect_speakers: bool,
+    glossary: Glossary,
+    chunking: ChunkingStrategy,
+    skip_non_speech: bool,
+    vad_params: VadParams,
+    preprocess: Option<AudioPreprocess>,
+    hallucination_filter: HallucinationFilter,
+    selection: AudioSelection,
+}
+
+impl TranscribeOptions {
+    fn new(entry: &UITranscribeEntry) -> Self {
+        Self {
+            detect_speakers: entry.detect_speakers,
+            glossary: Glossary::new(entry.glossary.lines()),
+            chunking: chunking_strategy(&entry.chunk_mode).with_vad_params(vad_params(entry)),
+            skip_non_speech: entry.skip_non_speech,
+            vad_params: vad_params(entry),
+            preprocess: audio_preprocess(entry),
+            hallucination_filter: hallucination_filter(entry),
+            selection: audio_selection(entry),
+        }
+    }
+
+    // Config of every transcription of the entry, a whole media or a range of subtitles
+    fn whisper_config(&self, model_path: impl Into<PathBuf>, lang: &str) -> Result<WhisperConfig> {
+        let mut config = WhisperConfig::new(model_path)
+            .with_language(lang)
+            .with_chunking(self.chunking)
+            .with_vad_params(self.vad_params)
+            .with_hallucination_filter(self.hallucination_filter.clone())
+            .with_speaker_detection(if self.detect_speakers {
+                SpeakerDetection::Auto
+            } else {
+                SpeakerDetection::None
+            });
+
+        if !self.glossary.is_empty() {
+            config = config.with_glossary(self.glossary.clone());
+        }
+
+        if let Some(preprocess) = &self.preprocess {
+            config = config.with_preprocess(preprocess.clone());
+        }
+
+        if self.skip_non_speech {
+            config = config.with_vad_model_path(whisper::bundled_silero_vad_model_path()?);
+        }
+
+        Ok(config)
+    }
+}
+
+// The silero threshold of `TranscribeEntry.vad_sensitivity`. Quiet speech has a lower speech
+// probability, and noise a higher one
+fn vad_params(entry: &UITranscribeEntry) -> VadParams {
+    let threshold = match entry.vad_sensitivity.as_str() {
+        "high" => 0.35,
+        "low" => 0.65,
+        _ => VadParams::default().threshold,
+    };
+
+    VadParams::default().with_threshold(threshold)
+}
+
+// 60-second chunks to avoid timestamp drift, split as `TranscribeEntry.chunk_mode`
+fn chunking_strategy(chunk_mode: &str) -> ChunkingStrategy {
+    let mode = match chunk_mode {
+        "fixed" => ChunkSplitMode::FixedOverlap,
+        "vad" => ChunkSplitMode::Vad,
+        _ => ChunkSplitMode::Silence,
+    };
+
+    ChunkingStrategy::new(mode)
+        .with_chunk_length_ms(60000)
+        .with_overlap_ms(1000)
+        .with_backward_search_ms(15000)
+}
+
+// The clean up stages checked in the transcribe setting, None if there is none
+fn audio_preprocess(entry: &UITranscribeEntry) -> Option<AudioPreprocess> {
+    let preprocess = AudioPreprocess {
+        high_pass: entry.high_pass.then(HighPass::default),
+        noise_reduction: entry.denoise.then(NoiseReduction::default),
+        compressor: entry.compress.then(Compressor::default),
+        normalization: match entry.normalize.as_str() {
+            "peak" => Some(Normalization::peak()),
+            "rms" => Some(Normalization::rms()),
+            _ => None,
+        },
+    };
+
+    (!preprocess.is_empty()).then_some(preprocess)
+}
+
+// Transcribe the selected audio of the media, true if it finished
 async fn transcribe(
     ui_weak: Weak<AppWindow>,
     id: String,
     model_path: &PathBuf,
-    audio_path: &PathBuf,
+    input_media_path: &PathBuf,
     lang: String,
-) {
+    options: TranscribeOptions,
+) -> bool {
     debug!("start transcribe. lang: {lang}");
 
+    let (detect_speakers, selection) = (options.detect_speakers, options.selection);
+
+    let config = match options.whisper_config(model_path, &lang) {
+        Ok(config) => config,
+        Err(e) => {
+            toast::async_toast_warn(ui_weak.clone(), e.to_string());
+            return false;
+        }
+    };
+
     let (ui, id_duplicate) = (ui_weak.clone(), id.clone());
     _ = slint::invoke_from_event_loop(move || {
         let ui = ui.unwrap();
         update_progress(&ui, id_duplicate, Some(ProgressType::Transcribe), 0.0);
     });
 
-    let config = transcribe::whisper::WhisperConfig::new(model_path)
-        .with_language(lang)
-        .with_chunk_length_ms(60000) // 60-second chunks to avoid timestamp drift
-        .with_chunk_overlap_ms(1000); // 1-second overlap between chunks
+    let checkpoint_path = config::cache_dir().join(format!("{id}.checkpoint.json"));
+    let config = config.with_checkpoint_path(&checkpoint_path);
 
     let (ui_progress, ui_segement) = (ui_weak.clone(), ui_weak.clone());
-    match transcribe::whisper::transcribe_file(
+    match transcribe::whisper::transcribe_media(
         config,
-        &audio_path,
+        &input_media_path,
+        selection,
         move |v: i32| {
             debug!("whisper transcribe progress: {v}");
 
@@ -1408,7 +1757,93 @@ async fn transcribe(
     )
     .await
     {
-        Ok(_) => {
+        Ok(mut result) => {
+            if lang == WhisperLang::Auto.to_string()
+                && let Some(detected) = result.language.clone()
+            {
+                let name = WhisperLang::all_languages()
+                    .into_iter()
+                    .find(|(_, code, _)| *code == detected)
+                    .map(|(_, _, name)| name.to_string())
+                    .unwrap_or(detected);
+
+                toast::async_toast_info(
+                    ui_weak.clone(),
+                    format!("{}: {name}", tr("Detected language")),
+                );
+            }
+
+            match &result.checkpoint {
+                Some(CheckpointStatus::Resumed { chunks }) => toast::async_toast_info(
+                    ui_weak.clone(),
+                    format!("{}: {chunks}", tr("Resumed chunks from checkpoint")),
+                ),
+                Some(CheckpointStatus::Discarded { reason }) => toast::async_toast_info(
+                    ui_weak.clone(),
+                    format!("{}: {reason}", tr("Discarded checkpoint")),
+                ),
+                _ => (),
+            }
+
+            for item in result.chunks.iter().filter(|item| item.split.is_some()) {
+                debug!("split chunk at {}ms: {:?}", item.end_ms, item.split);
+            }
+
+            if !result.hallucinations.is_empty() {
+                for item in result.hallucinations.iter() {
+                    info!(
+                        "{} hallucination {:?}: {}",
+                        if item.dropped { "remove" } else { "flag" },
+                        item.reason,
+                        item.segment.text
+                    );
+                }
+
+                let text = if result.hallucinations.iter().any(|item| item.dropped) {
+                    tr("Removed hallucinated subtitles")
+                } else {
+                    tr("Possibly hallucinated subtitles")
+                };
+
+                toast::async_toast_info(
+                    ui_weak.clone(),
+                    format!("{text}: {}", result.hallucinations.len()),
+                );
+            }
+
+            if !result.glossary_replacements.is_empty() {
+                for item in result.glossary_replacements.iter() {
+                    info!(
+                        "glossary replacement at {}ms: {} -> {}",
+                        item.start_time, item.original, item.replacement
+                    );
+                }
+
+                toast::async_toast_info(
+                    ui_weak.clone(),
+                    format!(
+                        "{}: {}",
+                        tr("Fixed glossary terms"),
+                        result.glossary_replacements.len()
+                    ),
+                );
+            }
+
+            if detect_speakers
+                && result
+                    .segments
+                    .iter()
+                    .all(|segment| segment.speaker.is_none())
+                && let Err(e) =
+                    detect_stereo_speakers(&id, input_media_path, selection, &mut result)
+            {
+                warn!("detect stereo speakers failed: {e}");
+                toast::async_toast_warn(
+                    ui_weak.clone(),
+                    format!("{}: {e}", tr("Detect speakers failed")),
+                );
+            }
+
             let (ui, id_duplicate) = (ui_weak.clone(), id.clone());
             _ = slint::invoke_from_event_loop(move || {
                 let ui = ui.unwrap();
@@ -1420,10 +1855,21 @@ async fn transcribe(
                 );
 
                 let entry = global_logic!(ui).invoke_current_transcribe_entry();
+
+                // The streamed segments carry no speakers and confidences and still have the
+                // hallucinations, so replace them with the final result
+                let subtitles = subtitle::transcription_to_subtitle(&result)
+                    .into_iter()
+                    .map(|sub| sub.into())
+                    .collect::<Vec<UISubtitleEntry>>();
+                store_transcribe_subtitle_entries!(entry).set_vec(subtitles);
+
                 update_db_entry(&ui, entry.into());
 
                 global_logic!(ui).invoke_init_current_sound_waves(MAX_SOUND_WAVE_FORM_SIZE);
             });
+
+            true
         }
         Err(e) => {
             if !progress_cancelled() {
@@ -1431,10 +1877,227 @@ async fn transcribe(
             } else {
                 toast::async_toast_info(ui_weak.clone(), tr("Cancelled transcribing"));
             }
+
+            false
         }
     }
 }
 
+// Transcribe the time range of the subtitles again and replace them with the new subtitles
+fn retranscribe_subtitles(ui: &AppWindow, setting: UIRetranscribeSetting) {
+    let entry = global_logic!(ui).invoke_current_transcribe_entry();
+    let id = entry.id.to_string();
+    let subtitles_len = store_transcribe_subtitle_entries!(entry).row_count();
+
+    let (start_index, end_index) = (
+        setting.start_index.min(setting.end_index),
+        setting.start_index.max(setting.end_index),
+    );
+    if start_index < 0 || end_index as usize >= subtitles_len {
+        toast_warn!(
+            ui,
+            format!(
+                "{}: {} -> {}",
+                tr("Invalid subtitle range"),
+                start_index + 1,
+                end_index + 1
+            )
+        );
+        return;
+    }
+    let (start_index, end_index) = (start_index as usize, end_index as usize);
+
+    let mut old_subtitles = vec![];
+    for index in start_index..=end_index {
+        let item = store_transcribe_subtitle_entries!(entry)
+            .row_data(index)
+            .unwrap();
+
+        match to_subtitle(index as i32 + 1, &item) {
+            Ok(item) => old_subtitles.push(item),
+            Err(e) => {
+                toast_warn!(ui, format!("{e}"));
+                return;
+            }
+        }
+    }
+
+    let start_ms = old_subtitles[0].start_timestamp;
+    let end_ms = old_subtitles[old_subtitles.len() - 1].end_timestamp;
+
+    let Some(model_path) = super::model::get_model_path(ui, &setting.model_name) else {
+        toast_warn!(ui, tr("Can't find modle"));
+        return;
+    };
+
+    let Some(lang) = WhisperLang::get_code_from_long_name(&entry.lang) else {
+        toast_warn!(
+            ui,
+            format!("{}: {}", tr("Unsupport whisper language"), entry.lang)
+        );
+        return;
+    };
+
+    let audio_path = config::cache_dir().join(format!("{id}.wav"));
+    if !audio_path.exists() {
+        toast_warn!(
+            ui,
+            format!("{}: {}", tr("Can't find file"), audio_path.display())
+        );
+        return;
+    }
+
+    // Split and cleaned up like the whole media, so the new subtitles match the old ones
+    let mut config = match TranscribeOptions::new(&entry).whisper_config(model_path, &lang) {
+        Ok(config) => config,
+        Err(e) => {
+            toast_warn!(ui, e.to_string());
+            return;
+        }
+    };
+    if !setting.prompt.trim().is_empty() {
+        config = config.with_initial_prompt(setting.prompt.trim());
+    }
+
+    update_progress(ui, id.clone(), Some(ProgressType::Transcribe), 0.0);
+
+    let ui_weak = ui.as_weak();
+    tokio::spawn(async move {
+        set_progressing(true);
+        set_progress_cancel_signal(false);
+
+        let (ui_progress, id_progress) = (ui_weak.clone(), id.clone());
+        match transcribe::whisper::transcribe_file_range(
+            config,
+            &audio_path,
+            start_ms,
+            end_ms,
+            move |v: i32| {
+                let (ui, id) = (ui_progress.clone(), id_progress.clone());
+                _ = slint::invoke_from_event_loop(move || {
+                    update_progress(&ui.unwrap(), id, None, v as f32 / 100.0);
+                });
+            },
+            |_| {},
+            || progress_cancelled(),
+        )
+        .await
+        {
+            Ok(result) => {
+                let mut subtitles = subtitle::transcription_to_subtitle(&result);
+                for item in subtitles.iter_mut() {
+                    if item.speaker.is_none() {
+                        item.speaker = overlapped_speaker(&old_subtitles, item);
+                    }
+                }
+
+                _ = slint::invoke_from_event_loop(move || {
+                    let ui = ui_weak.unwrap();
+                    let entry = global_logic!(ui).invoke_current_transcribe_entry();
+                    if entry.id.as_str() != id {
+                        return;
+                    }
+
+                    let mut ui_subtitles = store_transcribe_subtitle_entries!(entry)
+                        .iter()
+                        .collect::<Vec<UISubtitleEntry>>();
+
+                    if end_index >= ui_subtitles.len() {
+                        toast_warn!(ui, tr("Subtitles changed while re-transcribing"));
+                        return;
+                    }
+
+                    let count = subtitles.len();
+                    ui_subtitles.splice(
+                        start_index..=end_index,
+                        subtitles.into_iter().map(UISubtitleEntry::from),
+                    );
+                    store_transcribe_subtitle_entries!(entry).set_vec(ui_subtitles);
+
+                    update_progress(&ui, id, Some(ProgressType::TranscribeFinished), 1.0);
+                    update_db_entry(&ui, entry.into());
+
+                    toast_success!(ui, format!("{}: {count}", tr("Re-transcribed subtitles")));
+                    global_logic!(ui).invoke_init_current_sound_waves(MAX_SOUND_WAVE_FORM_SIZE);
+                });
+            }
+            Err(e) => {
+                if !progress_cancelled() {
+                    toast::async_toast_warn(ui_weak.clone(), e.to_string());
+                } else {
+                    toast::async_toast_info(ui_weak.clone(), tr("Cancelled transcribing"));
+                }
+
+                _ = slint::invoke_from_event_loop(move || {
+                    let ui = ui_weak.unwrap();
+                    let entry = global_logic!(ui).invoke_current_transcribe_entry();
+                    update_progress(
+                        &ui,
+                        id,
+                        Some(ProgressType::PartiallyFinished),
+                        entry.progress,
+                    );
+                });
+            }
+        }
+
+        set_progressing(false);
+    });
+}
+
+// Speaker of the old subtitle that overlaps `sub` the most
+fn overlapped_speaker(old_subtitles: &[Subtitle], sub: &Subtitle) -> Option<String> {
+    old_subtitles
+        .iter()
+        .filter(|old| old.speaker.is_some())
+        .map(|old| {
+            let overlap = old.end_timestamp.min(sub.end_timestamp) as i64
+                - old.start_timestamp.max(sub.start_timestamp) as i64;
+            (overlap, old)
+        })
+        .filter(|(overlap, _)| *overlap > 0)
+        .max_by_key(|(overlap, _)| *overlap)
+        .and_then(|(_, old)| old.speaker.clone())
+}
+
+// Fallback for non-tinydiarize models: label segments by the louder channel of the original media
+fn detect_stereo_speakers(
+    id: &str,
+    input_media_path: &PathBuf,
+    selection: AudioSelection,
+    result: &mut transcribe::whisper::TranscriptionResult,
+) -> Result<()> {
+    let stereo_audio_path = config::cache_dir().join(format!("{id}.stereo.wav"));
+
+    // Both channels of the transcribed stream. A selected channel was taken from the resolved
+    // stream, not from the stream ffmpeg picks
+    let stream = match selection.channel {
+        Some(_) => {
+            selection
+                .resolve(&ffmpeg::audio_streams(input_media_path)?)?
+                .stream
+        }
+        None => selection.stream,
+    };
+
+    ffmpeg::convert_audio(
+        input_media_path,
+        &stereo_audio_path,
+        false,
+        AudioSelection {
+            stream,
+            channel: None,
+        },
+        get_progress_cancel_signal(),
+        |v| trace!("convert to stereo audio progress: {v}%"),
+    )?;
+
+    let audio_data = transcribe::wav::read_file(&stereo_audio_path);
+    _ = fs::remove_file(&stereo_audio_path);
+
+    transcribe::speaker::assign_stereo_speakers(&audio_data?, &mut result.segments)
+}
+
 fn cancel_progress(ui: &AppWindow, id: SharedString, ty: ProgressType) {
     set_progress_cancel_signal(true);
 
@@ -1756,6 +2419,8 @@ fn handle_partial_subtitle(ui: &AppWindow, setting: UIAiHandleSubtitleSetting) {
             .collect::<Vec<(usize, String)>>(),
     );
 
+    let glossary = Arc::new(Glossary::new(entry.glossary.lines()).terms);
+
     let original_subtitles_len = original_subtitles.len();
     if original_subtitles_len == 0 {
         toast_info!(ui, "Already handled all subtitles");
@@ -1776,7 +2441,7 @@ fn handle_partial_subtitle(ui: &AppWindow, setting: UIAiHandleSubtitleSetting) {
             cutil::vec::chunk_with_merge(&original_subtitles, setting.chunk_size.max(1) as usize);
 
         for chunk in original_subtitle_chunks.into_iter() {
-            let original_subtitles = original_subtitles.clone();
+            let (original_subtitles, glossary) = (original_subtitles.clone(), glossary.clone());
             let (ui, tx) = (ui_weak.clone(), tx.clone());
             let setting = setting.clone();
             let chunk_size = chunk.len();
@@ -1790,7 +2455,7 @@ fn handle_partial_subtitle(ui: &AppWindow, setting: UIAiHandleSubtitleSetting) {
                     .map(|item| item.1)
                     .collect::<Vec<String>>();
 
-                let resp = ask_ai(&subtitle_chunk, &setting.prompt).await;
+                let resp = ask_ai(&subtitle_chunk, &setting.prompt, &glossary).await;
 
                 match resp {
                     Err(e) => {
@@ -1910,7 +2575,7 @@ fn handle_partial_subtitle(ui: &AppWindow, setting: UIAiHandleSubtitleSetting) {
     });
 }
 
-async fn ask_ai(subtitles: &[String], prompt: &str) -> Result<Vec<String>> {
+async fn ask_ai(subtitles: &[String], prompt: &str, glossary: &[String]) -> Result<Vec<String>> {
     let model_setting = config::model();
     if model_setting.api_key.is_empty()
         || model_setting.model_name.is_empty()
@@ -1919,6 +2584,7 @@ async fn ask_ai(subtitles: &[String], prompt: &str) -> Result<Vec<String>> {
         return Err(anyhow!(tr("Please configure model setting firstly")));
     }
 
+    let prompt = format!("{prompt}{}", glossary_prompt(glossary));
     debug!("prompt:\n{prompt}");
 
     let config = async_openai::config::OpenAIConfig::new()
@@ -1965,6 +2631,25 @@ async fn ask_ai(subtitles: &[String], prompt: &str) -> Result<Vec<String>> {
     }
 }
 
+// Glossary terms appended to the system prompt, so the terms are spelled the same way
+fn glossary_prompt(glossary: &[String]) -> String {
+    if glossary.is_empty() {
+        return String::default();
+    }
+
+    format!(
+        r#"
+
+<Glossary>
+{}
+</Glossary>
+
+Spell the terms of the glossary exactly as they are listed. Fix the words that sound like a term but are spelled differently. Don't translate the terms.
+"#,
+        glossary.join("\n")
+    )
+}
+
 fn accept_all_corrected_subtitles(ui: &AppWindow) {
     let entry = global_logic!(ui).invoke_current_transcribe_entry();
 
@@ -2107,9 +2792,12 @@ fn remove_all_subtitles(ui: &AppWindow) {
     update_db_entry(&ui, entry.into());
 }
 
+// Trim the silence of the subtitles to the speech found by silero, as sensitive as the
+// transcription
 fn optimize_subtitles_timestamp(ui: &AppWindow) {
     let entry = global_logic!(ui).invoke_current_transcribe_entry();
     let id = entry.id.clone().to_string();
+    let vad_params = vad_params(&entry);
 
     let timestamps = get_current_timestamps(ui);
     if timestamps.is_empty() {
@@ -2130,10 +2818,10 @@ fn optimize_subtitles_timestamp(ui: &AppWindow) {
 
     tokio::spawn(async move {
         let (ui_weak_duplicate, id_duplicate) = (ui_weak.clone(), id.clone());
-        match transcribe::vad::trim_slient_duration_of_audio(
+        match transcribe::vad::trim_silent_duration_with_silero(
             &audio_path,
             &timestamps,
-            0.5,
+            &vad_params,
             get_progress_cancel_signal(),
             move |v| {
                 let (ui_weak, id_duplicate) = (ui_weak_duplicate.clone(), id_duplicate.clone());
@@ -2280,6 +2968,7 @@ fn adjust_overlap_timestamp(ui: &AppWindow) {
             && et > next_st
         {
             next_item.start_timestamp = transcribe::subtitle::ms_to_srt_timestamp(et).into();
+            next_item.words = clamp_words_start(&next_item.words, et);
             store_transcribe_subtitle_entries!(entry).set_row_data(index + 1, next_item);
         }
     }
@@ -2287,6 +2976,25 @@ fn adjust_overlap_timestamp(ui: &AppWindow) {
     update_db_entry(&ui, entry.into());
 }
 
+fn rename_speaker(ui: &AppWindow, old_name: SharedString, new_name: SharedString) {
+    let entry = global_logic!(ui).invoke_current_transcribe_entry();
+
+    let subtitles = store_transcribe_subtitle_entries!(entry)
+        .iter()
+        .map(|mut entry| {
+            if entry.speaker == old_name {
+                entry.speaker = new_name.clone();
+            }
+            entry
+        })
+        .collect::<Vec<UISubtitleEntry>>();
+
+    store_transcribe_subtitle_entries!(entry).set_vec(subtitles);
+    toast_success!(ui, tr("rename speaker successfully"));
+
+    update_db_entry(&ui, entry.into());
+}
+
 fn split_subtitle(ui: &AppWindow, index: usize) {
     let entry = global_logic!(ui).invoke_current_transcribe_entry();
     let subtitles_len = store_transcribe_subtitle_entries!(entry).row_count();
@@ -2318,10 +3026,21 @@ fn split_subtitle(ui: &AppWindow, index: usize) {
         return;
     };
 
+    // Words are split at the end of the first part
+    let (first_words, second_words): (Vec<_>, Vec<_>) = subtitle
+        .words
+        .iter()
+        .partition(|word| (word.start_ms as u64) < first_part.1);
+
     let current_subtitle = UISubtitleEntry {
         start_timestamp: transcribe::subtitle::ms_to_srt_timestamp(first_part.0).into(),
         end_timestamp: transcribe::subtitle::ms_to_srt_timestamp(first_part.1).into(),
         original_text: first_part.2.into(),
+        speaker: subtitle.speaker.clone(),
+        confidence: subtitle.confidence,
+        hallucination: subtitle.hallucination.clone(),
+        glossary: subtitle.glossary.clone(),
+        words: ModelRc::new(VecModel::from(first_words)),
         ..Default::default()
     };
 
@@ -2329,6 +3048,11 @@ fn split_subtitle(ui: &AppWindow, index: usize) {
         start_timestamp: transcribe::subtitle::ms_to_srt_timestamp(second_part.0).into(),
         end_timestamp: transcribe::subtitle::ms_to_srt_timestamp(second_part.1).into(),
         original_text: second_part.2.into(),
+        speaker: subtitle.speaker.clone(),
+        confidence: subtitle.confidence,
+        hallucination: subtitle.hallucination.clone(),
+        glossary: subtitle.glossary.clone(),
+        words: ModelRc::new(VecModel::from(second_words)),
         ..Default::default()
     };
 
@@ -2385,6 +3109,28 @@ fn merge_above_subtitle(ui: &AppWindow, index: usize) {
         .translation_text
         .push_str(&current_subtitle.translation_text);
 
+    // The merged subtitle is as uncertain as its worst part
+    prev_subtitle.confidence = match (prev_subtitle.confidence, current_subtitle.confidence) {
+        (a, b) if a > 0.0 && b > 0.0 => a.min(b),
+        (a, b) => a.max(b),
+    };
+    if prev_subtitle.hallucination.is_empty() {
+        prev_subtitle.hallucination = current_subtitle.hallucination.clone();
+    }
+    if prev_subtitle.glossary.is_empty() {
+        prev_subtitle.glossary = current_subtitle.glossary.clone();
+    } else if !current_subtitle.glossary.is_empty() {
+        prev_subtitle.glossary =
+            format!("{}, {}", prev_subtitle.glossary, current_subtitle.glossary).into();
+    }
+    prev_subtitle.words = ModelRc::new(VecModel::from(
+        prev_subtitle
+            .words
+            .iter()
+            .chain(current_subtitle.words.iter())
+            .collect::<Vec<UISubtitleWord>>(),
+    ));
+
     store_transcribe_subtitle_entries!(entry).set_row_data(index - 1, prev_subtitle);
     store_transcribe_subtitle_entries!(entry).remove(index);
     global_logic!(ui).invoke_update_current_sound_wave(index as i32 - 1, MAX_SOUND_WAVE_FORM_SIZE);
@@ -2423,6 +3169,8 @@ fn shift_subtitles_timestamp(ui: &AppWindow, index: usize, shift_ms: SharedStrin
                     };
                     item.end_timestamp = ms_to_srt_timestamp(new_st).into();
                 }
+
+                item.words = shift_words(&item.words, shift_ms);
                 item
             }
         })
@@ -2433,6 +3181,50 @@ fn shift_subtitles_timestamp(ui: &AppWindow, index: usize, shift_ms: SharedStrin
     update_db_entry(&ui, entry.into());
 }
 
+// Word times follow the timestamps of their subtitle
+fn shift_words(words: &ModelRc<UISubtitleWord>, shift_ms: i32) -> ModelRc<UISubtitleWord> {
+    let words = words
+        .iter()
+        .map(|mut word| {
+            word.start_ms = (word.start_ms + shift_ms).max(0);
+            word.end_ms = (word.end_ms + shift_ms).max(0);
+            word
+        })
+        .collect::<Vec<_>>();
+
+    ModelRc::new(VecModel::from(words))
+}
+
+// Words before the new start of their subtitle start with it
+fn clamp_words_start(words: &ModelRc<UISubtitleWord>, start_ms: u64) -> ModelRc<UISubtitleWord> {
+    let words = words
+        .iter()
+        .map(|mut word| {
+            word.start_ms = word.start_ms.max(start_ms as i32);
+            word.end_ms = word.end_ms.max(word.start_ms);
+            word
+        })
+        .collect::<Vec<_>>();
+
+    ModelRc::new(VecModel::from(words))
+}
+
+// Words decoded below the low confidence threshold with their probability, e.g. "Tokio 32%"
+fn uncertain_words(words: &ModelRc<UISubtitleWord>, threshold: f32) -> String {
+    words
+        .iter()
+        .filter(|word| word.probability < threshold && !word.text.trim().is_empty())
+        .map(|word| {
+            format!(
+                "{} {}%",
+                word.text.trim(),
+                (word.probability * 100.0).round() as i32
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
 fn insert_above_subtitle(ui: &AppWindow, index: usize) {
     let entry = global_logic!(ui).invoke_current_transcribe_entry();
     let subtitles_len = store_transcribe_subtitle_entries!(entry).row_count();
@@ -2527,7 +3319,7 @@ fn remove_subtitle(ui: &AppWindow, index: usize) {
     update_db_entry(&ui, entry.into());
 }
 
-fn save_subtitle(ui: &AppWindow, index: usize, subtitle: UISubtitleEntry) {
+fn save_subtitle(ui: &AppWindow, index: usize, mut subtitle: UISubtitleEntry) {
     let entry = global_logic!(ui).invoke_current_transcribe_entry();
     let subtitles_len = store_transcribe_subtitle_entries!(entry).row_count();
 
@@ -2535,12 +3327,67 @@ fn save_subtitle(ui: &AppWindow, index: usize, subtitle: UISubtitleEntry) {
         return;
     }
 
+    // A subtitle edited by hand has been reviewed, so it is no longer low confidence or a
+    // possible hallucination
+    let old_subtitle = store_transcribe_subtitle_entries!(entry)
+        .row_data(index)
+        .unwrap();
+    if old_subtitle.original_text != subtitle.original_text {
+        subtitle.confidence = 0.0;
+        subtitle.hallucination = SharedString::default();
+        subtitle.glossary = SharedString::default();
+        subtitle.words = ModelRc::default();
+    }
+
     store_transcribe_subtitle_entries!(entry).set_row_data(index, subtitle);
     toast_success!(ui, tr("save subtitle successfully"));
 
     update_db_entry(&ui, entry.into());
 }
 
+// Select the next subtitle below the threshold or flagged as a possible hallucination after the
+// selected one, wrapping around at the end
+fn next_low_confidence_subtitle(ui: &AppWindow) {
+    let entry = global_logic!(ui).invoke_current_transcribe_entry();
+    let threshold = global_store!(ui).get_low_confidence_threshold();
+    let subtitles = store_transcribe_subtitle_entries!(entry)
+        .iter()
+        .collect::<Vec<UISubtitleEntry>>();
+
+    let start = (global_store!(ui).get_low_confidence_index() + 1).max(0) as usize;
+    let next = (0..subtitles.len())
+        .map(|i| (start + i) % subtitles.len())
+        .find(|i| {
+            let subtitle = &subtitles[*i];
+            (subtitle.confidence > 0.0 && subtitle.confidence < threshold)
+                || !subtitle.hallucination.is_empty()
+        });
+
+    let Some(index) = next else {
+        toast_info!(ui, tr("No low confidence or hallucinated subtitles"));
+        return;
+    };
+
+    global_store!(ui).set_low_confidence_index(index as i32);
+    global_store!(ui).set_low_confidence_flag(!global_store!(ui).get_low_confidence_flag());
+}
+
+fn set_low_confidence_threshold(ui: &AppWindow, threshold: SharedString) {
+    let Ok(threshold) = threshold.trim().trim_end_matches('%').parse::<f32>() else {
+        toast_warn!(ui, format!("{threshold} is not a number"));
+        return;
+    };
+
+    let threshold = (threshold / 100.0).clamp(0.01, 1.0);
+    global_store!(ui).set_low_confidence_threshold(threshold);
+
+    let mut all = config::all();
+    all.preference.low_confidence_threshold = threshold;
+    _ = config::save(all);
+
+    toast_success!(ui, tr("save configuration successfully"));
+}
+
 fn reject_subtitle_correction(ui: &AppWindow, index: usize) {
     let entry = global_logic!(ui).invoke_current_transcribe_entry();
     let subtitles_len = store_transcribe_subtitle_entries!(entry).row_count();
@@ -3068,6 +3915,11 @@ fn to_subtitle(index: i32, entry: &UISubtitleEntry) -> Result<Subtitle> {
         } else {
             format!("{}\n{}", entry.original_text, entry.translation_text)
         },
+        speaker: (!entry.speaker.is_empty()).then(|| entry.speaker.to_string()),
+        confidence: (entry.confidence > 0.0).then_some(entry.confidence),
+        hallucination: (!entry.hallucination.is_empty()).then(|| entry.hallucination.to_string()),
+        glossary: (!entry.glossary.is_empty()).then(|| entry.glossary.to_string()),
+        words: entry.words.iter().map(|item| item.into()).collect(),
     })
 }
 
@@ -3116,6 +3968,55 @@ fn get_current_timestamps(ui: &AppWindow) -> Vec<(u64, u64)> {
     timestamps
 }
 
+// Waveform peaks of the entry, loaded once. They are generated for audio converted before
+// peak files existed
+fn waveform_peaks(id: &str) -> Result<Arc<WaveformPeaks>> {
+    if let Some((cached_id, peaks)) = &CACHE.lock().unwrap().waveform_peaks
+        && cached_id == id
+    {
+        return Ok(peaks.clone());
+    }
+
+    let audio_path = config::cache_dir().join(format!("{id}.wav"));
+    let peaks_path = peaks::peaks_path(&audio_path);
+    let peaks = match WaveformPeaks::load(&peaks_path) {
+        Ok(peaks) => peaks,
+        Err(e) => {
+            debug!("load {} failed: {e}", peaks_path.display());
+            WaveformPeaks::load(peaks::generate_peaks_file(&audio_path)?)?
+        }
+    };
+
+    let peaks = Arc::new(peaks);
+    CACHE.lock().unwrap().waveform_peaks = Some((id.to_string(), peaks.clone()));
+    Ok(peaks)
+}
+
+// At most `max_samples` peak magnitudes of every timestamp range
+fn get_sound_waves(
+    id: &str,
+    timestamps: &[(u64, u64)],
+    max_samples: u64,
+) -> Result<Vec<Vec<f32>>> {
+    match waveform_peaks(id) {
+        Ok(peaks) => Ok(timestamps
+            .iter()
+            .map(|(start, end)| {
+                peaks
+                    .range(*start, *end, max_samples as usize)
+                    .iter()
+                    .map(|peak| peak.magnitude())
+                    .collect()
+            })
+            .collect()),
+        Err(e) => {
+            warn!("read waveform peaks failed: {e}");
+            let audio_path = config::cache_dir().join(format!("{id}.wav"));
+            transcribe::vad::get_audio_samples(audio_path, timestamps, max_samples)
+        }
+    }
+}
+
 fn init_current_audio_smaples(ui: &AppWindow, max_samples: u64) {
     let timestamps = get_current_timestamps(ui);
     if timestamps.is_empty() {
@@ -3125,10 +4026,9 @@ fn init_current_audio_smaples(ui: &AppWindow, max_samples: u64) {
     let ui_weak = ui.as_weak();
     let entry = global_logic!(ui).invoke_current_transcribe_entry();
     let id = entry.id.clone().to_string();
-    let audio_path = config::cache_dir().join(format!("{id}.wav"));
 
     tokio::spawn(async move {
-        match transcribe::vad::get_audio_samples(audio_path, &timestamps, max_samples) {
+        match get_sound_waves(&id, &timestamps, max_samples) {
             Ok(samples) => {
                 _ = ui_weak.upgrade_in_event_loop(move |ui| {
                     let entry = global_logic!(ui).invoke_current_transcribe_entry();
@@ -3186,13 +4086,12 @@ fn update_current_sound_wave(ui: &AppWindow, index: i32, max_samples: i32) {
     }
 
     let ui_weak = ui.as_weak();
-    let audio_path = config::cache_dir().join(format!("{}.wav", entry.id));
+    let id = entry.id.to_string();
 
     tokio::spawn(async move {
         let timestamps = [(start_timestamp.unwrap(), end_timestamp.unwrap())];
 
-        match transcribe::vad::get_audio_samples(audio_path, &timestamps, max_samples.max(1) as u64)
-        {
+        match get_sound_waves(&id, &timestamps, max_samples.max(1) as u64) {
             Ok(samples) => {
                 if samples.is_empty() {
                     return;
@@ -3375,6 +4274,8 @@ struct Cache {
     progress_cancel_signal: Arc<AtomicBool>,
     audio_player_handle: Option<SoundHandle>,
     video_player_cancel_signal: Arc<AtomicBool>,
+    waveform_peaks: Option<(String, Arc<WaveformPeaks>)>, // of the entry id
+    audio_streams: Option<(String, Vec<AudioStreamInfo>)>, // of the media file path
 }
 
 impl Default for Cache {
@@ -3385,6 +4286,8 @@ impl Default for Cache {
             audio_player_handle: None,
             progress_cancel_signal: Arc::new(AtomicBool::new(false)),
             video_player_cancel_signal: Arc::new(AtomicBool::new(false)),
+            waveform_peaks: None,
+            audio_streams: None,
         }
     }
 }
@@ -3395,6 +4298,16 @@ impl From<Subtitle> for UISubtitleEntry {
             start_timestamp: transcribe::subtitle::ms_to_srt_timestamp(sub.start_timestamp).into(),
             end_timestamp: transcribe::subtitle::ms_to_srt_timestamp(sub.end_timestamp).into(),
             original_text: sub.text.into(),
+            speaker: sub.speaker.unwrap_or_default().into(),
+            confidence: sub.confidence.unwrap_or_default(),
+            hallucination: sub.hallucination.unwrap_or_default().into(),
+            glossary: sub.glossary.unwrap_or_default().into(),
+            words: ModelRc::new(VecModel::from(
+                sub.words
+                    .into_iter()
+                    .map(|item| item.into())
+                    .collect::<Vec<UISubtitleWord>>(),
+            )),
             ..Default::default()
         }
     }